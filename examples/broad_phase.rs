@@ -0,0 +1,20 @@
+//! Finds which of a set of boxes intersect a query box, using a `LooseQuadTree`
+//! directly without a `GameState`.
+
+extern crate cgmath;
+extern crate platform_game_experiment;
+
+use cgmath::vec2;
+use platform_game_experiment::{Aabb, LooseQuadTree};
+
+fn main() {
+    let mut tree = LooseQuadTree::new(vec2(1000., 1000.));
+    for i in 0..10 {
+        let top_left = vec2(i as f32 * 100., i as f32 * 50.);
+        tree.insert(Aabb::new(top_left, vec2(80., 80.)), i);
+    }
+    let query = Aabb::new(vec2(150., 50.), vec2(200., 100.));
+    tree.for_each_intersection(&query, |aabb, i| {
+        println!("box {} at {:?} intersects the query", i, aabb.top_left());
+    });
+}
@@ -0,0 +1,34 @@
+//! Builds a small level in code and walks the player into a wall, printing where
+//! it ends up each step. Needs no graphics.
+
+extern crate cgmath;
+extern crate platform_game_experiment;
+
+use cgmath::vec2;
+use platform_game_experiment::shape::AxisAlignedRect;
+use platform_game_experiment::{EntityCommon, GameState, InputModel, Shape};
+
+fn rect(x: f32, y: f32, width: f32, height: f32) -> EntityCommon {
+    EntityCommon::new(
+        vec2(x, y),
+        Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(width, height))),
+        [1., 1., 1.],
+    )
+}
+
+fn main() {
+    let mut game_state = GameState::new(vec2(200., 100.), Default::default());
+    game_state.add_static_solid(rect(100., 0., 20., 100.));
+    let player = game_state.add_player(rect(0., 40., 16., 16.));
+    let mut input_model = InputModel::default();
+    input_model.set_right(1.);
+    for step in 0..30 {
+        game_state.update(&input_model);
+        let top_left = game_state.common(player).unwrap().top_left;
+        println!(
+            "step {:2}: player at ({:.2}, {:.2})",
+            step, top_left.x, top_left.y
+        );
+    }
+    println!("{:?}", game_state.collision_stats());
+}
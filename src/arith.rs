@@ -1,5 +1,61 @@
+use cgmath::{BaseNum, Vector2};
 use num::{Num, NumCast, One, Signed, ToPrimitive, Zero};
 
+/// The numeric type of coordinates throughout the geometry code. Floating point
+/// types and the fixed-point wrappers below are supported.
+pub trait Scalar: BaseNum + Signed {
+    /// Values closer together than this are treated as equal when deciding how
+    /// shapes touch. Zero for types with exact arithmetic.
+    fn tolerance() -> Self;
+    /// Largest integer not greater than `self / rhs`.
+    fn div_floor(self, rhs: Self) -> Self;
+    fn two() -> Self {
+        Self::one() + Self::one()
+    }
+}
+
+impl Scalar for f32 {
+    fn tolerance() -> Self {
+        0.001
+    }
+    fn div_floor(self, rhs: Self) -> Self {
+        (self / rhs).floor()
+    }
+}
+
+impl Scalar for f64 {
+    fn tolerance() -> Self {
+        0.001
+    }
+    fn div_floor(self, rhs: Self) -> Self {
+        (self / rhs).floor()
+    }
+}
+
+pub fn min<N: PartialOrd>(a: N, b: N) -> N {
+    if b < a {
+        b
+    } else {
+        a
+    }
+}
+
+pub fn max<N: PartialOrd>(a: N, b: N) -> N {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+pub fn dot<N: BaseNum>(v: Vector2<N>, w: Vector2<N>) -> N {
+    v.x * w.x + v.y * w.y
+}
+
+pub fn cross<N: BaseNum>(v: Vector2<N>, w: Vector2<N>) -> N {
+    v.x * w.y - v.y * w.x
+}
+
 macro_rules! make_i64_wrapper {
     ($name:ident) => {
         custom_derive! {
@@ -57,6 +113,20 @@ macro_rules! make_i64_wrapper {
             }
         }
 
+        impl Scalar for $name {
+            fn tolerance() -> Self {
+                $name(0)
+            }
+            fn div_floor(self, rhs: Self) -> Self {
+                let quotient = self.0 / rhs.0;
+                if (self.0 % rhs.0 != 0) && ((self.0 < 0) != (rhs.0 < 0)) {
+                    $name(quotient - 1)
+                } else {
+                    $name(quotient)
+                }
+            }
+        }
+
         impl Signed for $name {
             fn abs(&self) -> Self {
                 $name(self.0.abs())
@@ -0,0 +1,306 @@
+use aabb::Aabb;
+use cgmath::{vec2, InnerSpace, Vector2};
+use game::{EntityId, GameState, System, Velocity};
+use shape::{AxisAlignedRect, Shape};
+
+/// Ground further below an entity's feet than this counts as a ledge.
+const LEDGE_DEPTH: f32 = 8.;
+/// The size of the shape swept to look for ledges and obstacles to sight.
+const PROBE_SIZE: f32 = 1.;
+const DEFAULT_SIGHT_RANGE: f32 = 256.;
+
+fn default_sight_range() -> f32 {
+    DEFAULT_SIGHT_RANGE
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Walk back and forth, turning at walls and ledges.
+    Patrol,
+    /// Walk towards the player while it's in sight, and patrol otherwise.
+    Chase,
+    /// Walk away from the player while it's in sight, and patrol otherwise.
+    Flee,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Facing {
+    Left,
+    Right,
+}
+
+impl Default for Facing {
+    fn default() -> Self {
+        Facing::Right
+    }
+}
+
+impl Facing {
+    fn towards(dx: f32) -> Self {
+        if dx < 0. {
+            Facing::Left
+        } else {
+            Facing::Right
+        }
+    }
+    fn sign(self) -> f32 {
+        match self {
+            Facing::Left => -1.,
+            Facing::Right => 1.,
+        }
+    }
+    fn reverse(self) -> Self {
+        match self {
+            Facing::Left => Facing::Right,
+            Facing::Right => Facing::Left,
+        }
+    }
+}
+
+/// Built in behaviour for an entity which walks along the ground. Behaviours only
+/// set the horizontal part of the entity's velocity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Behaviour {
+    pub mode: Mode,
+    /// Distance walked each step.
+    pub speed: f32,
+    /// How far away, between centres, the player can be seen from.
+    #[serde(default = "default_sight_range")]
+    pub sight_range: f32,
+    #[serde(default)]
+    pub facing: Facing,
+}
+
+fn probe() -> Shape<f32> {
+    Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(PROBE_SIZE, PROBE_SIZE)))
+}
+
+/// Whether static entities stop `shape` moving the whole of `movement`.
+fn is_blocked(
+    game_state: &GameState,
+    shape: &Shape<f32>,
+    top_left: Vector2<f32>,
+    movement: Vector2<f32>,
+) -> bool {
+    const EPSILON: f32 = 0.01;
+    let moved = game_state.sweep(shape, top_left, movement) - top_left;
+    moved.magnitude() < movement.magnitude() - EPSILON
+}
+
+/// Whether the entity can walk `dx` without walking into a wall or, if it's on
+/// the ground, off a ledge. Slopes count as walls if they take away more than
+/// half of the progress.
+fn can_walk(
+    game_state: &GameState,
+    shape: &Shape<f32>,
+    aabb: &Aabb<f32>,
+    dx: f32,
+) -> bool {
+    let top_left = aabb.top_left();
+    let walked = game_state.sweep(shape, top_left, vec2(dx, 0.)) - top_left;
+    if walked.x.abs() < dx.abs() / 2. {
+        return false;
+    }
+    if !is_blocked(game_state, shape, top_left, vec2(0., LEDGE_DEPTH)) {
+        return true;
+    }
+    // Look for ground below the leading edge of where the entity will be.
+    let bottom_right = aabb.bottom_right_coord();
+    let x = if dx > 0. {
+        bottom_right.x + dx - PROBE_SIZE
+    } else {
+        top_left.x + dx
+    };
+    is_blocked(
+        game_state,
+        &probe(),
+        vec2(x, bottom_right.y - PROBE_SIZE),
+        vec2(0., LEDGE_DEPTH),
+    )
+}
+
+/// The vector from the centre of `aabb` to the centre of the player, if the player
+/// is in range and there are no static entities in the way.
+fn visible_player(
+    game_state: &GameState,
+    aabb: &Aabb<f32>,
+    range: f32,
+) -> Option<Vector2<f32>> {
+    let player = game_state.common(game_state.player_id()?)?;
+    let to_player = player.shape.aabb(player.top_left).centre() - aabb.centre();
+    if to_player.magnitude() > range {
+        return None;
+    }
+    let eye = aabb.centre() - vec2(PROBE_SIZE, PROBE_SIZE) / 2.;
+    if is_blocked(game_state, &probe(), eye, to_player) {
+        None
+    } else {
+        Some(to_player)
+    }
+}
+
+impl Behaviour {
+    /// Updates the facing and returns the velocity for the next step.
+    fn steer(
+        &mut self,
+        game_state: &GameState,
+        id: EntityId,
+        velocity: Vector2<f32>,
+    ) -> Vector2<f32> {
+        let common = match game_state.common(id) {
+            Some(common) => common,
+            None => return velocity,
+        };
+        let aabb = common.shape.aabb(common.top_left);
+        let to_player = match self.mode {
+            Mode::Patrol => None,
+            Mode::Chase | Mode::Flee => {
+                visible_player(game_state, &aabb, self.sight_range)
+            }
+        };
+        let dx = match to_player {
+            Some(to_player) => {
+                let towards = Facing::towards(to_player.x);
+                self.facing = if self.mode == Mode::Chase {
+                    towards
+                } else {
+                    towards.reverse()
+                };
+                let dx = self.facing.sign() * self.speed;
+                // Cornered, or close enough to stop rather than overshoot.
+                let arrived = self.mode == Mode::Chase && to_player.x.abs() < self.speed;
+                if arrived || !can_walk(game_state, &common.shape, &aabb, dx) {
+                    0.
+                } else {
+                    dx
+                }
+            }
+            None => {
+                let dx = self.facing.sign() * self.speed;
+                if can_walk(game_state, &common.shape, &aabb, dx) {
+                    dx
+                } else {
+                    self.facing = self.facing.reverse();
+                    if can_walk(game_state, &common.shape, &aabb, -dx) {
+                        -dx
+                    } else {
+                        0.
+                    }
+                }
+            }
+        };
+        vec2(dx, velocity.y)
+    }
+}
+
+/// Steers every entity with both a behaviour and a velocity, other than the
+/// player. Entities are steered independently, so the order doesn't matter.
+pub struct BehaviourSystem;
+
+impl System for BehaviourSystem {
+    fn run(&mut self, game_state: &mut GameState) {
+        let steered = {
+            let game_state: &GameState = game_state;
+            let player_id = game_state.player_id();
+            let mut steered = Vec::new();
+            game_state.components().join(
+                |id, behaviour: &Behaviour, &Velocity(velocity)| {
+                    if Some(id) != player_id {
+                        let mut behaviour = *behaviour;
+                        let velocity = behaviour.steer(game_state, id, velocity);
+                        steered.push((id, behaviour, velocity));
+                    }
+                },
+            );
+            steered
+        };
+        let components = game_state.components_mut();
+        for (id, behaviour, velocity) in steered {
+            components.insert(id, behaviour);
+            components.insert(id, Velocity(velocity));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::rect;
+
+    fn behaviour(mode: Mode) -> Behaviour {
+        Behaviour {
+            mode,
+            speed: 2.,
+            sight_range: DEFAULT_SIGHT_RANGE,
+            facing: Facing::Right,
+        }
+    }
+
+    /// A platform from 100 to 300 with a wall at its right end, and the player
+    /// far below it on the floor.
+    fn game_state() -> GameState {
+        let mut game_state = GameState::new(vec2(400., 400.), Default::default());
+        game_state.add_static_solid(rect(100., 100., 200., 10.));
+        game_state.add_static_solid(rect(280., 40., 20., 60.));
+        game_state.add_static_solid(rect(0., 380., 400., 10.));
+        game_state.add_player(rect(10., 364., 16., 16.));
+        game_state.add_system(Box::new(BehaviourSystem));
+        game_state
+    }
+
+    fn add_enemy(game_state: &mut GameState, x: f32, mode: Mode) -> EntityId {
+        let id = game_state.add_mover(rect(x, 84., 16., 16.), vec2(0., 0.));
+        game_state.components_mut().insert(id, behaviour(mode));
+        id
+    }
+
+    fn x(game_state: &GameState, id: EntityId) -> f32 {
+        game_state.common(id).unwrap().top_left.x
+    }
+
+    #[test]
+    fn patrol_turns_at_walls_and_ledges() {
+        let mut game_state = game_state();
+        let enemy = add_enemy(&mut game_state, 150., Mode::Patrol);
+        let mut min_x = x(&game_state, enemy);
+        let mut max_x = min_x;
+        for _ in 0..400 {
+            game_state.update(&Default::default());
+            min_x = min_x.min(x(&game_state, enemy));
+            max_x = max_x.max(x(&game_state, enemy));
+        }
+        // It turns before its leading edge goes past the end of the platform at 100,
+        // and the wall stops it at 264.
+        assert!(min_x > 99. && min_x < 102., "{}", min_x);
+        assert!(max_x > 260. && max_x <= 264., "{}", max_x);
+        assert_eq!(game_state.common(enemy).unwrap().top_left.y, 84.);
+    }
+
+    #[test]
+    fn chase_and_flee_need_sight_of_the_player() {
+        let mut game_state = game_state();
+        let player = game_state.player_id().unwrap();
+        let chaser = add_enemy(&mut game_state, 150., Mode::Chase);
+        let fleer = add_enemy(&mut game_state, 200., Mode::Flee);
+        // The platform hides the player, so both patrol to the right.
+        game_state.update(&Default::default());
+        game_state.update(&Default::default());
+        assert!(x(&game_state, chaser) > 150.);
+        assert!(x(&game_state, fleer) > 200.);
+
+        game_state.common_mut(player).unwrap().top_left = vec2(40., 84.);
+        for _ in 0..200 {
+            game_state.update(&Default::default());
+        }
+        // The chaser stops at the ledge nearest the player, and the fleer is
+        // cornered against the wall.
+        assert!(x(&game_state, chaser) < 102., "{}", x(&game_state, chaser));
+        assert!(x(&game_state, fleer) > 260., "{}", x(&game_state, fleer));
+        let facing = |id| game_state.components().get::<Behaviour>(id).unwrap().facing;
+        assert_eq!(facing(chaser), Facing::Left);
+        assert_eq!(facing(fleer), Facing::Right);
+    }
+}
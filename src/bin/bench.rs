@@ -0,0 +1,369 @@
+//! Benchmarks the broad phases, movement and stepping. This is the only binary
+//! which counts allocations, so the game doesn't pay for it.
+
+extern crate cgmath;
+extern crate platform_game_experiment;
+
+use cgmath::{vec2, Vector2};
+use platform_game_experiment::broad_phase::{AnyBroadPhase, BroadPhase, BroadPhaseKind};
+use platform_game_experiment::rng::XorShift;
+use platform_game_experiment::shape::{AxisAlignedRect, Shape};
+use platform_game_experiment::LineSegment;
+use platform_game_experiment::{Aabb, EntityCommon, EntityId, GameState, InputModel};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+static NUM_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static NUM_BYTES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Forwards to the system allocator, counting allocations so benchmarks can
+/// report them.
+struct CountingAllocator;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        NUM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        NUM_BYTES_ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        NUM_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        NUM_BYTES_ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+const SCENE_SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const BROAD_PHASE_KINDS: [BroadPhaseKind; 3] = [
+    BroadPhaseKind::LooseQuadTree,
+    BroadPhaseKind::UniformGrid { cell_size: 64. },
+    BroadPhaseKind::SweepAndPrune,
+];
+const NUM_QUERIES: usize = 1_000;
+const NUM_STEPS: usize = 10;
+const NUM_LINE_SEGMENT_PAIRS: usize = 1_000_000;
+/// Results are considered regressions if they are this much slower than the
+/// baseline.
+const REGRESSION_THRESHOLD: f64 = 0.1;
+
+fn broad_phase_kind_name(kind: BroadPhaseKind) -> &'static str {
+    match kind {
+        BroadPhaseKind::LooseQuadTree => "quad-tree",
+        BroadPhaseKind::UniformGrid { .. } => "grid",
+        BroadPhaseKind::SweepAndPrune => "sweep-and-prune",
+    }
+}
+
+struct Scene {
+    size: Vector2<f32>,
+    solids: Vec<Aabb<f32>>,
+    movers: Vec<(Aabb<f32>, Vector2<f32>)>,
+    queries: Vec<Aabb<f32>>,
+}
+
+impl Scene {
+    fn generate(num_solids: usize, num_movers: usize, seed: u64) -> Self {
+        let mut rng = XorShift::new(seed);
+        let side = (num_solids as f32).sqrt() * 48.;
+        let size = vec2(side, side);
+        let solids = (0..num_solids)
+            .map(|_| Aabb::new(rng.vector(0., side - 64.), rng.vector(8., 64.)))
+            .collect();
+        let movers = (0..num_movers)
+            .map(|_| {
+                let aabb = Aabb::new(rng.vector(0., side - 16.), vec2(16., 16.));
+                (aabb, rng.vector(-4., 4.))
+            })
+            .collect();
+        let queries = (0..NUM_QUERIES)
+            .map(|_| Aabb::new(rng.vector(0., side - 32.), rng.vector(4., 32.)))
+            .collect();
+        Self {
+            size,
+            solids,
+            movers,
+            queries,
+        }
+    }
+
+    fn game_state(&self, kind: BroadPhaseKind) -> (GameState, Vec<EntityId>) {
+        let mut game_state = GameState::new(self.size, kind);
+        for solid in self.solids.iter() {
+            game_state.add_static_solid(rect_common(solid));
+        }
+        game_state.add_player(rect_common(&Aabb::new(vec2(0., 0.), vec2(32., 64.))));
+        let mover_ids = self
+            .movers
+            .iter()
+            .map(|&(ref aabb, velocity)| {
+                game_state.add_mover(rect_common(aabb), velocity)
+            })
+            .collect();
+        (game_state, mover_ids)
+    }
+}
+
+fn rect_common(aabb: &Aabb<f32>) -> EntityCommon {
+    EntityCommon::new(
+        aabb.top_left(),
+        Shape::AxisAlignedRect(AxisAlignedRect::new(aabb.size())),
+        [1., 1., 1.],
+    )
+}
+
+fn duration_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+#[derive(Debug, Clone)]
+struct Measurement {
+    name: String,
+    operations: usize,
+    nanos_per_operation: f64,
+    allocations_per_operation: f64,
+    bytes_per_operation: f64,
+}
+
+impl Measurement {
+    fn header() -> String {
+        format!(
+            "{:<40} {:>10} {:>14} {:>12} {:>14}",
+            "name", "operations", "ns/op", "allocs/op", "bytes/op"
+        )
+    }
+    fn to_line(&self) -> String {
+        format!(
+            "{:<40} {:>10} {:>14.1} {:>12.2} {:>14.1}",
+            self.name,
+            self.operations,
+            self.nanos_per_operation,
+            self.allocations_per_operation,
+            self.bytes_per_operation
+        )
+    }
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let name = fields.next()?.to_string();
+        let operations = fields.next()?.parse().ok()?;
+        let nanos_per_operation = fields.next()?.parse().ok()?;
+        let allocations_per_operation = fields.next()?.parse().ok()?;
+        let bytes_per_operation = fields.next()?.parse().ok()?;
+        Some(Self {
+            name,
+            operations,
+            nanos_per_operation,
+            allocations_per_operation,
+            bytes_per_operation,
+        })
+    }
+}
+
+/// Runs `f` `iterations` times, recording the time taken and the number of
+/// allocations made. Each call to `f` is expected to perform `operations_per_call`
+/// of the operation being measured, and results are reported per operation.
+fn measure<F: FnMut()>(
+    name: String,
+    iterations: usize,
+    operations_per_call: usize,
+    mut f: F,
+) -> Measurement {
+    let allocations_before = NUM_ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes_before = NUM_BYTES_ALLOCATED.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let total_nanos = duration_nanos(start.elapsed());
+    let total_allocations = NUM_ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    let total_bytes = NUM_BYTES_ALLOCATED.load(Ordering::Relaxed) - bytes_before;
+    let operations = iterations * operations_per_call;
+    let measurement = Measurement {
+        name,
+        operations,
+        nanos_per_operation: total_nanos as f64 / operations as f64,
+        allocations_per_operation: total_allocations as f64 / operations as f64,
+        bytes_per_operation: total_bytes as f64 / operations as f64,
+    };
+    println!("{}", measurement.to_line());
+    measurement
+}
+
+fn broad_phase_with(kind: BroadPhaseKind, scene: &Scene) -> AnyBroadPhase<usize, f32> {
+    let mut broad_phase = AnyBroadPhase::new(kind, scene.size);
+    for (i, solid) in scene.solids.iter().enumerate() {
+        broad_phase.insert(*solid, i);
+    }
+    broad_phase
+}
+
+fn bench_scene(num_solids: usize, measurements: &mut Vec<Measurement>) {
+    let scene = Scene::generate(num_solids, (num_solids / 10).max(100), 0x5eed);
+    for &kind in BROAD_PHASE_KINDS.iter() {
+        let label = format!("{}/{}", broad_phase_kind_name(kind), num_solids);
+        measurements.push(measure(format!("insert/{}", label), 3, num_solids, || {
+            black_box(broad_phase_with(kind, &scene));
+        }));
+        let broad_phase = broad_phase_with(kind, &scene);
+        measurements.push(measure(
+            format!("query/{}", label),
+            10,
+            scene.queries.len(),
+            || {
+                for query in scene.queries.iter() {
+                    let mut count = 0;
+                    broad_phase.for_each_intersection(query, |_, _| count += 1);
+                    black_box(count);
+                }
+            },
+        ));
+        measurements.push(measure(format!("pairs/{}", label), 1, 1, || {
+            let mut count = 0;
+            broad_phase.for_each_overlapping_pair(|_, _, _, _| count += 1);
+            black_box(count);
+        }));
+        let (mut game_state, mover_ids) = scene.game_state(kind);
+        measurements.push(measure(
+            format!("movement/{}", label),
+            10,
+            mover_ids.len(),
+            || {
+                for (&id, &(_, velocity)) in mover_ids.iter().zip(scene.movers.iter()) {
+                    black_box(game_state.top_left_after_movement(id, velocity));
+                }
+            },
+        ));
+        let input_model = InputModel::default();
+        measurements.push(measure(format!("step/{}", label), NUM_STEPS, 1, || {
+            game_state.update(&input_model)
+        }));
+    }
+}
+
+fn bench_line_segment_intersection(measurements: &mut Vec<Measurement>) {
+    let mut rng = XorShift::new(0x11e);
+    let pairs = (0..1024)
+        .map(|_| {
+            let a = LineSegment::new(rng.vector(0., 100.), rng.vector(0., 100.));
+            let b = LineSegment::new(rng.vector(0., 100.), rng.vector(0., 100.));
+            (a, b)
+        })
+        .collect::<Vec<_>>();
+    measurements.push(measure(
+        "line-segment-intersection".to_string(),
+        NUM_LINE_SEGMENT_PAIRS / pairs.len(),
+        pairs.len(),
+        || {
+            for &(ref a, ref b) in pairs.iter() {
+                let _ = black_box(a.intersection(b));
+            }
+        },
+    ));
+}
+
+fn read_baseline(path: &str) -> io::Result<HashMap<String, Measurement>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .filter_map(Measurement::from_line)
+        .map(|measurement| (measurement.name.clone(), measurement))
+        .collect())
+}
+
+fn compare(baseline: &HashMap<String, Measurement>, measurements: &[Measurement]) {
+    println!();
+    println!("{:<40} {:>12} {:>14}", "name", "time", "allocs/op");
+    for measurement in measurements {
+        if let Some(base) = baseline.get(&measurement.name) {
+            let change = measurement.nanos_per_operation / base.nanos_per_operation - 1.;
+            let marker = if change > REGRESSION_THRESHOLD {
+                "  REGRESSION"
+            } else {
+                ""
+            };
+            println!(
+                "{:<40} {:>+11.1}% {:>+14.2}{}",
+                measurement.name,
+                change * 100.,
+                measurement.allocations_per_operation - base.allocations_per_operation,
+                marker
+            );
+        }
+    }
+}
+
+struct BenchArgs {
+    save_path: Option<String>,
+    compare_path: Option<String>,
+}
+
+/// Runs every benchmark, printing results as they complete. Results can be saved
+/// and later used as a baseline to spot regressions.
+fn run(args: &BenchArgs) -> io::Result<()> {
+    let baseline = match args.compare_path {
+        Some(ref path) => Some(read_baseline(path)?),
+        None => None,
+    };
+    let mut measurements = Vec::new();
+    println!("{}", Measurement::header());
+    bench_line_segment_intersection(&mut measurements);
+    for &num_solids in SCENE_SIZES.iter() {
+        bench_scene(num_solids, &mut measurements);
+    }
+    if let Some(ref path) = args.save_path {
+        let mut contents = Measurement::header();
+        for measurement in measurements.iter() {
+            contents.push('\n');
+            contents.push_str(&measurement.to_line());
+        }
+        contents.push('\n');
+        fs::write(path, contents)?;
+    }
+    if let Some(baseline) = baseline {
+        compare(&baseline, &measurements);
+    }
+    Ok(())
+}
+
+fn parse_args() -> Result<BenchArgs, String> {
+    let mut bench_args = BenchArgs {
+        save_path: None,
+        compare_path: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let path = args
+            .next()
+            .ok_or_else(|| format!("expected a path after {}", arg))?;
+        match arg.as_str() {
+            "--save" => bench_args.save_path = Some(path),
+            "--compare" => bench_args.compare_path = Some(path),
+            other => return Err(format!("unexpected argument {:?}", other)),
+        }
+    }
+    Ok(bench_args)
+}
+
+fn main() {
+    let bench_args = match parse_args() {
+        Ok(bench_args) => bench_args,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = run(&bench_args) {
+        eprintln!("benchmark failed: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,499 @@
+//! Runs a level twice with the same input and reports the first step and entity at
+//! which the runs differ. The second run can use a different broad phase, or be
+//! replaced by a hash trace written by another build with `--write`.
+
+extern crate fnv;
+extern crate platform_game_experiment;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use fnv::FnvHashMap;
+use platform_game_experiment::broad_phase::BroadPhaseKind;
+use platform_game_experiment::level::{Level, LevelError, LevelSource};
+use platform_game_experiment::replay::{Difference, InputScript, Recording, StateHash};
+use platform_game_experiment::snapshot::Snapshot;
+use platform_game_experiment::{EntityId, GameState, InputModel};
+use serde::de::{self, Deserialize, Deserializer};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+const DEFAULT_LEVEL_PATH: &str = "levels/demo.json";
+
+/// Version of the hash trace format written by this version of the game. Bump this
+/// when making a change to the format or to how states are hashed.
+const HASH_TRACE_FORMAT_VERSION: u32 = 1;
+
+fn supported_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == HASH_TRACE_FORMAT_VERSION {
+        Ok(version)
+    } else {
+        Err(de::Error::custom(format_args!(
+            "unsupported hash trace format version {} (expected {})",
+            version, HASH_TRACE_FORMAT_VERSION
+        )))
+    }
+}
+
+/// The hashes after one step. Only entities whose hash changed are stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TraceStep {
+    total: u64,
+    globals: u64,
+    /// Entities which were added or changed by the step.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    changed: Vec<(EntityId, u64)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<EntityId>,
+}
+
+/// The state hash after each step of a run, for comparing runs made at different
+/// times or with different builds of the game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HashTrace {
+    #[serde(deserialize_with = "supported_version")]
+    version: u32,
+    steps: Vec<TraceStep>,
+    /// The hashes of the entities after the last step.
+    #[serde(skip)]
+    entities: FnvHashMap<EntityId, u64>,
+}
+
+/// The first step at which two runs differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mismatch {
+    pub step: usize,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "runs diverged at step {}: {}",
+            self.step, self.difference
+        )
+    }
+}
+
+/// Rebuilds the state hash after each step of a trace.
+struct States<'a> {
+    steps: &'a [TraceStep],
+    entities: FnvHashMap<EntityId, u64>,
+}
+
+impl<'a> Iterator for States<'a> {
+    type Item = StateHash;
+    fn next(&mut self) -> Option<StateHash> {
+        let (step, rest) = self.steps.split_first()?;
+        self.steps = rest;
+        for id in step.removed.iter() {
+            self.entities.remove(id);
+        }
+        self.entities.extend(step.changed.iter().cloned());
+        let mut entities = self
+            .entities
+            .iter()
+            .map(|(&id, &hash)| (id, hash))
+            .collect::<Vec<_>>();
+        entities.sort_by_key(|&(id, _)| (id.index(), id.generation()));
+        Some(StateHash {
+            total: step.total,
+            globals: step.globals,
+            entities,
+        })
+    }
+}
+
+impl Default for HashTrace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashTrace {
+    pub fn new() -> Self {
+        Self {
+            version: HASH_TRACE_FORMAT_VERSION,
+            steps: Vec::new(),
+            entities: FnvHashMap::default(),
+        }
+    }
+
+    /// The number of steps traced.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Call after each step.
+    pub fn record(&mut self, state_hash: &StateHash) {
+        let current = state_hash
+            .entities
+            .iter()
+            .cloned()
+            .collect::<FnvHashMap<_, _>>();
+        let changed = state_hash
+            .entities
+            .iter()
+            .filter(|&&(id, hash)| self.entities.get(&id) != Some(&hash))
+            .cloned()
+            .collect();
+        let mut removed = self
+            .entities
+            .keys()
+            .filter(|id| !current.contains_key(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        removed.sort_by_key(|id| (id.index(), id.generation()));
+        self.steps.push(TraceStep {
+            total: state_hash.total,
+            globals: state_hash.globals,
+            changed,
+            removed,
+        });
+        self.entities = current;
+    }
+
+    fn states<'a>(&'a self) -> States<'a> {
+        States {
+            steps: &self.steps,
+            entities: FnvHashMap::default(),
+        }
+    }
+
+    /// The state hash after `step` (counting from 0).
+    #[cfg(test)]
+    pub fn state(&self, step: usize) -> Option<StateHash> {
+        self.states().nth(step)
+    }
+
+    /// The first step at which the runs differ, and what differs.
+    pub fn first_mismatch(&self, other: &HashTrace) -> Option<Mismatch> {
+        let mut ours = self.states();
+        let mut theirs = other.states();
+        let mut step = 0;
+        loop {
+            let difference = match (ours.next(), theirs.next()) {
+                (Some(a), Some(b)) => a.difference(&b),
+                (None, None) => return None,
+                _ => Some(Difference::Length),
+            };
+            if let Some(difference) = difference {
+                return Some(Mismatch { step, difference });
+            }
+            step += 1;
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(path, &text)
+    }
+
+    /// Parses a trace from `text`. `path` is only used in error messages.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let mut trace: Self =
+            serde_json::from_str(text).map_err(|error| LevelError::Parse {
+                path: path.to_path_buf(),
+                line: error.line(),
+                column: error.column(),
+                error,
+            })?;
+        let entities = trace
+            .states()
+            .last()
+            .map_or_else(Vec::new, |state_hash| state_hash.entities);
+        trace.entities = entities.into_iter().collect();
+        Ok(trace)
+    }
+
+    /// Traces are written without whitespace, as they can get long.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize hash trace")
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LevelError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json() + "\n").map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+/// Runs `game_state` for `steps` steps with the input returned by `input` for each
+/// step, hashing the state after each one.
+fn trace<F: FnMut(usize) -> InputModel>(
+    game_state: &mut GameState,
+    steps: usize,
+    mut input: F,
+) -> HashTrace {
+    let mut trace = HashTrace::new();
+    for step in 0..steps {
+        game_state.update(&input(step));
+        trace.record(&StateHash::new(game_state));
+    }
+    trace
+}
+
+struct Args {
+    level_source: LevelSource,
+    snapshot_path: Option<String>,
+    replay_path: Option<String>,
+    script: Option<InputScript>,
+    steps: Option<usize>,
+    broad_phase_kind: BroadPhaseKind,
+    /// Defaults to the broad phase of the first run.
+    other_broad_phase_kind: Option<BroadPhaseKind>,
+    write_path: Option<String>,
+    compare_path: Option<String>,
+}
+
+fn broad_phase_kind(arg: &str, value: &str) -> Result<BroadPhaseKind, String> {
+    BroadPhaseKind::from_name(value).ok_or_else(|| {
+        format!(
+            "expected one of quad-tree, grid or sweep-and-prune after {}, got {:?}",
+            arg, value
+        )
+    })
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Self {
+            level_source: LevelSource::Level(DEFAULT_LEVEL_PATH.to_string()),
+            snapshot_path: None,
+            replay_path: None,
+            script: None,
+            steps: None,
+            broad_phase_kind: BroadPhaseKind::default(),
+            other_broad_phase_kind: None,
+            write_path: None,
+            compare_path: None,
+        };
+        let mut raw_args = std::env::args().skip(1);
+        while let Some(arg) = raw_args.next() {
+            let value = raw_args
+                .next()
+                .ok_or_else(|| format!("expected a value after {}", arg))?;
+            match arg.as_str() {
+                "--level" => args.level_source = LevelSource::Level(value),
+                "--tiled-map" => args.level_source = LevelSource::TiledMap(value),
+                "--snapshot" => args.snapshot_path = Some(value),
+                "--replay" => args.replay_path = Some(value),
+                "--input" => args.script = Some(InputScript::parse(&value)?),
+                "--steps" => {
+                    args.steps = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("expected a number after {}", arg))?,
+                    )
+                }
+                "--broad-phase" => {
+                    args.broad_phase_kind = broad_phase_kind(&arg, &value)?
+                }
+                "--other-broad-phase" => {
+                    args.other_broad_phase_kind = Some(broad_phase_kind(&arg, &value)?)
+                }
+                "--write" => args.write_path = Some(value),
+                "--compare" => args.compare_path = Some(value),
+                other => return Err(format!("unexpected argument {:?}", other)),
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Everything needed to start a run from the beginning.
+struct Setup {
+    level: Level,
+    snapshot: Option<Snapshot>,
+    recording: Option<Recording>,
+    script: Option<InputScript>,
+    steps: usize,
+}
+
+impl Setup {
+    fn new(args: &Args) -> Result<Self, String> {
+        let level = args
+            .level_source
+            .load()
+            .map_err(|error| format!("failed to load level: {}", error))?;
+        let snapshot = match args.snapshot_path {
+            Some(ref path) => Some(
+                Snapshot::load(path)
+                    .map_err(|error| format!("failed to load snapshot: {}", error))?,
+            ),
+            None => None,
+        };
+        let recording = match args.replay_path {
+            Some(ref path) => Some(
+                Recording::load(path)
+                    .map_err(|error| format!("failed to load recording: {}", error))?,
+            ),
+            None => None,
+        };
+        let script = args.script.clone();
+        let steps = args.steps.unwrap_or_else(|| {
+            let recording_len = recording.as_ref().map_or(0, Recording::len);
+            let script_len = script.as_ref().map_or(0, InputScript::len);
+            recording_len.max(script_len)
+        });
+        Ok(Self {
+            level,
+            snapshot,
+            recording,
+            script,
+            steps,
+        })
+    }
+
+    /// Runs `steps` steps from the start, with input from the recording, then the
+    /// script, as the headless binary does. Recorded checksums aren't checked.
+    fn run(
+        &self,
+        broad_phase_kind: BroadPhaseKind,
+        steps: usize,
+    ) -> (GameState, HashTrace) {
+        let mut game_state = self.level.build(broad_phase_kind);
+        if let Some(ref snapshot) = self.snapshot {
+            game_state.restore(snapshot);
+        }
+        let trace = trace(&mut game_state, steps, |step| {
+            self.recording
+                .as_ref()
+                .and_then(|recording| recording.input(step))
+                .or_else(|| self.script.as_ref().and_then(|script| script.input(step)))
+                .unwrap_or_default()
+        });
+        (game_state, trace)
+    }
+}
+
+fn print_entity(label: &str, game_state: &GameState, id: EntityId) {
+    let snapshot = game_state.snapshot();
+    match snapshot.entities.iter().find(|entity| entity.id == id) {
+        Some(entity) => println!(
+            "{}:\n{}",
+            label,
+            serde_json::to_string_pretty(entity).expect("failed to serialize entity")
+        ),
+        None => println!("{}: no such entity", label),
+    }
+}
+
+fn run(args: &Args) -> Result<bool, String> {
+    let setup = Setup::new(args)?;
+    let (_, trace) = setup.run(args.broad_phase_kind, setup.steps);
+    if let Some(ref path) = args.write_path {
+        trace.save(path).map_err(|error| error.to_string())?;
+    }
+    let other_broad_phase_kind =
+        args.other_broad_phase_kind.unwrap_or(args.broad_phase_kind);
+    let other_trace = match args.compare_path {
+        Some(ref path) => HashTrace::load(path)
+            .map_err(|error| format!("failed to load hash trace: {}", error))?,
+        None => setup.run(other_broad_phase_kind, setup.steps).1,
+    };
+    let mismatch = match trace.first_mismatch(&other_trace) {
+        Some(mismatch) => mismatch,
+        None => {
+            println!("no divergence in {} steps", trace.len());
+            return Ok(true);
+        }
+    };
+    println!("{}", mismatch);
+    if let Difference::Entity(id) = mismatch.difference {
+        let (game_state, _) = setup.run(args.broad_phase_kind, mismatch.step + 1);
+        print_entity("this run", &game_state, id);
+        if args.compare_path.is_none() {
+            let (game_state, _) = setup.run(other_broad_phase_kind, mismatch.step + 1);
+            print_entity("other run", &game_state, id);
+        }
+    }
+    Ok(false)
+}
+
+fn main() {
+    match Args::parse().and_then(|args| run(&args)) {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DEMO: &str = include_str!("../../levels/demo.json");
+
+    fn input(step: usize) -> InputModel {
+        let mut input_model = InputModel::default();
+        input_model.set_right(1.);
+        input_model.set_up(if step % 40 < 10 { 1. } else { 0. });
+        input_model
+    }
+
+    #[test]
+    fn hash_ignores_storage_order() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let mut game_state = level.build(BroadPhaseKind::default());
+        trace(&mut game_state, 30, input);
+        // Restoring inserts entities into the hash maps in a different order to
+        // building the level and running it.
+        let mut restored = level.build(BroadPhaseKind::SweepAndPrune);
+        restored.restore(&game_state.snapshot());
+        assert_eq!(StateHash::new(&restored), StateHash::new(&game_state));
+        let a = trace(&mut game_state, 30, input);
+        let b = trace(&mut restored, 30, input);
+        assert_eq!(a.first_mismatch(&b), None);
+    }
+
+    #[test]
+    fn finds_first_diverging_entity() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let expected = trace(&mut level.build(BroadPhaseKind::default()), 60, input);
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let player = game_state.player_id().unwrap();
+        let mut actual = trace(&mut game_state, 20, input);
+        game_state.common_mut(player).unwrap().top_left.y -= 0.5;
+        for step in 20..60 {
+            game_state.update(&input(step));
+            actual.record(&StateHash::new(&game_state));
+        }
+        let parsed = HashTrace::parse("trace.json", &expected.to_json()).unwrap();
+        assert_eq!(parsed, expected);
+        assert_eq!(
+            parsed.first_mismatch(&actual),
+            Some(Mismatch {
+                step: 20,
+                difference: Difference::Entity(player),
+            })
+        );
+        assert_eq!(parsed.state(19), actual.state(19));
+        let shorter = trace(&mut level.build(BroadPhaseKind::default()), 40, input);
+        assert_eq!(
+            expected.first_mismatch(&shorter),
+            Some(Mismatch {
+                step: 40,
+                difference: Difference::Length,
+            })
+        );
+    }
+}
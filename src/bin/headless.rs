@@ -0,0 +1,240 @@
+//! Runs a level without a window, for machines with no display. This doesn't
+//! depend on the `graphics` feature.
+
+extern crate platform_game_experiment;
+
+use platform_game_experiment::broad_phase::BroadPhaseKind;
+use platform_game_experiment::level::LevelSource;
+use platform_game_experiment::replay::{InputScript, LevelIdentity, Recording, Replay};
+use platform_game_experiment::snapshot::Snapshot;
+use platform_game_experiment::GameState;
+use std::fmt::Write;
+
+const DEFAULT_LEVEL_PATH: &str = "levels/demo.json";
+
+struct HeadlessArgs {
+    /// Defaults to the length of the replay or script.
+    steps: Option<usize>,
+    script: Option<InputScript>,
+    /// Where to write a snapshot of the final state.
+    dump_path: Option<String>,
+}
+
+/// Runs `game_state` for the requested number of steps, with input from
+/// `recording` if there is one, then from the script. Once both run out, no
+/// keys are held. Fails if the replay diverges from the recording.
+fn simulate(
+    game_state: &mut GameState,
+    args: &HeadlessArgs,
+    recording: Option<&Recording>,
+) -> Result<(), String> {
+    let steps = args.steps.unwrap_or_else(|| {
+        let recording_len = recording.map_or(0, Recording::len);
+        let script_len = args.script.as_ref().map_or(0, InputScript::len);
+        recording_len.max(script_len)
+    });
+    let mut replay = recording.map(Replay::new);
+    for step in 0..steps {
+        let replay_input = replay.as_ref().and_then(Replay::next_input);
+        let input_model = replay_input
+            .or_else(|| args.script.as_ref().and_then(|script| script.input(step)))
+            .unwrap_or_default();
+        game_state.update(&input_model);
+        if replay_input.is_some() {
+            if let Some(ref mut replay) = replay {
+                replay
+                    .advance(game_state)
+                    .map_err(|divergence| divergence.to_string())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn entity_kind(snapshot: &Snapshot, entity_index: usize) -> &'static str {
+    let entity = &snapshot.entities[entity_index];
+    if snapshot.player_id == Some(entity.id) {
+        "player"
+    } else if entity.trigger.is_some() {
+        "trigger"
+    } else if entity.velocity.is_some() {
+        "mover"
+    } else {
+        "static"
+    }
+}
+
+/// The position of every entity with a shape, and the collision statistics.
+fn report(game_state: &GameState) -> String {
+    let snapshot = game_state.snapshot();
+    let mut report = String::new();
+    for (index, entity) in snapshot.entities.iter().enumerate() {
+        if let Some(ref common) = entity.common {
+            writeln!(
+                report,
+                "{:>6} {:<7} {:>10.3} {:>10.3}",
+                entity.id.index(),
+                entity_kind(&snapshot, index),
+                common.top_left[0],
+                common.top_left[1]
+            )
+            .unwrap();
+        }
+    }
+    let stats = game_state.collision_stats();
+    writeln!(report, "movements: {}", stats.movements).unwrap();
+    writeln!(report, "broad phase queries: {}", stats.queries).unwrap();
+    writeln!(report, "narrow phase tests: {}", stats.candidates).unwrap();
+    writeln!(report, "collisions: {}", stats.collisions).unwrap();
+    writeln!(
+        report,
+        "iteration limit reached: {}",
+        stats.iteration_limit_reached
+    )
+    .unwrap();
+    report
+}
+
+/// Simulates without a window, then prints the final state and optionally dumps
+/// it as a snapshot.
+fn simulate_and_report(
+    game_state: &mut GameState,
+    args: &HeadlessArgs,
+    recording: Option<&Recording>,
+) -> Result<(), String> {
+    simulate(game_state, args, recording)?;
+    print!("{}", report(game_state));
+    if let Some(ref path) = args.dump_path {
+        game_state
+            .snapshot()
+            .save(path)
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+struct Args {
+    level_source: LevelSource,
+    snapshot_path: Option<String>,
+    replay_path: Option<String>,
+    broad_phase_kind: BroadPhaseKind,
+    headless: HeadlessArgs,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut level_source = LevelSource::Level(DEFAULT_LEVEL_PATH.to_string());
+        let mut snapshot_path = None;
+        let mut replay_path = None;
+        let mut broad_phase_kind = BroadPhaseKind::default();
+        let mut headless = HeadlessArgs {
+            steps: None,
+            script: None,
+            dump_path: None,
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("expected a value after {}", arg))?;
+            match arg.as_str() {
+                "--level" => level_source = LevelSource::Level(value),
+                "--tiled-map" => level_source = LevelSource::TiledMap(value),
+                "--snapshot" => snapshot_path = Some(value),
+                "--replay" => replay_path = Some(value),
+                "--broad-phase" => {
+                    broad_phase_kind =
+                        BroadPhaseKind::from_name(&value).ok_or_else(|| {
+                            format!(
+                                "expected one of quad-tree, grid or sweep-and-prune \
+                                 after --broad-phase, got {:?}",
+                                value
+                            )
+                        })?
+                }
+                "--steps" => {
+                    headless.steps = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("expected a number after {}", arg))?,
+                    )
+                }
+                "--input" => headless.script = Some(InputScript::parse(&value)?),
+                "--dump" => headless.dump_path = Some(value),
+                other => return Err(format!("unexpected argument {:?}", other)),
+            }
+        }
+        if snapshot_path.is_some() && replay_path.is_some() {
+            // Recordings are checked against a run from the start of the level.
+            return Err("--snapshot can't be used with --replay".to_string());
+        }
+        Ok(Self {
+            level_source,
+            snapshot_path,
+            replay_path,
+            broad_phase_kind,
+            headless,
+        })
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let level = args
+        .level_source
+        .load()
+        .map_err(|error| format!("failed to load level: {}", error))?;
+    let recording = match args.replay_path {
+        Some(ref path) => {
+            let recording = Recording::load(path)
+                .map_err(|error| format!("failed to load recording: {}", error))?;
+            recording
+                .check_level(&LevelIdentity::new(args.level_source.path(), &level))
+                .map_err(|message| format!("{}: {}", path, message))?;
+            Some(recording)
+        }
+        None => None,
+    };
+    let mut game_state = level.build(args.broad_phase_kind);
+    if let Some(ref path) = args.snapshot_path {
+        let snapshot = Snapshot::load(path)
+            .map_err(|error| format!("failed to load snapshot: {}", error))?;
+        game_state.restore(&snapshot);
+    }
+    simulate_and_report(&mut game_state, &args.headless, recording.as_ref())
+}
+
+fn main() {
+    let result = Args::parse().and_then(|args| run(&args));
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use platform_game_experiment::level::Level;
+
+    const DEMO: &str = include_str!("../../levels/demo.json");
+
+    #[test]
+    fn scripted_input_moves_player() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let player = game_state.player_id().unwrap();
+        let start = game_state.common(player).unwrap().top_left;
+        let args = HeadlessArgs {
+            steps: Some(20),
+            script: Some(InputScript::parse("right*10").unwrap()),
+            dump_path: None,
+        };
+        simulate(&mut game_state, &args, None).unwrap();
+        let end = game_state.common(player).unwrap().top_left;
+        assert!(end.x > start.x);
+        assert!(game_state.collision_stats().movements > 0);
+        let report = report(&game_state);
+        assert!(report.contains("player"), "{}", report);
+        assert!(report.contains("broad phase queries: "), "{}", report);
+    }
+}
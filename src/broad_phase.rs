@@ -0,0 +1,259 @@
+use aabb::Aabb;
+use arith::Scalar;
+use cgmath::Vector2;
+use loose_quad_tree::LooseQuadTree;
+use sweep_and_prune::SweepAndPrune;
+use uniform_grid::UniformGrid;
+
+pub trait BroadPhase<T, N: Scalar> {
+    fn clear(&mut self);
+    fn insert(&mut self, aabb: Aabb<N>, t: T);
+    /// Removes the first item inserted with `aabb` for which `predicate` returns
+    /// true.
+    fn remove<P: FnMut(&T) -> bool>(&mut self, aabb: &Aabb<N>, predicate: P)
+        -> Option<T>;
+    /// Moves the first item inserted with `old_aabb` for which `predicate` returns
+    /// true to `new_aabb`, applying `f` to it on the way. Returns false if no such
+    /// item was found.
+    fn update<P, F>(
+        &mut self,
+        old_aabb: &Aabb<N>,
+        new_aabb: Aabb<N>,
+        predicate: P,
+        f: F,
+    ) -> bool
+    where
+        P: FnMut(&T) -> bool,
+        F: FnOnce(&mut T),
+    {
+        match self.remove(old_aabb, predicate) {
+            Some(mut t) => {
+                f(&mut t);
+                self.insert(new_aabb, t);
+                true
+            }
+            None => false,
+        }
+    }
+    fn for_each_intersection<F: FnMut(&Aabb<N>, &T)>(&self, aabb: &Aabb<N>, f: F);
+    /// Calls `f` exactly once for each unordered pair of items whose aabbs intersect.
+    fn for_each_overlapping_pair<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(&self, f: F);
+}
+
+impl<T, N: Scalar> BroadPhase<T, N> for LooseQuadTree<T, N> {
+    fn clear(&mut self) {
+        LooseQuadTree::clear(self);
+    }
+    fn insert(&mut self, aabb: Aabb<N>, t: T) {
+        LooseQuadTree::insert(self, aabb, t);
+    }
+    fn remove<P: FnMut(&T) -> bool>(
+        &mut self,
+        aabb: &Aabb<N>,
+        predicate: P,
+    ) -> Option<T> {
+        LooseQuadTree::remove(self, aabb, predicate)
+    }
+    fn for_each_intersection<F: FnMut(&Aabb<N>, &T)>(&self, aabb: &Aabb<N>, f: F) {
+        LooseQuadTree::for_each_intersection(self, aabb, f);
+    }
+    fn for_each_overlapping_pair<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(&self, f: F) {
+        LooseQuadTree::for_each_overlapping_pair(self, f);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BroadPhaseKind {
+    LooseQuadTree,
+    UniformGrid { cell_size: f32 },
+    SweepAndPrune,
+}
+
+impl BroadPhaseKind {
+    /// Parses the names used on the command line.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "quad-tree" => Some(BroadPhaseKind::LooseQuadTree),
+            "grid" => Some(BroadPhaseKind::UniformGrid { cell_size: 64. }),
+            "sweep-and-prune" => Some(BroadPhaseKind::SweepAndPrune),
+            _ => None,
+        }
+    }
+}
+
+impl Default for BroadPhaseKind {
+    fn default() -> Self {
+        BroadPhaseKind::LooseQuadTree
+    }
+}
+
+/// A broad phase whose backend is chosen at runtime.
+#[derive(Debug, Clone)]
+pub enum AnyBroadPhase<T, N> {
+    LooseQuadTree(LooseQuadTree<T, N>),
+    UniformGrid(UniformGrid<T, N>),
+    SweepAndPrune(SweepAndPrune<T, N>),
+}
+
+impl<T, N: Scalar> AnyBroadPhase<T, N> {
+    pub fn new(kind: BroadPhaseKind, size_hint: Vector2<N>) -> Self {
+        match kind {
+            BroadPhaseKind::LooseQuadTree => {
+                AnyBroadPhase::LooseQuadTree(LooseQuadTree::new(size_hint))
+            }
+            BroadPhaseKind::UniformGrid { cell_size } => {
+                let cell_size = N::from(cell_size).expect("cell size out of range");
+                AnyBroadPhase::UniformGrid(UniformGrid::new(cell_size))
+            }
+            BroadPhaseKind::SweepAndPrune => {
+                AnyBroadPhase::SweepAndPrune(SweepAndPrune::new())
+            }
+        }
+    }
+}
+
+impl<T, N: Scalar> BroadPhase<T, N> for AnyBroadPhase<T, N> {
+    fn clear(&mut self) {
+        match self {
+            &mut AnyBroadPhase::LooseQuadTree(ref mut b) => b.clear(),
+            &mut AnyBroadPhase::UniformGrid(ref mut b) => b.clear(),
+            &mut AnyBroadPhase::SweepAndPrune(ref mut b) => b.clear(),
+        }
+    }
+    fn insert(&mut self, aabb: Aabb<N>, t: T) {
+        match self {
+            &mut AnyBroadPhase::LooseQuadTree(ref mut b) => b.insert(aabb, t),
+            &mut AnyBroadPhase::UniformGrid(ref mut b) => b.insert(aabb, t),
+            &mut AnyBroadPhase::SweepAndPrune(ref mut b) => b.insert(aabb, t),
+        }
+    }
+    fn remove<P: FnMut(&T) -> bool>(
+        &mut self,
+        aabb: &Aabb<N>,
+        predicate: P,
+    ) -> Option<T> {
+        match self {
+            &mut AnyBroadPhase::LooseQuadTree(ref mut b) => b.remove(aabb, predicate),
+            &mut AnyBroadPhase::UniformGrid(ref mut b) => b.remove(aabb, predicate),
+            &mut AnyBroadPhase::SweepAndPrune(ref mut b) => b.remove(aabb, predicate),
+        }
+    }
+    fn for_each_intersection<F: FnMut(&Aabb<N>, &T)>(&self, aabb: &Aabb<N>, f: F) {
+        match self {
+            &AnyBroadPhase::LooseQuadTree(ref b) => b.for_each_intersection(aabb, f),
+            &AnyBroadPhase::UniformGrid(ref b) => b.for_each_intersection(aabb, f),
+            &AnyBroadPhase::SweepAndPrune(ref b) => b.for_each_intersection(aabb, f),
+        }
+    }
+    fn for_each_overlapping_pair<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(&self, f: F) {
+        match self {
+            &AnyBroadPhase::LooseQuadTree(ref b) => b.for_each_overlapping_pair(f),
+            &AnyBroadPhase::UniformGrid(ref b) => b.for_each_overlapping_pair(f),
+            &AnyBroadPhase::SweepAndPrune(ref b) => b.for_each_overlapping_pair(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::vec2;
+
+    const KINDS: [BroadPhaseKind; 3] = [
+        BroadPhaseKind::LooseQuadTree,
+        BroadPhaseKind::UniformGrid { cell_size: 32. },
+        BroadPhaseKind::SweepAndPrune,
+    ];
+
+    fn aabbs() -> Vec<Aabb<f32>> {
+        let mut aabbs = Vec::new();
+        for i in 0..16 {
+            for j in 0..16 {
+                let size = vec2(
+                    3. + ((i * 7 + j * 3) % 40) as f32,
+                    2. + ((i + j * 5) % 90) as f32,
+                );
+                aabbs.push(Aabb::new(vec2(i as f32 * 29., j as f32 * 37.), size));
+            }
+        }
+        aabbs
+    }
+
+    fn populated(kind: BroadPhaseKind, aabbs: &[Aabb<f32>]) -> AnyBroadPhase<usize, f32> {
+        let mut broad_phase = AnyBroadPhase::new(kind, vec2(480., 640.));
+        for (i, aabb) in aabbs.iter().enumerate() {
+            broad_phase.insert(*aabb, i);
+        }
+        broad_phase
+    }
+
+    fn query(broad_phase: &AnyBroadPhase<usize, f32>, aabb: &Aabb<f32>) -> Vec<usize> {
+        let mut found = Vec::new();
+        broad_phase.for_each_intersection(aabb, |_, &i| found.push(i));
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn queries_match_brute_force() {
+        let aabbs = aabbs();
+        let queries = [
+            Aabb::new(vec2(0., 0.), vec2(10., 10.)),
+            Aabb::new(vec2(100., 50.), vec2(200., 30.)),
+            Aabb::new(vec2(-50., 300.), vec2(1000., 2.)),
+            Aabb::new(vec2(250., 250.), vec2(0., 0.)),
+        ];
+        for &kind in KINDS.iter() {
+            let broad_phase = populated(kind, &aabbs);
+            for q in queries.iter() {
+                let expected = (0..aabbs.len())
+                    .filter(|&i| aabbs[i].is_intersecting(q))
+                    .collect::<Vec<_>>();
+                assert_eq!(query(&broad_phase, q), expected, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_pairs_match_brute_force() {
+        let aabbs = aabbs();
+        let mut expected = Vec::new();
+        for i in 0..aabbs.len() {
+            for j in (i + 1)..aabbs.len() {
+                if aabbs[i].is_intersecting(&aabbs[j]) {
+                    expected.push((i, j));
+                }
+            }
+        }
+        for &kind in KINDS.iter() {
+            let broad_phase = populated(kind, &aabbs);
+            let mut pairs = Vec::new();
+            broad_phase.for_each_overlapping_pair(|_, &a, _, &b| {
+                pairs.push((a.min(b), a.max(b)))
+            });
+            pairs.sort();
+            assert_eq!(pairs, expected, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn remove_and_update() {
+        let aabbs = aabbs();
+        let everything = Aabb::new(vec2(-1000., -1000.), vec2(3000., 3000.));
+        for &kind in KINDS.iter() {
+            let mut broad_phase = populated(kind, &aabbs);
+            for i in (0..aabbs.len()).filter(|i| i % 2 == 0) {
+                assert_eq!(broad_phase.remove(&aabbs[i], |&j| j == i), Some(i));
+            }
+            assert_eq!(broad_phase.remove(&aabbs[0], |&j| j == 0), None);
+            let expected = (0..aabbs.len()).filter(|i| i % 2 == 1).collect::<Vec<_>>();
+            assert_eq!(query(&broad_phase, &everything), expected, "{:?}", kind);
+
+            let moved_to = Aabb::new(vec2(470., 630.), vec2(4., 4.));
+            assert!(!query(&broad_phase, &moved_to).contains(&1));
+            assert!(broad_phase.update(&aabbs[1], moved_to, |&j| j == 1, |_| ()));
+            assert!(query(&broad_phase, &moved_to).contains(&1), "{:?}", kind);
+            assert!(!query(&broad_phase, &aabbs[1]).contains(&1), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use cgmath::{vec2, InnerSpace, Vector2};
+use game::{EntityId, GameState, System};
+use shape::Shape;
+
+/// Steps a hitbox lasts for unless it's given another lifetime.
+const DEFAULT_HITBOX_STEPS: u32 = 10;
+const DEFAULT_KNOCKBACK: [f32; 2] = [6., -4.];
+/// Knockback is multiplied by this after each step.
+const KNOCKBACK_DECAY: f32 = 0.7;
+/// Knockback shorter than this stops.
+const MIN_KNOCKBACK: f32 = 0.1;
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+fn is_still(knockback: &[f32; 2]) -> bool {
+    knockback[0] == 0. && knockback[1] == 0.
+}
+
+/// Hitboxes only hurt entities on other teams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Team {
+    Player,
+    Enemy,
+}
+
+/// An entity with health can be hurt by hitboxes of other teams which overlap its
+/// bounding box, which is its hurtbox.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Health {
+    pub team: Team,
+    pub current: u32,
+    pub max: u32,
+    /// Steps the entity can't be hurt for after being hurt.
+    #[serde(default)]
+    pub invulnerability: u32,
+    /// Steps of invulnerability left.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub invulnerable_for: u32,
+    /// How far the entity will be pushed in the next step. Knockback is stopped by
+    /// static entities, and dies away over a few steps.
+    #[serde(default, skip_serializing_if = "is_still")]
+    pub knockback: [f32; 2],
+}
+
+impl Health {
+    pub fn new(team: Team, max: u32) -> Self {
+        Self {
+            team,
+            current: max,
+            max,
+            invulnerability: 0,
+            invulnerable_for: 0,
+            knockback: [0., 0.],
+        }
+    }
+
+    pub fn with_invulnerability(self, invulnerability: u32) -> Self {
+        Self {
+            invulnerability,
+            ..self
+        }
+    }
+}
+
+/// An attack, which hurts entities overlapping it for the steps it lasts. Each
+/// entity is only hurt once by each hitbox.
+#[derive(Debug, Clone)]
+pub struct Hitbox {
+    pub team: Team,
+    /// The entity making the attack, which the hitbox moves with. The hitbox is
+    /// removed if its owner is.
+    pub owner: Option<EntityId>,
+    /// Relative to the top left of the owner, or of the world if there's no owner.
+    pub offset: Vector2<f32>,
+    pub shape: Shape<f32>,
+    pub damage: u32,
+    /// Knockback given to entities hurt. Its horizontal part pushes them away
+    /// from the centre of the hitbox.
+    pub knockback: Vector2<f32>,
+    /// Steps left before the hitbox is removed.
+    pub steps: u32,
+    /// Entities already hurt by the hitbox.
+    pub hit: Vec<EntityId>,
+}
+
+impl Hitbox {
+    pub fn new(team: Team, offset: Vector2<f32>, shape: Shape<f32>, damage: u32) -> Self {
+        Self {
+            team,
+            owner: None,
+            offset,
+            shape,
+            damage,
+            knockback: DEFAULT_KNOCKBACK.into(),
+            steps: DEFAULT_HITBOX_STEPS,
+            hit: Vec::new(),
+        }
+    }
+
+    pub fn with_owner(self, owner: EntityId) -> Self {
+        Self {
+            owner: Some(owner),
+            ..self
+        }
+    }
+
+    pub fn with_knockback(self, knockback: Vector2<f32>) -> Self {
+        Self { knockback, ..self }
+    }
+
+    pub fn with_steps(self, steps: u32) -> Self {
+        Self { steps, ..self }
+    }
+
+    /// Where the hitbox is, or `None` if its owner has no shape.
+    pub fn top_left(&self, game_state: &GameState) -> Option<Vector2<f32>> {
+        match self.owner {
+            Some(owner) => game_state
+                .common(owner)
+                .map(|common| common.top_left + self.offset),
+            None => Some(self.offset),
+        }
+    }
+}
+
+/// Adds a hitbox to the game, as an entity with no shape of its own.
+pub fn spawn_hitbox(game_state: &mut GameState, hitbox: Hitbox) -> EntityId {
+    let id = game_state.add_entity();
+    game_state.components_mut().insert(id, hitbox);
+    id
+}
+
+/// An entity being hurt by a hitbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamageEvent {
+    pub hitbox: EntityId,
+    /// The owner of the hitbox, if it has one.
+    pub attacker: Option<EntityId>,
+    pub target: EntityId,
+    pub damage: u32,
+    /// The target's health afterwards.
+    pub health: u32,
+}
+
+fn ids_with<T: 'static>(game_state: &GameState) -> Vec<EntityId> {
+    let mut ids = Vec::new();
+    game_state.components().for_each(|id, _: &T| ids.push(id));
+    ids.sort_by_key(|id| id.index());
+    ids
+}
+
+/// Hurts entities overlapped by hitboxes, moves entities which have been knocked
+/// back, and removes hitboxes which have run out. Entities other than the player
+/// are despawned when their health reaches zero. Everything is done in order of
+/// entity id, so that runs are deterministic.
+pub struct CombatSystem;
+
+impl CombatSystem {
+    fn wear_off_invulnerability(game_state: &mut GameState) {
+        game_state
+            .components_mut()
+            .for_each_mut(|_, health: &mut Health| {
+                health.invulnerable_for = health.invulnerable_for.saturating_sub(1);
+            });
+    }
+
+    /// The entities `hitbox` can hurt now, in order of id.
+    fn targets(
+        game_state: &GameState,
+        hitbox: &Hitbox,
+        top_left: Vector2<f32>,
+    ) -> Vec<EntityId> {
+        let mut targets = Vec::new();
+        game_state.for_each_entity_in(&hitbox.shape.aabb(top_left), |id| {
+            let can_hurt = game_state
+                .components()
+                .get::<Health>(id)
+                .map_or(false, |health| {
+                    health.team != hitbox.team && health.invulnerable_for == 0
+                });
+            if can_hurt && Some(id) != hitbox.owner && !hitbox.hit.contains(&id) {
+                targets.push(id);
+            }
+        });
+        targets.sort_by_key(|id| id.index());
+        targets.dedup();
+        targets
+            .into_iter()
+            .filter(|&id| {
+                game_state.common(id).map_or(false, |common| {
+                    let aabb = common.shape.aabb(common.top_left);
+                    hitbox.shape.overlaps_aabb(top_left, &aabb)
+                })
+            })
+            .collect()
+    }
+
+    fn hurt(
+        game_state: &mut GameState,
+        id: EntityId,
+        hitbox: &Hitbox,
+        top_left: Vector2<f32>,
+    ) -> u32 {
+        let centre = hitbox.shape.aabb(top_left).centre();
+        let direction = match game_state.common(id) {
+            Some(common) if common.shape.aabb(common.top_left).centre().x < centre.x => {
+                -1.
+            }
+            _ => 1.,
+        };
+        let knockback = vec2(hitbox.knockback.x.abs() * direction, hitbox.knockback.y);
+        let health = game_state
+            .components_mut()
+            .get_mut::<Health>(id)
+            .expect("target has no health");
+        health.current = health.current.saturating_sub(hitbox.damage);
+        health.invulnerable_for = health.invulnerability;
+        health.knockback = knockback.into();
+        health.current
+    }
+
+    fn resolve_hits(game_state: &mut GameState) {
+        for hitbox_id in ids_with::<Hitbox>(game_state) {
+            let mut hitbox = match game_state.components().get::<Hitbox>(hitbox_id) {
+                Some(hitbox) => hitbox.clone(),
+                None => continue,
+            };
+            let top_left = match hitbox.top_left(game_state) {
+                Some(top_left) if hitbox.steps > 0 => top_left,
+                _ => continue,
+            };
+            for target in Self::targets(game_state, &hitbox, top_left) {
+                let health = Self::hurt(game_state, target, &hitbox, top_left);
+                hitbox.hit.push(target);
+                game_state.report_damage(DamageEvent {
+                    hitbox: hitbox_id,
+                    attacker: hitbox.owner,
+                    target,
+                    damage: hitbox.damage,
+                    health,
+                });
+            }
+            game_state.components_mut().insert(hitbox_id, hitbox);
+        }
+    }
+
+    fn apply_knockback(game_state: &mut GameState) {
+        for id in ids_with::<Health>(game_state) {
+            let knockback = match game_state.components().get::<Health>(id) {
+                Some(health) if !is_still(&health.knockback) => health.knockback,
+                _ => continue,
+            };
+            let movement = Vector2::from(knockback);
+            if !game_state.is_static(id) {
+                let top_left = game_state.top_left_after_movement(id, movement);
+                if let Some(top_left) = top_left {
+                    game_state.set_top_left(id, top_left);
+                }
+            }
+            let decayed = movement * KNOCKBACK_DECAY;
+            if let Some(health) = game_state.components_mut().get_mut::<Health>(id) {
+                health.knockback = if decayed.magnitude() < MIN_KNOCKBACK {
+                    [0., 0.]
+                } else {
+                    decayed.into()
+                };
+            }
+        }
+    }
+
+    fn remove_expired(game_state: &mut GameState) {
+        for id in ids_with::<Hitbox>(game_state) {
+            let expired = {
+                let hitbox = game_state
+                    .components_mut()
+                    .get_mut::<Hitbox>(id)
+                    .expect("hitbox disappeared");
+                hitbox.steps = hitbox.steps.saturating_sub(1);
+                hitbox.steps == 0
+            };
+            let orphaned = game_state
+                .components()
+                .get::<Hitbox>(id)
+                .and_then(|hitbox| hitbox.owner)
+                .map_or(false, |owner| !game_state.is_alive(owner));
+            if expired || orphaned {
+                game_state.despawn(id);
+            }
+        }
+        let player_id = game_state.player_id();
+        for id in ids_with::<Health>(game_state) {
+            let dead = game_state
+                .components()
+                .get::<Health>(id)
+                .map_or(false, |health| health.current == 0);
+            if dead && Some(id) != player_id {
+                game_state.despawn(id);
+            }
+        }
+    }
+}
+
+impl System for CombatSystem {
+    fn run(&mut self, game_state: &mut GameState) {
+        Self::wear_off_invulnerability(game_state);
+        Self::resolve_hits(game_state);
+        Self::apply_knockback(game_state);
+        Self::remove_expired(game_state);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shape::AxisAlignedRect;
+    use test_util::rect;
+
+    /// The player with an enemy to its right, against a wall.
+    fn game_state() -> (GameState, EntityId, EntityId) {
+        let mut game_state = GameState::new(vec2(200., 100.), Default::default());
+        game_state.add_system(Box::new(CombatSystem));
+        game_state.add_static_solid(rect(100., 0., 10., 100.));
+        let player = game_state.add_player(rect(0., 50., 16., 16.));
+        let enemy = game_state.add_mover(rect(40., 50., 16., 16.), vec2(0., 0.));
+        game_state
+            .components_mut()
+            .insert(player, Health::new(Team::Player, 3));
+        game_state
+            .components_mut()
+            .insert(enemy, Health::new(Team::Enemy, 2).with_invulnerability(5));
+        (game_state, player, enemy)
+    }
+
+    fn health(game_state: &GameState, id: EntityId) -> Option<u32> {
+        game_state
+            .components()
+            .get::<Health>(id)
+            .map(|health| health.current)
+    }
+
+    #[test]
+    fn hitboxes_hurt_other_teams_once() {
+        let (mut game_state, player, enemy) = game_state();
+        // Overlaps both the player and the enemy, so only the enemy is hurt.
+        let hitbox = Hitbox::new(
+            Team::Player,
+            vec2(10., 0.),
+            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(40., 16.))),
+            1,
+        )
+        .with_owner(player)
+        .with_knockback(vec2(4., 0.))
+        .with_steps(3);
+        let hitbox = spawn_hitbox(&mut game_state, hitbox);
+        game_state.update(&Default::default());
+        assert_eq!(
+            game_state.damage_events(),
+            &[DamageEvent {
+                hitbox,
+                attacker: Some(player),
+                target: enemy,
+                damage: 1,
+                health: 1,
+            }]
+        );
+        assert_eq!(health(&game_state, player), Some(3));
+        assert_eq!(health(&game_state, enemy), Some(1));
+        // Knocked away from the hitbox's centre.
+        assert_eq!(game_state.common(enemy).unwrap().top_left, vec2(44., 50.));
+        for _ in 0..10 {
+            game_state.update(&Default::default());
+            assert!(game_state.damage_events().is_empty());
+        }
+        assert!(!game_state.is_alive(hitbox));
+        let x = game_state.common(enemy).unwrap().top_left.x;
+        assert!(x > 50. && x < 60., "{}", x);
+    }
+
+    #[test]
+    fn invulnerability_and_death() {
+        let (mut game_state, _, enemy) = game_state();
+        let attack = |game_state: &mut GameState| {
+            let hitbox = Hitbox::new(
+                Team::Player,
+                vec2(30., 40.),
+                Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(60., 40.))),
+                1,
+            )
+            .with_knockback(vec2(0., 0.))
+            .with_steps(1);
+            spawn_hitbox(game_state, hitbox);
+            game_state.update(&Default::default());
+            game_state.damage_events().len()
+        };
+        assert_eq!(attack(&mut game_state), 1);
+        // Invulnerable for 5 steps, including the one it was hurt in.
+        for _ in 0..4 {
+            assert_eq!(attack(&mut game_state), 0);
+        }
+        assert_eq!(health(&game_state, enemy), Some(1));
+        assert_eq!(attack(&mut game_state), 1);
+        assert!(!game_state.is_alive(enemy));
+    }
+}
@@ -0,0 +1,232 @@
+use fnv::FnvHashMap;
+use game::EntityId;
+use std::any::{Any, TypeId};
+
+type Table<T> = FnvHashMap<EntityId, T>;
+
+/// Operations on a table which don't need to know its component type.
+trait AnyTable {
+    fn remove_entity(&mut self, id: EntityId);
+    fn clear(&mut self);
+    fn for_each_entity(&self, f: &mut dyn FnMut(EntityId));
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> AnyTable for Table<T> {
+    fn remove_entity(&mut self, id: EntityId) {
+        self.remove(&id);
+    }
+    fn clear(&mut self) {
+        FnvHashMap::clear(self);
+    }
+    fn for_each_entity(&self, f: &mut dyn FnMut(EntityId)) {
+        for &id in self.keys() {
+            f(id);
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Per-entity data, stored in one table per component type.
+#[derive(Default)]
+pub struct ComponentStore {
+    tables: FnvHashMap<TypeId, Box<dyn AnyTable>>,
+}
+
+impl ComponentStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn table<T: 'static>(&self) -> Option<&Table<T>> {
+        self.tables
+            .get(&TypeId::of::<T>())
+            .and_then(|table| table.as_any().downcast_ref())
+    }
+
+    fn table_mut<T: 'static>(&mut self) -> Option<&mut Table<T>> {
+        self.tables
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|table| table.as_any_mut().downcast_mut())
+    }
+
+    /// Sets the `T` component of an entity, returning the previous value if any.
+    pub fn insert<T: 'static>(&mut self, id: EntityId, component: T) -> Option<T> {
+        self.tables
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Table::<T>::default()))
+            .as_any_mut()
+            .downcast_mut::<Table<T>>()
+            .expect("component table has wrong type")
+            .insert(id, component)
+    }
+
+    pub fn get<T: 'static>(&self, id: EntityId) -> Option<&T> {
+        self.table().and_then(|table| table.get(&id))
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, id: EntityId) -> Option<&mut T> {
+        self.table_mut().and_then(|table| table.get_mut(&id))
+    }
+
+    pub fn contains<T: 'static>(&self, id: EntityId) -> bool {
+        self.get::<T>(id).is_some()
+    }
+
+    pub fn remove<T: 'static>(&mut self, id: EntityId) -> Option<T> {
+        self.table_mut().and_then(|table| table.remove(&id))
+    }
+
+    /// Removes every component of an entity.
+    pub fn remove_entity(&mut self, id: EntityId) {
+        for table in self.tables.values_mut() {
+            table.remove_entity(id);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for table in self.tables.values_mut() {
+            table.clear();
+        }
+    }
+
+    /// Calls `f` for each entity with any component, once per component it has.
+    pub fn for_each_entity<F: FnMut(EntityId)>(&self, mut f: F) {
+        for table in self.tables.values() {
+            table.for_each_entity(&mut f);
+        }
+    }
+
+    pub fn for_each<T: 'static, F: FnMut(EntityId, &T)>(&self, mut f: F) {
+        if let Some(table) = self.table::<T>() {
+            for (&id, component) in table.iter() {
+                f(id, component);
+            }
+        }
+    }
+
+    pub fn for_each_mut<T: 'static, F: FnMut(EntityId, &mut T)>(&mut self, mut f: F) {
+        if let Some(table) = self.table_mut::<T>() {
+            for (&id, component) in table.iter_mut() {
+                f(id, component);
+            }
+        }
+    }
+
+    /// Calls `f` for each entity with both an `A` and a `B` component.
+    pub fn join<A, B, F>(&self, mut f: F)
+    where
+        A: 'static,
+        B: 'static,
+        F: FnMut(EntityId, &A, &B),
+    {
+        if let (Some(a), Some(b)) = (self.table::<A>(), self.table::<B>()) {
+            for (&id, a) in a.iter() {
+                if let Some(b) = b.get(&id) {
+                    f(id, a, b);
+                }
+            }
+        }
+    }
+
+    /// Like `join`, but with mutable access to the `A` component. `A` and `B` must
+    /// be different types.
+    pub fn join_mut<A, B, F>(&mut self, mut f: F)
+    where
+        A: 'static,
+        B: 'static,
+        F: FnMut(EntityId, &mut A, &B),
+    {
+        assert!(
+            TypeId::of::<A>() != TypeId::of::<B>(),
+            "cannot join a component with itself"
+        );
+        // The `A` table is taken out of the map while iterating, so that the `B`
+        // table can be borrowed at the same time.
+        let mut a = match self.tables.remove(&TypeId::of::<A>()) {
+            Some(a) => a,
+            None => return,
+        };
+        if let Some(b) = self.table::<B>() {
+            let a = a
+                .as_any_mut()
+                .downcast_mut::<Table<A>>()
+                .expect("component table has wrong type");
+            for (&id, a) in a.iter_mut() {
+                if let Some(b) = b.get(&id) {
+                    f(id, a, b);
+                }
+            }
+        }
+        self.tables.insert(TypeId::of::<A>(), a);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::vec2;
+    use game::{EntityCommon, GameState};
+    use shape::{AxisAlignedRect, Shape};
+
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Debug, PartialEq)]
+    struct Poisoned;
+
+    fn ids(n: usize) -> Vec<EntityId> {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        (0..n)
+            .map(|_| {
+                game_state.add_mover(
+                    EntityCommon::new(
+                        vec2(0., 0.),
+                        Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(1., 1.))),
+                        [1., 1., 1.],
+                    ),
+                    vec2(0., 0.),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let ids = ids(2);
+        let mut store = ComponentStore::new();
+        assert_eq!(store.insert(ids[0], Health(10)), None);
+        assert_eq!(store.insert(ids[0], Health(5)), Some(Health(10)));
+        store.insert(ids[1], Poisoned);
+        assert_eq!(store.get::<Health>(ids[0]), Some(&Health(5)));
+        assert_eq!(store.get::<Health>(ids[1]), None);
+        assert!(store.contains::<Poisoned>(ids[1]));
+        store.get_mut::<Health>(ids[0]).unwrap().0 += 1;
+        assert_eq!(store.remove::<Health>(ids[0]), Some(Health(6)));
+        assert_eq!(store.get::<Health>(ids[0]), None);
+        store.remove_entity(ids[1]);
+        assert!(!store.contains::<Poisoned>(ids[1]));
+    }
+
+    #[test]
+    fn join_visits_entities_with_both_components() {
+        let ids = ids(3);
+        let mut store = ComponentStore::new();
+        store.insert(ids[0], Health(10));
+        store.insert(ids[1], Health(10));
+        store.insert(ids[1], Poisoned);
+        store.insert(ids[2], Poisoned);
+        store.join_mut::<Health, Poisoned, _>(|_, health, _| health.0 -= 1);
+        assert_eq!(store.get::<Health>(ids[0]), Some(&Health(10)));
+        assert_eq!(store.get::<Health>(ids[1]), Some(&Health(9)));
+        let mut joined = Vec::new();
+        store.join::<Poisoned, Health, _>(|id, _, _| joined.push(id));
+        assert_eq!(joined, vec![ids[1]]);
+    }
+}
@@ -0,0 +1,256 @@
+use aabb::Aabb;
+use arith::{self, Scalar};
+use cgmath::{vec2, Vector2};
+use line_segment::LineSegment;
+use shape::Collide;
+
+/// The outward normal of the edge from `start` to `end`. With y pointing down, this
+/// points to the left of the edge, so the solid side of a chain is on its right.
+fn normal<N: Scalar>(start: Vector2<N>, end: Vector2<N>) -> Vector2<N> {
+    let v = end - start;
+    vec2(v.y, -v.x)
+}
+
+/// A connected sequence of one-sided edges. Edges only collide with things
+/// approaching from the side their normal faces, and vertices where the chain bends
+/// inwards or carries straight on are ignored. This prevents entities catching on
+/// the seams between edges, as they would between adjacent rects.
+///
+/// An open chain can be given ghost vertices, which are the neighbouring vertices
+/// of an adjacent chain. They aren't collided with, but let the chain treat its end
+/// vertices as internal, so that chains can be joined without seams.
+#[derive(Debug, Clone)]
+pub struct EdgeChain<N> {
+    vertices: Vec<Vector2<N>>,
+    closed: bool,
+    previous_ghost: Option<Vector2<N>>,
+    next_ghost: Option<Vector2<N>>,
+}
+
+impl<N: Scalar> EdgeChain<N> {
+    pub fn new(vertices: Vec<Vector2<N>>) -> Self {
+        assert!(vertices.len() >= 2, "edge chain needs at least 2 vertices");
+        Self {
+            vertices,
+            closed: false,
+            previous_ghost: None,
+            next_ghost: None,
+        }
+    }
+
+    /// A chain whose last vertex joins back up with its first. It should be wound
+    /// clockwise (with y pointing down) to be solid on the inside.
+    pub fn closed(vertices: Vec<Vector2<N>>) -> Self {
+        assert!(vertices.len() >= 2, "edge chain needs at least 2 vertices");
+        Self {
+            vertices,
+            closed: true,
+            previous_ghost: None,
+            next_ghost: None,
+        }
+    }
+
+    pub fn with_ghost_vertices(
+        self,
+        previous_ghost: Option<Vector2<N>>,
+        next_ghost: Option<Vector2<N>>,
+    ) -> Self {
+        assert!(!self.closed, "closed edge chains have no ends");
+        Self {
+            previous_ghost,
+            next_ghost,
+            ..self
+        }
+    }
+
+    pub fn vertices(&self) -> &[Vector2<N>] {
+        &self.vertices
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The previous and next ghost vertices.
+    pub fn ghost_vertices(&self) -> (Option<Vector2<N>>, Option<Vector2<N>>) {
+        (self.previous_ghost, self.next_ghost)
+    }
+
+    pub fn for_each_edge<F: FnMut(LineSegment<N>)>(&self, mut f: F) {
+        for pair in self.vertices.windows(2) {
+            f(LineSegment::new(pair[0], pair[1]));
+        }
+        if self.closed {
+            f(LineSegment::new(
+                self.vertices[self.vertices.len() - 1],
+                self.vertices[0],
+            ));
+        }
+    }
+
+    fn previous(&self, index: usize) -> Option<Vector2<N>> {
+        if index > 0 {
+            Some(self.vertices[index - 1])
+        } else if self.closed {
+            self.vertices.last().cloned()
+        } else {
+            self.previous_ghost
+        }
+    }
+
+    fn next(&self, index: usize) -> Option<Vector2<N>> {
+        if index + 1 < self.vertices.len() {
+            Some(self.vertices[index + 1])
+        } else if self.closed {
+            Some(self.vertices[0])
+        } else {
+            self.next_ghost
+        }
+    }
+}
+
+impl<N: Scalar> Collide<N> for EdgeChain<N> {
+    fn aabb(&self, top_left: Vector2<N>) -> Aabb<N> {
+        let first = self.vertices[0];
+        let (min, max) = self.vertices.iter().fold((first, first), |(min, max), v| {
+            (
+                vec2(arith::min(min.x, v.x), arith::min(min.y, v.y)),
+                vec2(arith::max(max.x, v.x), arith::max(max.y, v.y)),
+            )
+        });
+        Aabb::new(min + top_left, max - min)
+    }
+    fn for_each_edge_facing<F: FnMut(LineSegment<N>)>(
+        &self,
+        direction: Vector2<N>,
+        mut f: F,
+    ) {
+        self.for_each_edge(|edge| {
+            if arith::dot(normal(edge.start, edge.end), direction) > N::zero() {
+                f(edge);
+            }
+        });
+    }
+    fn for_each_vertex_facing<F: FnMut(Vector2<N>)>(
+        &self,
+        direction: Vector2<N>,
+        mut f: F,
+    ) {
+        for (index, &vertex) in self.vertices.iter().enumerate() {
+            let previous = self.previous(index);
+            let next = self.next(index);
+            let faces = match (previous, next) {
+                (Some(previous), Some(next)) => {
+                    // Only vertices where the chain bends outwards can be hit before
+                    // the edges either side of them.
+                    arith::cross(vertex - previous, next - vertex) > N::zero()
+                        && (arith::dot(normal(previous, vertex), direction) > N::zero()
+                            || arith::dot(normal(vertex, next), direction) > N::zero())
+                }
+                // The free end of a chain can also be hit by things moving towards it
+                // along the front of its edge.
+                (Some(neighbour), None) | (None, Some(neighbour)) => {
+                    let edge_normal = if next.is_some() {
+                        normal(vertex, neighbour)
+                    } else {
+                        normal(neighbour, vertex)
+                    };
+                    arith::dot(edge_normal, direction) >= N::zero()
+                        && arith::dot(vertex - neighbour, direction) > N::zero()
+                }
+                (None, None) => false,
+            };
+            if faces {
+                f(vertex);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use shape::AxisAlignedRect;
+
+    fn mover() -> AxisAlignedRect<f32> {
+        AxisAlignedRect::new(vec2(4., 4.))
+    }
+
+    fn ground() -> EdgeChain<f32> {
+        EdgeChain::new(vec![vec2(0., 0.), vec2(10., 0.), vec2(20., 0.)])
+    }
+
+    #[test]
+    fn lands_on_the_front() {
+        let collision = mover()
+            .movement_collision_test(
+                vec2(5., -10.),
+                &ground(),
+                vec2(0., 0.),
+                vec2(0., 10.),
+            )
+            .unwrap();
+        assert!((collision.movement_vector_ratio.ratio() - 0.6).abs() < 0.01);
+        assert_eq!(collision.colliding_with.vector().y, 0.);
+    }
+
+    #[test]
+    fn passes_through_the_back() {
+        assert!(mover()
+            .movement_collision_test(
+                vec2(5., 2.),
+                &ground(),
+                vec2(0., 0.),
+                vec2(0., -10.)
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn slides_over_internal_vertices() {
+        // Resting slightly below the surface, as after a landing.
+        let position = vec2(2., -3.99);
+        let movement = vec2(15., 0.);
+        let left = AxisAlignedRect::new(vec2(10., 10.));
+        let right = AxisAlignedRect::new(vec2(10., 10.));
+        let snags_on_rect_seam = mover()
+            .movement_collision_test(position, &left, vec2(0., 0.), movement)
+            .is_some()
+            || mover()
+                .movement_collision_test(position, &right, vec2(10., 0.), movement)
+                .is_some();
+        assert!(snags_on_rect_seam);
+        assert!(mover()
+            .movement_collision_test(position, &ground(), vec2(0., 0.), movement)
+            .is_none());
+    }
+
+    #[test]
+    fn ghost_vertices_join_chains() {
+        let position = vec2(2., -3.99);
+        let movement = vec2(15., 0.);
+        let right = EdgeChain::new(vec![vec2(10., 0.), vec2(20., 0.)]);
+        assert!(mover()
+            .movement_collision_test(position, &right, vec2(0., 0.), movement)
+            .is_some());
+        let right = right.with_ghost_vertices(Some(vec2(0., 0.)), None);
+        assert!(mover()
+            .movement_collision_test(position, &right, vec2(0., 0.), movement)
+            .is_none());
+    }
+
+    #[test]
+    fn closed_chain_blocks_from_outside() {
+        let square = EdgeChain::closed(vec![
+            vec2(0., 0.),
+            vec2(10., 0.),
+            vec2(10., 10.),
+            vec2(0., 10.),
+        ]);
+        let collision = mover()
+            .movement_collision_test(vec2(-10., 3.), &square, vec2(0., 0.), vec2(10., 0.))
+            .unwrap();
+        assert!((collision.movement_vector_ratio.ratio() - 0.6).abs() < 0.01);
+        assert_eq!(collision.colliding_with.vector().x, 0.);
+    }
+}
@@ -0,0 +1,642 @@
+use cgmath::{vec2, InnerSpace, Vector2};
+use platform_game_experiment::game::{EntityId, GameState};
+use platform_game_experiment::level::{Level, LevelEntity, LevelError, LevelShape};
+use std::path::{Path, PathBuf};
+
+const PICK_RADIUS: f32 = 6.;
+const COLOUR: [f32; 3] = [1., 1., 0.];
+const SELECTED_COLOUR: [f32; 3] = [1., 0.5, 1.];
+
+fn vector(v: [f32; 2]) -> Vector2<f32> {
+    vec2(v[0], v[1])
+}
+
+fn distance_to_segment(
+    point: Vector2<f32>,
+    start: Vector2<f32>,
+    end: Vector2<f32>,
+) -> f32 {
+    let v = end - start;
+    let t = if v.magnitude2() > 0. {
+        ((point - start).dot(v) / v.magnitude2()).max(0.).min(1.)
+    } else {
+        0.
+    };
+    (start + v * t - point).magnitude()
+}
+
+fn rect_from_corners(a: Vector2<f32>, b: Vector2<f32>, min_size: f32) -> LevelEntity {
+    let top_left = vec2(a.x.min(b.x), a.y.min(b.y));
+    let size = vec2(
+        (a.x - b.x).abs().max(min_size),
+        (a.y - b.y).abs().max(min_size),
+    );
+    LevelEntity {
+        top_left: top_left.into(),
+        shape: LevelShape::Rect { size: size.into() },
+        colour: COLOUR,
+        velocity: None,
+        behaviour: None,
+        health: None,
+        script: None,
+    }
+}
+
+fn segment_from_ends(start: Vector2<f32>, end: Vector2<f32>) -> LevelEntity {
+    LevelEntity {
+        top_left: start.into(),
+        shape: LevelShape::LineSegment {
+            start: [0., 0.],
+            end: (end - start).into(),
+        },
+        colour: COLOUR,
+        velocity: None,
+        behaviour: None,
+        health: None,
+        script: None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// Press on an entity to select it, then drag to move it, or drag one of its
+    /// handles to resize it.
+    Select,
+    /// Drag from one corner of a rect to the opposite one.
+    Rect,
+    /// Drag from the start of a line segment to its end.
+    LineSegment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handle {
+    /// The bottom-right corner of a rect.
+    Corner,
+    SegmentStart,
+    SegmentEnd,
+}
+
+#[derive(Debug, Clone)]
+enum Drag {
+    Create {
+        index: usize,
+        start: Vector2<f32>,
+    },
+    Move {
+        index: usize,
+        original: LevelEntity,
+        offset: Vector2<f32>,
+    },
+    Resize {
+        index: usize,
+        original: LevelEntity,
+        handle: Handle,
+    },
+}
+
+/// A change to the level which can be undone.
+#[derive(Debug, Clone)]
+enum Edit {
+    Add {
+        index: usize,
+        entity: LevelEntity,
+    },
+    Remove {
+        index: usize,
+        entity: LevelEntity,
+    },
+    Replace {
+        index: usize,
+        before: LevelEntity,
+        after: LevelEntity,
+    },
+}
+
+impl Edit {
+    fn inverse(self) -> Self {
+        match self {
+            Edit::Add { index, entity } => Edit::Remove { index, entity },
+            Edit::Remove { index, entity } => Edit::Add { index, entity },
+            Edit::Replace {
+                index,
+                before,
+                after,
+            } => Edit::Replace {
+                index,
+                before: after,
+                after: before,
+            },
+        }
+    }
+}
+
+/// Edits the entities of a level, keeping a game state in sync with it so that
+/// changes show up immediately. Positions are in world coordinates.
+pub struct Editor {
+    level: Level,
+    path: PathBuf,
+    entity_ids: Vec<EntityId>,
+    tool: Tool,
+    grid_size: f32,
+    selected: Option<usize>,
+    drag: Option<Drag>,
+    undo_stack: Vec<Edit>,
+    redo_stack: Vec<Edit>,
+}
+
+impl Editor {
+    /// `path` is where the level is saved to.
+    pub fn new<P: AsRef<Path>>(level: Level, path: P) -> Self {
+        Self {
+            level,
+            path: path.as_ref().to_path_buf(),
+            entity_ids: Vec::new(),
+            tool: Tool::Select,
+            grid_size: 16.,
+            selected: None,
+            drag: None,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+        }
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn grid_size(&self) -> f32 {
+        self.grid_size
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+    }
+
+    /// Positions are snapped to multiples of the grid size. A size of 0 turns off
+    /// snapping.
+    pub fn set_grid_size(&mut self, grid_size: f32) {
+        self.grid_size = grid_size.max(0.);
+    }
+
+    /// Resets `game_state` to the level being edited. This must be called before
+    /// editing, and whenever `game_state` has been changed by anything else.
+    pub fn attach(&mut self, game_state: &mut GameState) {
+        self.drag = None;
+        self.entity_ids = self.level.populate(game_state);
+        if let Some(index) = self.selected {
+            self.highlight(index, game_state);
+        }
+    }
+
+    /// Replaces the level being edited with `level`, which has been changed outside
+    /// the editor, keeping the player where it is. This discards the undo history.
+    pub fn reload(&mut self, level: Level, game_state: &mut GameState) {
+        self.level = level;
+        self.selected = None;
+        self.drag = None;
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.entity_ids = self.level.reload(game_state);
+    }
+
+    pub fn save(&self) -> Result<(), LevelError> {
+        self.level.save(&self.path)
+    }
+
+    fn snap(&self, point: Vector2<f32>) -> Vector2<f32> {
+        if self.grid_size > 0. {
+            let snap = |x: f32| (x / self.grid_size).round() * self.grid_size;
+            vec2(snap(point.x), snap(point.y))
+        } else {
+            point
+        }
+    }
+
+    fn min_size(&self) -> f32 {
+        if self.grid_size > 0. {
+            self.grid_size
+        } else {
+            1.
+        }
+    }
+
+    fn highlight(&self, index: usize, game_state: &mut GameState) {
+        if let Some(common) = game_state.common_mut(self.entity_ids[index]) {
+            common.colour = SELECTED_COLOUR;
+        }
+    }
+
+    fn select(&mut self, selected: Option<usize>, game_state: &mut GameState) {
+        if let Some(index) = self.selected {
+            let colour = self.level.entities[index].colour;
+            if let Some(common) = game_state.common_mut(self.entity_ids[index]) {
+                common.colour = colour;
+            }
+        }
+        self.selected = selected;
+        if let Some(index) = selected {
+            self.highlight(index, game_state);
+        }
+    }
+
+    fn insert(&mut self, index: usize, entity: LevelEntity, game_state: &mut GameState) {
+        self.entity_ids.insert(index, entity.spawn(game_state));
+        self.level.entities.insert(index, entity);
+    }
+
+    fn remove(&mut self, index: usize, game_state: &mut GameState) -> LevelEntity {
+        game_state.despawn(self.entity_ids.remove(index));
+        self.level.entities.remove(index)
+    }
+
+    fn replace(&mut self, index: usize, entity: LevelEntity, game_state: &mut GameState) {
+        game_state.despawn(self.entity_ids[index]);
+        self.entity_ids[index] = entity.spawn(game_state);
+        self.level.entities[index] = entity;
+        if self.selected == Some(index) {
+            self.highlight(index, game_state);
+        }
+    }
+
+    fn apply(&mut self, edit: &Edit, game_state: &mut GameState) {
+        self.select(None, game_state);
+        match edit {
+            &Edit::Add { index, ref entity } => {
+                self.insert(index, entity.clone(), game_state)
+            }
+            &Edit::Remove { index, .. } => {
+                self.remove(index, game_state);
+            }
+            &Edit::Replace {
+                index, ref after, ..
+            } => self.replace(index, after.clone(), game_state),
+        }
+    }
+
+    fn push(&mut self, edit: Edit) {
+        self.undo_stack.push(edit);
+        self.redo_stack.clear();
+    }
+
+    pub fn undo(&mut self, game_state: &mut GameState) {
+        if self.drag.is_some() {
+            return;
+        }
+        if let Some(edit) = self.undo_stack.pop() {
+            let inverse = edit.inverse();
+            self.apply(&inverse, game_state);
+            self.redo_stack.push(inverse.inverse());
+        }
+    }
+
+    pub fn redo(&mut self, game_state: &mut GameState) {
+        if self.drag.is_some() {
+            return;
+        }
+        if let Some(edit) = self.redo_stack.pop() {
+            self.apply(&edit, game_state);
+            self.undo_stack.push(edit);
+        }
+    }
+
+    pub fn delete_selected(&mut self, game_state: &mut GameState) {
+        if self.drag.is_some() {
+            return;
+        }
+        if let Some(index) = self.selected {
+            self.select(None, game_state);
+            let entity = self.remove(index, game_state);
+            self.push(Edit::Remove { index, entity });
+        }
+    }
+
+    /// The topmost entity under `point`, and which of its handles it's on, if any.
+    fn pick(&self, point: Vector2<f32>) -> Option<(usize, Option<Handle>)> {
+        for (index, entity) in self.level.entities.iter().enumerate().rev() {
+            let top_left = vector(entity.top_left);
+            match entity.shape {
+                LevelShape::Rect { size } => {
+                    let bottom_right = top_left + vector(size);
+                    if (point - bottom_right).magnitude() <= PICK_RADIUS {
+                        return Some((index, Some(Handle::Corner)));
+                    }
+                    if point.x >= top_left.x
+                        && point.y >= top_left.y
+                        && point.x <= bottom_right.x
+                        && point.y <= bottom_right.y
+                    {
+                        return Some((index, None));
+                    }
+                }
+                LevelShape::LineSegment { start, end } => {
+                    let start = top_left + vector(start);
+                    let end = top_left + vector(end);
+                    if (point - start).magnitude() <= PICK_RADIUS {
+                        return Some((index, Some(Handle::SegmentStart)));
+                    }
+                    if (point - end).magnitude() <= PICK_RADIUS {
+                        return Some((index, Some(Handle::SegmentEnd)));
+                    }
+                    if distance_to_segment(point, start, end) <= PICK_RADIUS {
+                        return Some((index, None));
+                    }
+                }
+                LevelShape::EdgeChain {
+                    ref vertices,
+                    closed,
+                } => {
+                    let vertices = vertices
+                        .iter()
+                        .map(|&v| top_left + vector(v))
+                        .collect::<Vec<_>>();
+                    let mut edges = vertices
+                        .windows(2)
+                        .map(|w| (w[0], w[1]))
+                        .collect::<Vec<_>>();
+                    if closed {
+                        edges.push((vertices[vertices.len() - 1], vertices[0]));
+                    }
+                    if edges
+                        .iter()
+                        .any(|&(a, b)| distance_to_segment(point, a, b) <= PICK_RADIUS)
+                    {
+                        return Some((index, None));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn mouse_press(&mut self, point: Vector2<f32>, game_state: &mut GameState) {
+        if self.drag.is_some() {
+            return;
+        }
+        match self.tool {
+            Tool::Select => {
+                let picked = self.pick(point);
+                self.select(picked.map(|(index, _)| index), game_state);
+                self.drag = picked.map(|(index, handle)| {
+                    let original = self.level.entities[index].clone();
+                    match handle {
+                        Some(handle) => Drag::Resize {
+                            index,
+                            original,
+                            handle,
+                        },
+                        None => Drag::Move {
+                            index,
+                            offset: vector(original.top_left) - point,
+                            original,
+                        },
+                    }
+                });
+            }
+            Tool::Rect | Tool::LineSegment => {
+                self.select(None, game_state);
+                let start = self.snap(point);
+                let index = self.level.entities.len();
+                let entity = if self.tool == Tool::Rect {
+                    rect_from_corners(start, start, self.min_size())
+                } else {
+                    segment_from_ends(start, start + vec2(self.min_size(), 0.))
+                };
+                self.insert(index, entity, game_state);
+                self.drag = Some(Drag::Create { index, start });
+            }
+        }
+    }
+
+    /// The entity being dragged, as it would be with the mouse at `point`.
+    fn dragged(&self, drag: &Drag, point: Vector2<f32>) -> (usize, LevelEntity) {
+        let snapped = self.snap(point);
+        match drag {
+            &Drag::Create { index, start } => {
+                let entity = if self.tool == Tool::Rect {
+                    rect_from_corners(start, snapped, self.min_size())
+                } else if snapped == start {
+                    self.level.entities[index].clone()
+                } else {
+                    segment_from_ends(start, snapped)
+                };
+                (index, entity)
+            }
+            &Drag::Move {
+                index,
+                ref original,
+                offset,
+            } => {
+                let mut entity = original.clone();
+                entity.top_left = self.snap(point + offset).into();
+                (index, entity)
+            }
+            &Drag::Resize {
+                index,
+                ref original,
+                handle,
+            } => {
+                let top_left = vector(original.top_left);
+                let mut entity = original.clone();
+                match (handle, &mut entity.shape) {
+                    (Handle::Corner, &mut LevelShape::Rect { ref mut size }) => {
+                        let min_size = self.min_size();
+                        let new_size = snapped - top_left;
+                        *size = [new_size.x.max(min_size), new_size.y.max(min_size)];
+                    }
+                    (
+                        Handle::SegmentStart,
+                        &mut LevelShape::LineSegment { ref mut start, .. },
+                    ) => {
+                        *start = (snapped - top_left).into();
+                    }
+                    (
+                        Handle::SegmentEnd,
+                        &mut LevelShape::LineSegment { ref mut end, .. },
+                    ) => {
+                        *end = (snapped - top_left).into();
+                    }
+                    _ => (),
+                }
+                (index, entity)
+            }
+        }
+    }
+
+    pub fn mouse_drag(&mut self, point: Vector2<f32>, game_state: &mut GameState) {
+        if let Some(drag) = self.drag.take() {
+            let (index, entity) = self.dragged(&drag, point);
+            if entity != self.level.entities[index] {
+                self.replace(index, entity, game_state);
+            }
+            self.drag = Some(drag);
+        }
+    }
+
+    pub fn mouse_release(&mut self, point: Vector2<f32>, game_state: &mut GameState) {
+        self.mouse_drag(point, game_state);
+        let edit = match self.drag.take() {
+            Some(Drag::Create { index, .. }) => Edit::Add {
+                index,
+                entity: self.level.entities[index].clone(),
+            },
+            Some(Drag::Move {
+                index,
+                ref original,
+                ..
+            })
+            | Some(Drag::Resize {
+                index,
+                ref original,
+                ..
+            }) => {
+                if *original == self.level.entities[index] {
+                    return;
+                }
+                Edit::Replace {
+                    index,
+                    before: original.clone(),
+                    after: self.level.entities[index].clone(),
+                }
+            }
+            None => return,
+        };
+        self.push(edit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use platform_game_experiment::broad_phase::BroadPhaseKind;
+    use std::env;
+    use std::fs;
+
+    fn empty_level() -> Level {
+        Level::parse(
+            "empty.json",
+            r#"{
+                "version": 1,
+                "world_size": [256, 256],
+                "player": { "top_left": [0, 0], "size": [16, 16], "colour": [1, 0, 0] },
+                "entities": []
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn setup() -> (Editor, GameState) {
+        let level = empty_level();
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let mut editor = Editor::new(level, env::temp_dir().join("editor_test.json"));
+        editor.attach(&mut game_state);
+        (editor, game_state)
+    }
+
+    fn drag(
+        editor: &mut Editor,
+        game_state: &mut GameState,
+        from: Vector2<f32>,
+        to: Vector2<f32>,
+    ) {
+        editor.mouse_press(from, game_state);
+        editor.mouse_drag((from + to) / 2., game_state);
+        editor.mouse_release(to, game_state);
+    }
+
+    fn rect(editor: &Editor, index: usize) -> ([f32; 2], [f32; 2]) {
+        let entity = &editor.level().entities[index];
+        match entity.shape {
+            LevelShape::Rect { size } => (entity.top_left, size),
+            ref other => panic!("expected rect, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn place_rect_snaps_to_grid_and_collides() {
+        let (mut editor, mut game_state) = setup();
+        editor.set_tool(Tool::Rect);
+        drag(&mut editor, &mut game_state, vec2(-2., 50.), vec2(97., 62.));
+        assert_eq!(rect(&editor, 0), ([0., 48.], [96., 16.]));
+        let player = game_state.player_id().unwrap();
+        let landed = game_state
+            .top_left_after_movement(player, vec2(0., 100.))
+            .unwrap();
+        assert!(landed.y < 33., "{:?}", landed);
+    }
+
+    #[test]
+    fn move_resize_and_undo_redo() {
+        let (mut editor, mut game_state) = setup();
+        editor.set_tool(Tool::Rect);
+        drag(&mut editor, &mut game_state, vec2(32., 32.), vec2(64., 64.));
+        editor.set_tool(Tool::Select);
+        drag(&mut editor, &mut game_state, vec2(40., 40.), vec2(72., 40.));
+        assert_eq!(rect(&editor, 0), ([64., 32.], [32., 32.]));
+        drag(
+            &mut editor,
+            &mut game_state,
+            vec2(96., 64.),
+            vec2(128., 96.),
+        );
+        assert_eq!(rect(&editor, 0), ([64., 32.], [64., 64.]));
+
+        editor.undo(&mut game_state);
+        assert_eq!(rect(&editor, 0), ([64., 32.], [32., 32.]));
+        editor.undo(&mut game_state);
+        assert_eq!(rect(&editor, 0), ([32., 32.], [32., 32.]));
+        editor.undo(&mut game_state);
+        assert!(editor.level().entities.is_empty());
+        editor.redo(&mut game_state);
+        editor.redo(&mut game_state);
+        assert_eq!(rect(&editor, 0), ([64., 32.], [32., 32.]));
+        // The game state only has the player and the rect.
+        assert_eq!(game_state.common_iter().count(), 2);
+    }
+
+    #[test]
+    fn delete_and_line_segments() {
+        let (mut editor, mut game_state) = setup();
+        editor.set_tool(Tool::LineSegment);
+        drag(
+            &mut editor,
+            &mut game_state,
+            vec2(0., 100.),
+            vec2(100., 130.),
+        );
+        match editor.level().entities[0].shape {
+            LevelShape::LineSegment { start, end } => {
+                assert_eq!((start, end), ([0., 0.], [96., 32.]))
+            }
+            ref other => panic!("expected line segment, got {:?}", other),
+        }
+        editor.set_tool(Tool::Select);
+        drag(
+            &mut editor,
+            &mut game_state,
+            vec2(96., 132.),
+            vec2(128., 128.),
+        );
+        editor.mouse_press(vec2(64., 116.), &mut game_state);
+        editor.mouse_release(vec2(64., 116.), &mut game_state);
+        editor.delete_selected(&mut game_state);
+        assert!(editor.level().entities.is_empty());
+        assert_eq!(game_state.common_iter().count(), 1);
+        editor.undo(&mut game_state);
+        match editor.level().entities[0].shape {
+            LevelShape::LineSegment { end, .. } => assert_eq!(end, [128., 32.]),
+            ref other => panic!("expected line segment, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn save_writes_level_file() {
+        let (mut editor, mut game_state) = setup();
+        editor.set_tool(Tool::Rect);
+        drag(&mut editor, &mut game_state, vec2(0., 0.), vec2(32., 32.));
+        editor.save().unwrap();
+        let path = env::temp_dir().join("editor_test.json");
+        let saved = Level::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.entities, editor.level().entities);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Notices when a file changes by polling its modification time, which works on
+/// every platform without needing filesystem notifications.
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    interval: Duration,
+    last_checked: Instant,
+}
+
+impl FileWatcher {
+    /// The file is checked at most once per `interval`.
+    pub fn new<P: AsRef<Path>>(path: P, interval: Duration) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self {
+            modified: modified(&path),
+            path,
+            interval,
+            last_checked: Instant::now(),
+        }
+    }
+
+    /// Returns true if the file has been modified since it was last seen.
+    pub fn poll(&mut self) -> bool {
+        if self.last_checked.elapsed() < self.interval {
+            return false;
+        }
+        self.last_checked = Instant::now();
+        let modified = modified(&self.path);
+        self.update(modified)
+    }
+
+    /// Treats the file's current contents as seen, so that changes made by the
+    /// game itself aren't reported.
+    pub fn mark_seen(&mut self) {
+        self.modified = modified(&self.path);
+    }
+
+    fn update(&mut self, modified: Option<SystemTime>) -> bool {
+        // While a file is being saved it may briefly not exist. This isn't
+        // reported, as there would be nothing to load.
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_each_change_once() {
+        let mut watcher = FileWatcher::new("does-not-exist.json", Duration::from_secs(0));
+        assert!(!watcher.poll());
+        let time = SystemTime::now();
+        assert!(watcher.update(Some(time)));
+        assert!(!watcher.update(Some(time)));
+        assert!(!watcher.update(None));
+        assert!(watcher.update(Some(time + Duration::from_secs(1))));
+    }
+}
@@ -1,15 +1,26 @@
 use aabb::Aabb;
+use behaviour::Behaviour;
 use best::BestMap;
+use broad_phase::{AnyBroadPhase, BroadPhase, BroadPhaseKind};
 use cgmath::{vec2, InnerSpace, Vector2};
-use fnv::FnvHashMap;
-use line_segment::LineSegment;
-use loose_quad_tree::LooseQuadTree;
-use shape::{AxisAlignedRect, CollisionInfo, Shape};
+use combat::{DamageEvent, Health, Hitbox};
+use component::ComponentStore;
+use fnv::{FnvHashMap, FnvHashSet};
+use script::Script;
+use shape::{CollisionInfo, Shape};
+use snapshot::{
+    AllocatorSnapshot, CommonSnapshot, EntitySnapshot, Snapshot, Solidity,
+    SNAPSHOT_FORMAT_VERSION,
+};
+use std::collections::HashSet;
+use std::mem;
+use trigger::Trigger;
 
 fn clamp(value: f32, min: f32, max: f32) -> f32 {
     value.max(min).min(max)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct InputModel {
     left: f32,
     right: f32,
@@ -57,41 +68,105 @@ impl InputModel {
     }
 }
 
-pub type EntityId = u32;
+/// Identifies an entity. Indices are reused after an entity is despawned, but the
+/// generation is bumped each time so stale ids never refer to the new entity.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct EntityId {
+    index: u32,
+    generation: u32,
+}
+
+impl EntityId {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Velocity(pub Vector2<f32>);
 
 #[derive(Clone)]
 pub struct EntityCommon {
     pub top_left: Vector2<f32>,
-    pub shape: Shape,
+    pub shape: Shape<f32>,
     pub colour: [f32; 3],
 }
 
 impl EntityCommon {
-    fn new(top_left: Vector2<f32>, shape: Shape, colour: [f32; 3]) -> Self {
+    pub fn new(top_left: Vector2<f32>, shape: Shape<f32>, colour: [f32; 3]) -> Self {
         Self {
             top_left,
             shape,
             colour,
         }
     }
-    fn aabb(&self) -> Aabb {
+    fn aabb(&self) -> Aabb<f32> {
         self.shape.aabb(self.top_left)
     }
 }
 
 #[derive(Default)]
 struct EntityIdAllocator {
-    next: u32,
+    generations: Vec<u32>,
+    free: Vec<u32>,
+    /// Bumped on every change.
+    version: u64,
 }
 
 impl EntityIdAllocator {
     fn allocate(&mut self) -> EntityId {
-        let id = self.next;
-        self.next += 1;
-        id
+        self.version += 1;
+        match self.free.pop() {
+            Some(index) => EntityId {
+                index,
+                generation: self.generations[index as usize],
+            },
+            None => {
+                let index = self.generations.len() as u32;
+                self.generations.push(0);
+                EntityId {
+                    index,
+                    generation: 0,
+                }
+            }
+        }
+    }
+    fn is_live(&self, id: EntityId) -> bool {
+        self.generations.get(id.index as usize) == Some(&id.generation)
     }
+    /// Returns false if `id` was already freed.
+    fn free(&mut self, id: EntityId) -> bool {
+        if !self.is_live(id) {
+            return false;
+        }
+        self.generations[id.index as usize] += 1;
+        self.free.push(id.index);
+        self.version += 1;
+        true
+    }
+    /// Frees every id, keeping generations so ids from before the reset stay stale.
     fn reset(&mut self) {
-        self.next = 0;
+        for generation in self.generations.iter_mut() {
+            *generation += 1;
+        }
+        self.free = (0..self.generations.len() as u32).rev().collect();
+        self.version += 1;
+    }
+    fn restore(&mut self, snapshot: &AllocatorSnapshot) {
+        self.generations = snapshot.generations.clone();
+        self.free = snapshot.free.clone();
+        self.version += 1;
+    }
+    fn snapshot(&self) -> AllocatorSnapshot {
+        AllocatorSnapshot {
+            generations: self.generations.clone(),
+            free: self.free.clone(),
+        }
     }
 }
 
@@ -99,29 +174,83 @@ impl EntityIdAllocator {
 struct SpatialInfo {
     entity_id: EntityId,
     position: Vector2<f32>,
-    shape: Shape,
+    shape: Shape<f32>,
+    one_way: bool,
 }
 
 impl SpatialInfo {
-    fn new(entity_id: EntityId, position: Vector2<f32>, shape: Shape) -> Self {
+    fn new(entity_id: EntityId, position: Vector2<f32>, shape: Shape<f32>) -> Self {
         SpatialInfo {
             entity_id,
             position,
             shape,
+            one_way: false,
         }
     }
 }
 
-type SpatialLooseQuadTree = LooseQuadTree<SpatialInfo>;
+/// One-way platforms only stop entities which are moving down from above them.
+fn is_blocked_by_one_way(
+    aabb: &Aabb<f32>,
+    movement: Vector2<f32>,
+    platform_aabb: &Aabb<f32>,
+) -> bool {
+    const EPSILON: f32 = 0.01;
+    movement.y > 0. && aabb.bottom_right_coord().y <= platform_aabb.top_left().y + EPSILON
+}
+
+type SpatialBroadPhase = AnyBroadPhase<SpatialInfo, f32>;
+
+/// Counts of the work done resolving movement, accumulated over every step.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollisionStats {
+    /// Movements of entities which were checked for collisions.
+    pub movements: u64,
+    /// Broad phase queries, one for each part of a movement between collisions.
+    pub queries: u64,
+    /// Static entities returned by the broad phase and tested in the narrow phase.
+    pub candidates: u64,
+    /// Queries which found a collision.
+    pub collisions: u64,
+    /// Movements which gave up before resolving every collision.
+    pub iteration_limit_reached: u64,
+}
+
+/// A moving entity running into a static one during a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collision {
+    pub mover: EntityId,
+    pub solid: EntityId,
+}
+
+/// Behaviour run once per step, after movement, in the order systems were added.
+pub trait System {
+    fn run(&mut self, game_state: &mut GameState);
+    /// Called when the level is reloaded, to drop anything loaded from its files.
+    fn reload(&mut self) {}
+}
 
 pub struct GameState {
     player_id: Option<EntityId>,
     entity_id_allocator: EntityIdAllocator,
     common: FnvHashMap<EntityId, EntityCommon>,
-    velocity: FnvHashMap<EntityId, Vector2<f32>>,
-    static_aabb_quad_tree: SpatialLooseQuadTree,
+    /// Entities in the broad phase, and whether each is a one-way platform.
+    statics: FnvHashMap<EntityId, bool>,
+    /// Entities with shapes which aren't in the broad phase.
+    dynamic: FnvHashSet<EntityId>,
+    /// Bumped whenever a static entity is added, removed or could have changed.
+    statics_version: u64,
+    components: ComponentStore,
+    systems: Vec<Box<dyn System>>,
+    static_broad_phase: SpatialBroadPhase,
+    collision_stats: CollisionStats,
+    collisions: Vec<Collision>,
+    damage_events: Vec<DamageEvent>,
 }
 
+/// The colour of static entities the player has touched.
+const TOUCHED_COLOUR: [f32; 3] = [0., 1., 1.];
+
 fn update_player_velocity(
     _current_velocity: Vector2<f32>,
     input_model: &InputModel,
@@ -132,59 +261,79 @@ fn update_player_velocity(
 
 enum EntityMovementStep {
     MoveWithoutCollision,
-    MoveWithCollision(CollisionInfo),
+    MoveWithCollision(CollisionInfo<f32>, EntityId),
 }
 
 fn entity_movement_step(
     top_left: Vector2<f32>,
-    shape: &Shape,
+    shape: &Shape<f32>,
     movement: Vector2<f32>,
-    static_aabb_quad_tree: &SpatialLooseQuadTree,
+    static_broad_phase: &SpatialBroadPhase,
+    stats: &mut CollisionStats,
 ) -> EntityMovementStep {
+    stats.queries += 1;
     let new_top_left = top_left + movement;
-    let movement_aabb = shape.aabb(top_left).union(&shape.aabb(new_top_left));
+    let aabb = shape.aabb(top_left);
+    let movement_aabb = aabb.union(&shape.aabb(new_top_left));
     let mut collision = BestMap::new();
-    static_aabb_quad_tree.for_each_intersection(&movement_aabb, |_solid_aabb, info| {
+    static_broad_phase.for_each_intersection(&movement_aabb, |solid_aabb, info| {
+        if info.one_way && !is_blocked_by_one_way(&aabb, movement, solid_aabb) {
+            return;
+        }
+        stats.candidates += 1;
         let collision_result =
             shape.movement_collision_test(top_left, &info.shape, info.position, movement);
         match collision_result {
             Some(CollisionInfo {
                 movement_vector_ratio,
                 colliding_with,
-            }) => collision.insert_lt(movement_vector_ratio, colliding_with),
+            }) => collision
+                .insert_lt(movement_vector_ratio, (colliding_with, info.entity_id)),
             None => (),
         }
     });
     match collision.into_key_and_value() {
         None => EntityMovementStep::MoveWithoutCollision,
-        Some((movement_vector_ratio, colliding_with)) => {
-            EntityMovementStep::MoveWithCollision(CollisionInfo {
-                movement_vector_ratio,
-                colliding_with,
-            })
+        Some((movement_vector_ratio, (colliding_with, entity_id))) => {
+            stats.collisions += 1;
+            EntityMovementStep::MoveWithCollision(
+                CollisionInfo {
+                    movement_vector_ratio,
+                    colliding_with,
+                },
+                entity_id,
+            )
         }
     }
 }
 
+/// Static entities collided with along the way are added to `collided_with`.
 fn top_left_after_movement(
-    common: &EntityCommon,
+    mut top_left: Vector2<f32>,
+    shape: &Shape<f32>,
     mut movement: Vector2<f32>,
-    static_aabb_quad_tree: &SpatialLooseQuadTree,
+    static_broad_phase: &SpatialBroadPhase,
+    stats: &mut CollisionStats,
+    collided_with: &mut Vec<EntityId>,
 ) -> Vector2<f32> {
     const EPSILON: f32 = 0.0001;
     const MAX_ITERATIONS: usize = 16;
-    let mut top_left = common.top_left;
     if movement.dot(movement) < EPSILON {
         return top_left;
     }
-    let shape = &common.shape;
+    stats.movements += 1;
     for _ in 0..MAX_ITERATIONS {
-        match entity_movement_step(top_left, shape, movement, static_aabb_quad_tree) {
+        match entity_movement_step(top_left, shape, movement, static_broad_phase, stats) {
             EntityMovementStep::MoveWithoutCollision => return top_left + movement,
-            EntityMovementStep::MoveWithCollision(CollisionInfo {
-                movement_vector_ratio,
-                colliding_with,
-            }) => {
+            EntityMovementStep::MoveWithCollision(
+                CollisionInfo {
+                    movement_vector_ratio,
+                    colliding_with,
+                },
+                entity_id,
+            ) => {
+                collided_with.push(entity_id);
+                let movement_vector_ratio = movement_vector_ratio.ratio();
                 top_left = top_left + movement * movement_vector_ratio;
                 let remaining_ratio = 1. - movement_vector_ratio;
                 if remaining_ratio < EPSILON {
@@ -199,109 +348,917 @@ fn top_left_after_movement(
             }
         }
     }
+    stats.iteration_limit_reached += 1;
     top_left
 }
 
 impl GameState {
-    pub fn new(size_hint: Vector2<f32>) -> Self {
+    pub fn new(size_hint: Vector2<f32>, broad_phase_kind: BroadPhaseKind) -> Self {
         Self {
             player_id: None,
             entity_id_allocator: Default::default(),
             common: Default::default(),
-            velocity: Default::default(),
-            static_aabb_quad_tree: LooseQuadTree::new(size_hint),
+            statics: Default::default(),
+            dynamic: Default::default(),
+            statics_version: 0,
+            components: Default::default(),
+            systems: Vec::new(),
+            static_broad_phase: AnyBroadPhase::new(broad_phase_kind, size_hint),
+            collision_stats: Default::default(),
+            collisions: Vec::new(),
+            damage_events: Vec::new(),
         }
     }
-    fn clear(&mut self) {
+    pub fn clear(&mut self) {
         self.player_id = None;
         self.entity_id_allocator.reset();
         self.common.clear();
-        self.velocity.clear();
-        self.static_aabb_quad_tree.clear();
+        self.statics.clear();
+        self.dynamic.clear();
+        self.statics_version += 1;
+        self.components.clear();
+        self.static_broad_phase.clear();
+        self.collisions.clear();
+        self.damage_events.clear();
     }
-    fn add_static_solid(&mut self, common: EntityCommon) -> EntityId {
+    fn add_static(&mut self, common: EntityCommon, one_way: bool) -> EntityId {
         let id = self.entity_id_allocator.allocate();
-        self.static_aabb_quad_tree.insert(
-            common.aabb(),
-            SpatialInfo::new(id, common.top_left, common.shape.clone()),
-        );
-        self.common.insert(id, common);
+        self.insert_static(id, common, one_way);
         id
     }
+    fn insert_static(&mut self, id: EntityId, common: EntityCommon, one_way: bool) {
+        let mut info = SpatialInfo::new(id, common.top_left, common.shape.clone());
+        info.one_way = one_way;
+        self.static_broad_phase.insert(common.aabb(), info);
+        self.common.insert(id, common);
+        self.statics.insert(id, one_way);
+        self.statics_version += 1;
+    }
+    fn solidity(&self, id: EntityId) -> Option<Solidity> {
+        self.statics.get(&id).map(|&one_way| {
+            if one_way {
+                Solidity::OneWay
+            } else {
+                Solidity::Solid
+            }
+        })
+    }
+    pub fn add_static_solid(&mut self, common: EntityCommon) -> EntityId {
+        self.add_static(common, false)
+    }
+    /// Adds a static entity which can be jumped through from below.
+    pub fn add_one_way_platform(&mut self, common: EntityCommon) -> EntityId {
+        self.add_static(common, true)
+    }
+    /// Adds an entity with no shape, whose data is entirely in components.
+    pub fn add_entity(&mut self) -> EntityId {
+        self.entity_id_allocator.allocate()
+    }
     fn add_common(&mut self, common: EntityCommon) -> EntityId {
         let id = self.entity_id_allocator.allocate();
         self.common.insert(id, common);
+        self.dynamic.insert(id);
         id
     }
-    pub fn init_demo(&mut self) {
-        self.clear();
-        let player_id = self.add_common(EntityCommon::new(
-            vec2(200., 50.),
-            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(32., 64.))),
-            [1., 0., 0.],
-        ));
-        self.player_id = Some(player_id);
-        self.velocity.insert(player_id, vec2(0., 0.));
-        self.add_static_solid(EntityCommon::new(
-            vec2(50., 200.),
-            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(400., 20.))),
-            [1., 1., 0.],
-        ));
-        self.add_static_solid(EntityCommon::new(
-            vec2(150., 250.),
-            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(500., 20.))),
-            [1., 1., 0.],
-        ));
-        self.add_static_solid(EntityCommon::new(
-            vec2(50., 450.),
-            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(100., 20.))),
-            [1., 1., 0.],
-        ));
-        self.add_static_solid(EntityCommon::new(
-            vec2(50., 500.),
-            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(800., 20.))),
-            [1., 1., 0.],
-        ));
-        self.add_static_solid(EntityCommon::new(
-            vec2(600., 100.),
-            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(20., 200.))),
-            [1., 1., 0.],
-        ));
-        self.add_static_solid(EntityCommon::new(
-            vec2(20., 20.),
-            Shape::LineSegment(LineSegment::new(vec2(0., 0.), vec2(50., 100.))),
-            [0., 1., 0.],
-        ));
+    pub fn add_mover(
+        &mut self,
+        common: EntityCommon,
+        velocity: Vector2<f32>,
+    ) -> EntityId {
+        let id = self.add_common(common);
+        self.components.insert(id, Velocity(velocity));
+        id
+    }
+    pub fn add_player(&mut self, common: EntityCommon) -> EntityId {
+        let id = self.add_mover(common, vec2(0., 0.));
+        self.player_id = Some(id);
+        id
+    }
+    pub fn is_alive(&self, id: EntityId) -> bool {
+        self.entity_id_allocator.is_live(id)
+    }
+    /// Whether the entity is a static solid or one-way platform.
+    pub fn is_static(&self, id: EntityId) -> bool {
+        self.statics.contains_key(&id)
+    }
+    /// Moves an entity without checking for collisions. Returns false if it has no
+    /// shape.
+    pub fn set_top_left(&mut self, id: EntityId, top_left: Vector2<f32>) -> bool {
+        let common = match self.common.get_mut(&id) {
+            Some(common) => common,
+            None => return false,
+        };
+        let old_aabb = common.aabb();
+        common.top_left = top_left;
+        if self.statics.contains_key(&id) {
+            self.statics_version += 1;
+        }
+        self.static_broad_phase.update(
+            &old_aabb,
+            common.aabb(),
+            |info| info.entity_id == id,
+            |info| info.position = top_left,
+        );
+        true
+    }
+    /// Removes an entity from every store. Returns false if `id` refers to an
+    /// entity which was already despawned.
+    pub fn despawn(&mut self, id: EntityId) -> bool {
+        if !self.entity_id_allocator.free(id) {
+            return false;
+        }
+        self.remove_entity(id);
+        if self.player_id == Some(id) {
+            self.player_id = None;
+        }
+        true
     }
     pub fn update(&mut self, input_model: &InputModel) {
-        let player_id = self.player_id.expect("No player id");
+        if let Some(player_id) = self.player_id {
+            self.update_player(player_id, input_model);
+        }
+        self.collisions.clear();
+        self.damage_events.clear();
+        let common = &mut self.common;
+        let static_broad_phase = &self.static_broad_phase;
+        let collision_stats = &mut self.collision_stats;
+        let collisions = &mut self.collisions;
+        let mut collided_with = Vec::new();
+        self.components.for_each(|id, &Velocity(velocity)| {
+            if let Some(common) = common.get_mut(&id) {
+                let new_top_left = top_left_after_movement(
+                    common.top_left,
+                    &common.shape,
+                    velocity,
+                    static_broad_phase,
+                    collision_stats,
+                    &mut collided_with,
+                );
+                common.top_left = new_top_left;
+                collisions.extend(
+                    collided_with
+                        .drain(..)
+                        .map(|solid| Collision { mover: id, solid }),
+                );
+            }
+        });
+        self.run_systems();
+    }
+    fn run_systems(&mut self) {
+        // Systems get mutable access to the whole game state, so the list is taken
+        // out while they run. Systems added during the step run from the next one.
+        let mut systems = mem::replace(&mut self.systems, Vec::new());
+        for system in systems.iter_mut() {
+            system.run(self);
+        }
+        systems.extend(self.systems.drain(..));
+        self.systems = systems;
+    }
+    fn update_player(&mut self, player_id: EntityId, input_model: &InputModel) {
         if let Some(player_common) = self.common.get(&player_id).cloned() {
             let common = &mut self.common;
+            let mut recoloured = false;
 
-            self.static_aabb_quad_tree.for_each_intersection(
+            self.static_broad_phase.for_each_intersection(
                 &player_common.aabb(),
                 |_solid_aabb, info| {
                     if let Some(common) = common.get_mut(&info.entity_id) {
-                        common.colour = [0., 1., 1.];
+                        if common.colour != TOUCHED_COLOUR {
+                            common.colour = TOUCHED_COLOUR;
+                            recoloured = true;
+                        }
                     }
                 },
             );
+            // Statics are only compared by rewinding when they might have changed.
+            if recoloured {
+                self.statics_version += 1;
+            }
         }
-        if let Some(velocity) = self.velocity.get_mut(&player_id) {
+        if let Some(&mut Velocity(ref mut velocity)) = self.components.get_mut(player_id)
+        {
             *velocity = update_player_velocity(*velocity, input_model);
         }
-        for (id, velocity) in self.velocity.iter() {
-            if let Some(common) = self.common.get_mut(id) {
-                let new_top_left = top_left_after_movement(
-                    common,
-                    *velocity,
-                    &self.static_aabb_quad_tree,
-                );
-                common.top_left = new_top_left;
+    }
+    /// Where the entity would end up if it attempted to move by `movement` this
+    /// step, without actually moving it.
+    pub fn top_left_after_movement(
+        &self,
+        id: EntityId,
+        movement: Vector2<f32>,
+    ) -> Option<Vector2<f32>> {
+        self.common
+            .get(&id)
+            .map(|common| self.sweep(&common.shape, common.top_left, movement))
+    }
+    /// Where `shape` would end up if it attempted to move by `movement` from
+    /// `top_left`, stopped by static entities. It needn't be the shape of an entity.
+    pub fn sweep(
+        &self,
+        shape: &Shape<f32>,
+        top_left: Vector2<f32>,
+        movement: Vector2<f32>,
+    ) -> Vector2<f32> {
+        top_left_after_movement(
+            top_left,
+            shape,
+            movement,
+            &self.static_broad_phase,
+            &mut CollisionStats::default(),
+            &mut Vec::new(),
+        )
+    }
+    pub fn collision_stats(&self) -> CollisionStats {
+        self.collision_stats
+    }
+    /// Collisions during the last step, in no particular order. A mover which
+    /// slides along a solid collides with it once per step.
+    pub fn collisions(&self) -> &[Collision] {
+        &self.collisions
+    }
+    /// Damage dealt during the last step, in the order it was dealt.
+    pub fn damage_events(&self) -> &[DamageEvent] {
+        &self.damage_events
+    }
+    /// Adds to the damage reported by `damage_events`, for systems which deal it.
+    pub fn report_damage(&mut self, event: DamageEvent) {
+        self.damage_events.push(event);
+    }
+    /// Calls `f` with each entity whose shape, trigger or hitbox has a bounding box
+    /// overlapping `aabb`, in no particular order. An entity may be passed more
+    /// than once. Static entities are found with the broad phase, and the rest are
+    /// checked one by one.
+    pub fn for_each_entity_in<F: FnMut(EntityId)>(&self, aabb: &Aabb<f32>, mut f: F) {
+        self.static_broad_phase
+            .for_each_intersection(aabb, |_, info| f(info.entity_id));
+        for &id in &self.dynamic {
+            if self.common[&id].aabb().is_intersecting(aabb) {
+                f(id);
+            }
+        }
+        self.components.for_each(|id, trigger: &Trigger| {
+            if trigger.aabb.is_intersecting(aabb) {
+                f(id);
             }
+        });
+        self.components.for_each(|id, hitbox: &Hitbox| {
+            if let Some(top_left) = hitbox.top_left(self) {
+                if hitbox.shape.aabb(top_left).is_intersecting(aabb) {
+                    f(id);
+                }
+            }
+        });
+    }
+    /// Calls `f` with each static entity and whether it's a one-way platform, in no
+    /// particular order.
+    pub fn for_each_static<F: FnMut(EntityId, &EntityCommon, bool)>(&self, mut f: F) {
+        for (&id, &one_way) in &self.statics {
+            f(id, &self.common[&id], one_way);
+        }
+    }
+    /// Calls `f` with each static entity whose bounding box intersects `aabb`, and
+    /// whether it's a one-way platform, in no particular order.
+    pub fn for_each_static_intersecting<F: FnMut(EntityId, &EntityCommon, bool)>(
+        &self,
+        aabb: &Aabb<f32>,
+        mut f: F,
+    ) {
+        let common = &self.common;
+        self.static_broad_phase
+            .for_each_intersection(aabb, |_, info| {
+                if let Some(entity) = common.get(&info.entity_id) {
+                    f(info.entity_id, entity, info.one_way);
+                }
+            });
+    }
+    pub fn player_id(&self) -> Option<EntityId> {
+        self.player_id
+    }
+    pub fn common(&self, id: EntityId) -> Option<&EntityCommon> {
+        self.common.get(&id)
+    }
+    pub fn common_mut(&mut self, id: EntityId) -> Option<&mut EntityCommon> {
+        if self.statics.contains_key(&id) {
+            self.statics_version += 1;
+        }
+        self.common.get_mut(&id)
+    }
+    pub fn components(&self) -> &ComponentStore {
+        &self.components
+    }
+    pub fn components_mut(&mut self) -> &mut ComponentStore {
+        &mut self.components
+    }
+    pub fn add_system(&mut self, system: Box<dyn System>) {
+        self.systems.push(system);
+    }
+    pub fn reload_systems(&mut self) {
+        for system in self.systems.iter_mut() {
+            system.reload();
         }
     }
     pub fn common_iter(&self) -> impl Iterator<Item = &EntityCommon> {
         self.common.values()
     }
+    /// Entities which have components or can move, sorted by id.
+    pub fn dynamic_entities(&self) -> Vec<EntityId> {
+        let mut ids = self.dynamic.iter().cloned().collect::<Vec<_>>();
+        self.components.for_each_entity(|id| ids.push(id));
+        ids.sort();
+        ids.dedup();
+        ids
+    }
+    /// Static solids and one-way platforms, sorted by id.
+    pub fn static_entities(&self) -> Vec<EntityId> {
+        let mut ids = self.statics.keys().cloned().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+    /// Changes whenever a static entity is added, removed or borrowed mutably.
+    pub fn statics_version(&self) -> u64 {
+        self.statics_version
+    }
+    /// Changes whenever an entity id is allocated or freed.
+    pub fn allocator_version(&self) -> u64 {
+        self.entity_id_allocator.version
+    }
+    pub fn allocator_snapshot(&self) -> AllocatorSnapshot {
+        self.entity_id_allocator.snapshot()
+    }
+    /// Returns `None` if the entity is dead or has no data.
+    pub fn snapshot_entity(&self, id: EntityId) -> Option<EntitySnapshot> {
+        let entity = EntitySnapshot {
+            id,
+            common: self.common.get(&id).map(|common| CommonSnapshot {
+                top_left: common.top_left.into(),
+                shape: (&common.shape).into(),
+                colour: common.colour,
+                solidity: self.solidity(id),
+            }),
+            velocity: self
+                .components
+                .get::<Velocity>(id)
+                .map(|velocity| velocity.0.into()),
+            trigger: self.components.get::<Trigger>(id).map(Into::into),
+            behaviour: self.components.get::<Behaviour>(id).cloned(),
+            health: self.components.get::<Health>(id).cloned(),
+            hitbox: self.components.get::<Hitbox>(id).map(Into::into),
+            script: self.components.get::<Script>(id).cloned(),
+        };
+        if entity.common.is_some()
+            || entity.velocity.is_some()
+            || entity.trigger.is_some()
+            || entity.behaviour.is_some()
+            || entity.health.is_some()
+            || entity.hitbox.is_some()
+            || entity.script.is_some()
+        {
+            Some(entity)
+        } else {
+            None
+        }
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        let allocator = &self.entity_id_allocator;
+        let free = allocator.free.iter().cloned().collect::<HashSet<_>>();
+        let entities = allocator
+            .generations
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| !free.contains(&(index as u32)))
+            .filter_map(|(index, &generation)| {
+                self.snapshot_entity(EntityId {
+                    index: index as u32,
+                    generation,
+                })
+            })
+            .collect();
+        Snapshot {
+            version: SNAPSHOT_FORMAT_VERSION,
+            player_id: self.player_id,
+            allocator: allocator.snapshot(),
+            entities,
+        }
+    }
+    /// Replaces the state with `snapshot`. Systems are kept, and the broad phase
+    /// keeps its current backend.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.clear();
+        self.entity_id_allocator.restore(&snapshot.allocator);
+        for entity in snapshot.entities.iter() {
+            self.insert_entity(entity);
+        }
+        self.player_id = snapshot.player_id;
+    }
+    /// Replaces each listed entity with its snapshot, or removes it if there is none,
+    /// leaving every other entity and the rest of the broad phase untouched.
+    pub fn restore_entities(
+        &mut self,
+        player_id: Option<EntityId>,
+        allocator: Option<&AllocatorSnapshot>,
+        entities: &[(EntityId, Option<EntitySnapshot>)],
+    ) {
+        if let Some(allocator) = allocator {
+            self.entity_id_allocator.restore(allocator);
+        }
+        for &(id, ref entity) in entities.iter() {
+            self.remove_entity(id);
+            if let Some(ref entity) = *entity {
+                self.insert_entity(entity);
+            }
+        }
+        self.player_id = player_id;
+    }
+    fn remove_entity(&mut self, id: EntityId) {
+        if let Some(common) = self.common.remove(&id) {
+            if self.statics.remove(&id).is_some() {
+                self.static_broad_phase
+                    .remove(&common.aabb(), |info| info.entity_id == id);
+                self.statics_version += 1;
+            }
+        }
+        self.dynamic.remove(&id);
+        self.components.remove_entity(id);
+    }
+    fn insert_entity(&mut self, entity: &EntitySnapshot) {
+        let id = entity.id;
+        if let Some(ref common) = entity.common {
+            let top_left = vec2(common.top_left[0], common.top_left[1]);
+            let common_entity =
+                EntityCommon::new(top_left, common.shape.to_shape(), common.colour);
+            match common.solidity {
+                Some(solidity) => {
+                    self.insert_static(id, common_entity, solidity == Solidity::OneWay)
+                }
+                None => {
+                    self.common.insert(id, common_entity);
+                    self.dynamic.insert(id);
+                }
+            }
+        }
+        if let Some(velocity) = entity.velocity {
+            self.components
+                .insert(id, Velocity(vec2(velocity[0], velocity[1])));
+        }
+        if let Some(ref trigger) = entity.trigger {
+            self.components.insert(id, trigger.to_trigger());
+        }
+        if let Some(behaviour) = entity.behaviour {
+            self.components.insert(id, behaviour);
+        }
+        if let Some(health) = entity.health {
+            self.components.insert(id, health);
+        }
+        if let Some(ref hitbox) = entity.hitbox {
+            self.components.insert(id, hitbox.to_hitbox());
+        }
+        if let Some(ref script) = entity.script {
+            self.components.insert(id, script.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use combat::{spawn_hitbox, Team};
+    use line_segment::LineSegment;
+    use proptest::prelude::*;
+    use scenario;
+    use shape::AxisAlignedRect;
+    use test_util::rect;
+
+    #[test]
+    fn despawn_removes_from_every_store() {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let solid = game_state.add_static_solid(rect(20., 0., 10., 10.));
+        let mover = game_state.add_mover(rect(0., 0., 10., 10.), vec2(1., 0.));
+        let blocked = game_state
+            .top_left_after_movement(mover, vec2(20., 0.))
+            .unwrap();
+        assert!(blocked.x < 10.1);
+        assert!(game_state.despawn(solid));
+        assert_eq!(
+            game_state.top_left_after_movement(mover, vec2(20., 0.)),
+            Some(vec2(20., 0.))
+        );
+        assert!(game_state.despawn(mover));
+        assert_eq!(
+            game_state.top_left_after_movement(mover, vec2(20., 0.)),
+            None
+        );
+        assert_eq!(game_state.common_iter().count(), 0);
+        game_state.update(&Default::default());
+    }
+
+    #[test]
+    fn collisions_are_recorded() {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let solid = game_state.add_static_solid(rect(20., 0., 10., 10.));
+        let mover = game_state.add_mover(rect(0., 0., 10., 10.), vec2(8., 0.));
+        game_state.update(&Default::default());
+        assert!(game_state.collisions().is_empty());
+        game_state.update(&Default::default());
+        assert_eq!(game_state.collisions(), &[Collision { mover, solid }]);
+        assert!(game_state.is_static(solid));
+        assert!(!game_state.is_static(mover));
+        let mut found = Vec::new();
+        game_state.for_each_entity_in(&Aabb::new(vec2(5., 0.), vec2(20., 5.)), |id| {
+            found.push(id)
+        });
+        found.sort_by_key(|id| id.index());
+        assert_eq!(found, vec![solid, mover]);
+        assert!(game_state.set_top_left(solid, vec2(50., 50.)));
+        game_state.update(&Default::default());
+        assert!(game_state.collisions().is_empty());
+        // Unblocked, so it moves the full 8 from just short of where the solid was.
+        assert!(game_state.common(mover).unwrap().top_left.x > 17.9);
+    }
+
+    #[test]
+    fn entities_in_include_non_moving_ones() {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let still = game_state.add_mover(rect(0., 0., 10., 10.), vec2(0., 0.));
+        game_state.components_mut().remove::<Velocity>(still);
+        let trigger = game_state.add_entity();
+        game_state.components_mut().insert(
+            trigger,
+            Trigger {
+                name: "switch".to_string(),
+                aabb: Aabb::new(vec2(5., 5.), vec2(10., 10.)),
+                properties: Default::default(),
+            },
+        );
+        let hitbox = spawn_hitbox(
+            &mut game_state,
+            Hitbox::new(
+                Team::Enemy,
+                vec2(8., 0.),
+                Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(4., 4.))),
+                1,
+            ),
+        );
+        game_state.add_mover(rect(50., 50., 10., 10.), vec2(0., 0.));
+        let mut found = Vec::new();
+        game_state.for_each_entity_in(&Aabb::new(vec2(0., 0.), vec2(10., 10.)), |id| {
+            found.push(id)
+        });
+        found.sort_by_key(|id| id.index());
+        assert_eq!(found, vec![still, trigger, hitbox]);
+    }
+
+    #[test]
+    fn stale_ids_are_detected() {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let first = game_state.add_player(rect(0., 0., 10., 10.));
+        assert!(game_state.despawn(first));
+        assert!(!game_state.despawn(first));
+        let second = game_state.add_mover(rect(50., 50., 10., 10.), vec2(0., 0.));
+        assert_ne!(first, second);
+        assert!(!game_state.is_alive(first));
+        assert!(game_state.is_alive(second));
+        assert_eq!(
+            game_state.top_left_after_movement(first, vec2(0., 0.)),
+            None
+        );
+        game_state.clear();
+        assert!(!game_state.is_alive(second));
+        let third = game_state.add_mover(rect(0., 0., 10., 10.), vec2(0., 0.));
+        assert!(!game_state.despawn(second));
+        assert!(game_state.is_alive(third));
+    }
+
+    struct Slow;
+
+    /// Halves the velocity of every entity marked `Slow`.
+    struct SlowSystem {
+        runs: usize,
+    }
+
+    impl System for SlowSystem {
+        fn run(&mut self, game_state: &mut GameState) {
+            self.runs += 1;
+            game_state
+                .components_mut()
+                .join_mut::<Velocity, Slow, _>(|_, velocity, _| velocity.0 /= 2.);
+        }
+    }
+
+    #[test]
+    fn systems_run_each_update() {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let slow = game_state.add_mover(rect(0., 0., 10., 10.), vec2(8., 0.));
+        let fast = game_state.add_mover(rect(0., 50., 10., 10.), vec2(8., 0.));
+        game_state.components_mut().insert(slow, Slow);
+        game_state.add_system(Box::new(SlowSystem { runs: 0 }));
+        game_state.update(&Default::default());
+        game_state.update(&Default::default());
+        assert_eq!(game_state.common(slow).unwrap().top_left, vec2(12., 0.));
+        assert_eq!(game_state.common(fast).unwrap().top_left, vec2(16., 50.));
+        assert_eq!(
+            game_state.components().get::<Velocity>(slow),
+            Some(&Velocity(vec2(2., 0.)))
+        );
+        game_state.despawn(slow);
+        assert!(!game_state.components().contains::<Slow>(slow));
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        use broad_phase::BroadPhaseKind;
+        use line_segment::LineSegment;
+
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let despawned = game_state.add_static_solid(rect(0., 90., 10., 10.));
+        game_state.add_static_solid(rect(50., 20., 10., 10.));
+        game_state.add_one_way_platform(EntityCommon::new(
+            vec2(0., 40.),
+            Shape::LineSegment(LineSegment::new(vec2(0., 0.), vec2(40., 0.))),
+            [1., 1., 1.],
+        ));
+        let player = game_state.add_player(rect(5., 5., 10., 10.));
+        game_state.add_mover(rect(70., 70., 10., 10.), vec2(-1., 0.));
+        let trigger = game_state.add_entity();
+        game_state.components_mut().insert(
+            trigger,
+            Trigger {
+                name: "exit".to_string(),
+                aabb: Aabb::new(vec2(90., 0.), vec2(10., 10.)),
+                properties: Default::default(),
+            },
+        );
+        game_state.despawn(despawned);
+
+        let snapshot = game_state.snapshot();
+        let text = snapshot.to_json();
+        let parsed = Snapshot::parse("snapshot.json", &text).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        let mut restored =
+            GameState::new(vec2(100., 100.), BroadPhaseKind::SweepAndPrune);
+        restored.restore(&parsed);
+        assert_eq!(restored.snapshot().to_json(), text);
+        assert_eq!(restored.player_id(), Some(player));
+        assert!(!restored.is_alive(despawned));
+        // The player still stands on the one-way platform and is blocked by the
+        // solid.
+        assert_eq!(
+            restored.top_left_after_movement(player, vec2(0., 40.)),
+            game_state.top_left_after_movement(player, vec2(0., 40.))
+        );
+        assert_eq!(
+            restored.top_left_after_movement(player, vec2(60., 0.)),
+            game_state.top_left_after_movement(player, vec2(60., 0.))
+        );
+        for _ in 0..10 {
+            game_state.update(&Default::default());
+            restored.update(&Default::default());
+        }
+        assert_eq!(restored.snapshot(), game_state.snapshot());
+        // New ids carry on from where the snapshot left off.
+        assert_eq!(restored.add_entity(), game_state.add_entity());
+    }
+
+    #[test]
+    fn snapshot_rejects_unallocated_entities() {
+        let text = r#"{
+            "version": 1,
+            "player_id": { "index": 0, "generation": 1 },
+            "allocator": { "generations": [0], "free": [] },
+            "entities": []
+        }"#;
+        let message = Snapshot::parse("bad.json", text).unwrap_err().to_string();
+        assert!(message.contains("is not allocated"), "{}", message);
+    }
+
+    #[test]
+    fn snapshot_rejects_moving_statics() {
+        let mut game_state = GameState::new(vec2(100., 100.), Default::default());
+        let solid = game_state.add_static_solid(rect(20., 0., 10., 10.));
+        let mut snapshot = game_state.snapshot();
+        assert!(Snapshot::parse("ok.json", &snapshot.to_json()).is_ok());
+        snapshot.entities[solid.index() as usize].velocity = Some([1., 0.]);
+        let message = Snapshot::parse("bad.json", &snapshot.to_json())
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("moves but is static"), "{}", message);
+        snapshot.entities[solid.index() as usize].velocity = None;
+        snapshot.player_id = Some(solid);
+        assert!(Snapshot::parse("bad.json", &snapshot.to_json()).is_err());
+    }
+
+    #[test]
+    fn snapshot_rejects_bad_free_lists() {
+        let parse = |free: &str| {
+            let text = format!(
+                r#"{{
+                    "version": 1,
+                    "player_id": null,
+                    "allocator": {{ "generations": [1, 1], "free": {} }},
+                    "entities": []
+                }}"#,
+                free
+            );
+            Snapshot::parse("bad.json", &text).map_err(|error| error.to_string())
+        };
+        assert!(parse("[1, 0]").is_ok());
+        let message = parse("[5]").unwrap_err();
+        assert!(
+            message.contains("free index 5 was never allocated"),
+            "{}",
+            message
+        );
+        let message = parse("[1, 1]").unwrap_err();
+        assert!(
+            message.contains("free index 1 appears more than once"),
+            "{}",
+            message
+        );
+    }
+
+    fn coordinate() -> impl Strategy<Value = f32> {
+        // Small multiples of 4 make movement which exactly meets an edge likely.
+        prop_oneof![(0i32..25).prop_map(|x| x as f32 * 4.), 0f32..100.]
+    }
+
+    fn static_solid() -> impl Strategy<Value = (Vector2<f32>, Shape<f32>)> {
+        let rect = (coordinate(), coordinate(), 1f32..40., 1f32..40.).prop_map(
+            |(x, y, width, height)| {
+                let shape = AxisAlignedRect::new(vec2(width, height));
+                (vec2(x, y), Shape::AxisAlignedRect(shape))
+            },
+        );
+        let line_segment = (coordinate(), coordinate(), coordinate(), coordinate())
+            .prop_filter("degenerate", |&(x0, y0, x1, y1)| x0 != x1 || y0 != y1)
+            .prop_map(|(x0, y0, x1, y1)| {
+                let shape = LineSegment::new(vec2(x0, y0), vec2(x1, y1));
+                (vec2(0., 0.), Shape::LineSegment(shape))
+            });
+        prop_oneof![rect, line_segment]
+    }
+
+    /// A player among static solids.
+    #[derive(Debug, Clone)]
+    struct Scene {
+        solids: Vec<(Vector2<f32>, Shape<f32>)>,
+        player_top_left: Vector2<f32>,
+        player_size: Vector2<f32>,
+    }
+
+    impl Scene {
+        /// Solids which the player would start inside are left out.
+        fn build(&self) -> (GameState, EntityId) {
+            // Large enough to contain every solid, as the broad phase requires.
+            let mut game_state = GameState::new(vec2(200., 200.), Default::default());
+            let player = game_state.add_player(EntityCommon::new(
+                self.player_top_left,
+                Shape::AxisAlignedRect(AxisAlignedRect::new(self.player_size)),
+                [1., 1., 1.],
+            ));
+            for &(top_left, ref shape) in self.solids.iter() {
+                let common = EntityCommon::new(top_left, shape.clone(), [1., 1., 1.]);
+                let id = game_state.add_static_solid(common);
+                if scenario::overlapping_solid(&game_state, player, 0.).is_some() {
+                    game_state.despawn(id);
+                }
+            }
+            (game_state, player)
+        }
+
+        /// Moves the player by each movement in turn, returning the first solid it
+        /// ends up inside and where it ended up.
+        fn first_overlap(
+            &self,
+            movements: &[Vector2<f32>],
+        ) -> Option<(EntityId, Vector2<f32>)> {
+            let (mut game_state, player) = self.build();
+            // Later movements start from wherever the previous one stopped, which is
+            // often touching a solid.
+            for &movement in movements {
+                let top_left = game_state
+                    .top_left_after_movement(player, movement)
+                    .unwrap();
+                game_state.common_mut(player).unwrap().top_left = top_left;
+                if let Some(id) = scenario::overlapping_solid(&game_state, player, 0.01) {
+                    return Some((id, top_left));
+                }
+            }
+            None
+        }
+    }
+
+    fn segment(start: (f32, f32), end: (f32, f32)) -> (Vector2<f32>, Shape<f32>) {
+        let shape = LineSegment::new(vec2(start.0, start.1), vec2(end.0, end.1));
+        (vec2(0., 0.), Shape::LineSegment(shape))
+    }
+
+    // The following scenes are failures found by `movement_never_ends_inside_solid`,
+    // shrunk by proptest. Their seeds are in proptest-regressions/game.txt.
+
+    #[test]
+    fn regression_steep_segment() {
+        let scene = Scene {
+            solids: vec![segment((44., 7.7528534), (20., 90.81982))],
+            player_top_left: vec2(4., 72.),
+            player_size: vec2(9.858967, 4.6785517),
+        };
+        let movements = [vec2(28.71054, 12.615974), vec2(0., 8.), vec2(4., 0.)];
+        assert_eq!(scene.first_overlap(&movements), None);
+    }
+
+    #[test]
+    fn regression_tall_player_below_segment() {
+        let scene = Scene {
+            solids: vec![segment((36., 77.384995), (7.917374, 56.722275))],
+            player_top_left: vec2(21.201427, 21.639927),
+            player_size: vec2(1., 5.219672),
+        };
+        let movements = [vec2(4., 20.), vec2(-28., 16.)];
+        assert_eq!(scene.first_overlap(&movements), None);
+    }
+
+    #[test]
+    fn regression_segment_beside_rect() {
+        let scene = Scene {
+            solids: vec![
+                segment((56., 12.), (7.99525, 27.928133)),
+                (
+                    vec2(20., 8.),
+                    Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(
+                        30.672203, 30.30284,
+                    ))),
+                ),
+            ],
+            player_top_left: vec2(52., 52.),
+            player_size: vec2(1., 1.),
+        };
+        let movements = [
+            vec2(0., -26.36203),
+            vec2(-36.403564, -29.881989),
+            vec2(-40.262215, 2.1216714),
+        ];
+        assert_eq!(scene.first_overlap(&movements), None);
+    }
+
+    #[test]
+    fn regression_segment_through_origin() {
+        let scene = Scene {
+            solids: vec![segment((58.475716, 57.88556), (0., 0.))],
+            player_top_left: vec2(90.72934, 40.),
+            player_size: vec2(1., 18.396097),
+        };
+        let movements = [vec2(-16., -16.), vec2(-40.255424, 38.36568)];
+        assert_eq!(scene.first_overlap(&movements), None);
+    }
+
+    prop_compose! {
+        fn scene()(
+            solids in prop::collection::vec(static_solid(), 1..6),
+            x in coordinate(),
+            y in coordinate(),
+            width in 1f32..20.,
+            height in 1f32..20.,
+        ) -> Scene {
+            Scene {
+                solids,
+                player_top_left: vec2(x, y),
+                player_size: vec2(width, height),
+            }
+        }
+    }
+
+    fn movement() -> impl Strategy<Value = Vector2<f32>> {
+        prop_oneof![
+            (-8i32..8, -8i32..8).prop_map(|(x, y)| vec2(x as f32, y as f32) * 4.),
+            (-50f32..50., -50f32..50.).prop_map(|(x, y)| vec2(x, y)),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn movement_never_ends_inside_solid(
+            scene in scene(),
+            movements in prop::collection::vec(movement(), 1..4),
+        ) {
+            let overlap = scene.first_overlap(&movements);
+            prop_assert!(overlap.is_none(), "{:?}", overlap);
+        }
+
+        #[test]
+        fn movement_never_exceeds_requested_vector(
+            scene in scene(),
+            movement in movement(),
+        ) {
+            let (game_state, player) = scene.build();
+            let start = game_state.common(player).unwrap().top_left;
+            let end = game_state.top_left_after_movement(player, movement).unwrap();
+            let distance = (end - start).magnitude();
+            prop_assert!(
+                distance <= movement.magnitude() + 0.001,
+                "moved {} for {:?}",
+                distance,
+                movement
+            );
+        }
+    }
 }
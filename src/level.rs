@@ -0,0 +1,570 @@
+use aabb::Aabb;
+use behaviour::{Behaviour, BehaviourSystem};
+use broad_phase::BroadPhaseKind;
+use cgmath::{vec2, Vector2};
+use combat::{CombatSystem, Health};
+use edge_chain::EdgeChain;
+use game::{EntityCommon, EntityId, GameState, Velocity};
+use line_segment::LineSegment;
+use script::Script;
+#[cfg(feature = "scripting")]
+use script_system::ScriptSystem;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json;
+use shape::{AxisAlignedRect, Shape};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tiled;
+use trigger::{Properties, Trigger};
+
+/// Version of the level format written by this version of the game. Bump this when
+/// making a change to the format which old levels can't be read with.
+pub const LEVEL_FORMAT_VERSION: u32 = 1;
+
+fn supported_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == LEVEL_FORMAT_VERSION {
+        Ok(version)
+    } else {
+        Err(de::Error::custom(format_args!(
+            "unsupported level format version {} (expected {})",
+            version, LEVEL_FORMAT_VERSION
+        )))
+    }
+}
+
+fn positive_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[f32; 2], D::Error> {
+    let size = <[f32; 2]>::deserialize(deserializer)?;
+    if size[0] > 0. && size[1] > 0. {
+        Ok(size)
+    } else {
+        Err(de::Error::custom(format_args!(
+            "size must be positive, got {:?}",
+            size
+        )))
+    }
+}
+
+fn edge_chain_vertices<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<[f32; 2]>, D::Error> {
+    let vertices = Vec::<[f32; 2]>::deserialize(deserializer)?;
+    if vertices.len() >= 2 {
+        Ok(vertices)
+    } else {
+        Err(de::Error::custom("edge chain needs at least 2 vertices"))
+    }
+}
+
+/// Only rects can move, so entities with other shapes can't have a velocity or a
+/// behaviour.
+fn level_entities<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<LevelEntity>, D::Error> {
+    struct EntitiesVisitor;
+    impl<'de> de::Visitor<'de> for EntitiesVisitor {
+        type Value = Vec<LevelEntity>;
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a list of entities")
+        }
+        fn visit_seq<A: de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut entities = Vec::new();
+            // Checked as each entity is read, so errors point at the entity.
+            while let Some(entity) = seq.next_element::<LevelEntity>()? {
+                if entity.velocity.is_some() || entity.behaviour.is_some() {
+                    match entity.shape {
+                        LevelShape::Rect { .. } => (),
+                        LevelShape::LineSegment { .. } => {
+                            return Err(de::Error::custom("line segments can't move"))
+                        }
+                        LevelShape::EdgeChain { .. } => {
+                            return Err(de::Error::custom("edge chains can't move"))
+                        }
+                    }
+                }
+                entities.push(entity);
+            }
+            Ok(entities)
+        }
+    }
+    deserializer.deserialize_seq(EntitiesVisitor)
+}
+
+fn vector(v: [f32; 2]) -> Vector2<f32> {
+    vec2(v[0], v[1])
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LevelShape {
+    Rect {
+        #[serde(deserialize_with = "positive_size")]
+        size: [f32; 2],
+    },
+    LineSegment {
+        start: [f32; 2],
+        end: [f32; 2],
+    },
+    /// Solid on the right of each edge, so a closed chain should be clockwise.
+    EdgeChain {
+        #[serde(deserialize_with = "edge_chain_vertices")]
+        vertices: Vec<[f32; 2]>,
+        #[serde(default)]
+        closed: bool,
+    },
+}
+
+impl LevelShape {
+    fn to_shape(&self) -> Shape<f32> {
+        match self {
+            &LevelShape::Rect { size } => {
+                Shape::AxisAlignedRect(AxisAlignedRect::new(vector(size)))
+            }
+            &LevelShape::LineSegment { start, end } => {
+                Shape::LineSegment(LineSegment::new(vector(start), vector(end)))
+            }
+            &LevelShape::EdgeChain {
+                ref vertices,
+                closed,
+            } => {
+                let vertices = vertices.iter().cloned().map(vector).collect();
+                Shape::EdgeChain(if closed {
+                    EdgeChain::closed(vertices)
+                } else {
+                    EdgeChain::new(vertices)
+                })
+            }
+        }
+    }
+}
+
+/// An entity which isn't the player. Entities with a velocity or a behaviour move,
+/// and the rest are static solids. Only rects can move.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelEntity {
+    pub top_left: [f32; 2],
+    pub shape: LevelShape,
+    pub colour: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behaviour: Option<Behaviour>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<Health>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Script>,
+}
+
+impl LevelEntity {
+    fn common(&self) -> EntityCommon {
+        EntityCommon::new(vector(self.top_left), self.shape.to_shape(), self.colour)
+    }
+
+    pub fn spawn(&self, game_state: &mut GameState) -> EntityId {
+        let id = match (self.velocity, self.behaviour) {
+            (Some(velocity), _) => game_state.add_mover(self.common(), vector(velocity)),
+            (None, Some(_)) => game_state.add_mover(self.common(), vec2(0., 0.)),
+            (None, None) => game_state.add_static_solid(self.common()),
+        };
+        if let Some(behaviour) = self.behaviour {
+            game_state.components_mut().insert(id, behaviour);
+        }
+        if let Some(health) = self.health {
+            game_state.components_mut().insert(id, health);
+        }
+        if let Some(ref script) = self.script {
+            game_state.components_mut().insert(id, script.clone());
+        }
+        id
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerSpawn {
+    pub top_left: [f32; 2],
+    #[serde(deserialize_with = "positive_size")]
+    pub size: [f32; 2],
+    pub colour: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<Health>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelTrigger {
+    pub name: String,
+    pub top_left: [f32; 2],
+    #[serde(deserialize_with = "positive_size")]
+    pub size: [f32; 2],
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Script>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Level {
+    #[serde(deserialize_with = "supported_version")]
+    pub version: u32,
+    #[serde(deserialize_with = "positive_size")]
+    pub world_size: [f32; 2],
+    pub player: PlayerSpawn,
+    #[serde(deserialize_with = "level_entities")]
+    pub entities: Vec<LevelEntity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<LevelTrigger>,
+    /// The directory of the file the level was loaded from. Script paths are
+    /// relative to it.
+    #[serde(skip)]
+    pub directory: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        error: serde_json::Error,
+    },
+    Unsupported {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LevelError::Io {
+                ref path,
+                ref error,
+            } => write!(f, "{}: {}", path.display(), error),
+            &LevelError::Parse {
+                ref path,
+                line,
+                column,
+                ref error,
+            } => {
+                // serde_json appends the position to its own messages, but the
+                // file name goes first so editors can jump to the location.
+                let message = error.to_string();
+                let suffix = format!(" at line {} column {}", line, column);
+                let message = if message.ends_with(&suffix) {
+                    &message[..message.len() - suffix.len()]
+                } else {
+                    &message
+                };
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            }
+            &LevelError::Unsupported {
+                ref path,
+                ref message,
+            } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+/// Where a level is loaded from.
+#[derive(Debug, Clone)]
+pub enum LevelSource {
+    Level(String),
+    TiledMap(String),
+}
+
+impl LevelSource {
+    pub fn load(&self) -> Result<Level, LevelError> {
+        match self {
+            &LevelSource::Level(ref path) => Level::load(path),
+            &LevelSource::TiledMap(ref path) => tiled::import(path),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            &LevelSource::Level(ref path) | &LevelSource::TiledMap(ref path) => path,
+        }
+    }
+
+    /// Where the editor saves to. Imported Tiled maps are saved alongside the map
+    /// rather than overwriting it.
+    pub fn save_path(&self) -> PathBuf {
+        match self {
+            &LevelSource::Level(ref path) => PathBuf::from(path),
+            &LevelSource::TiledMap(ref path) => {
+                PathBuf::from(path).with_extension("level.json")
+            }
+        }
+    }
+}
+
+impl Level {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(path, &text)
+    }
+
+    /// Parses a level from `text`. `path` is used in error messages and to find
+    /// scripts, but isn't read.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let mut level: Self =
+            serde_json::from_str(text).map_err(|error| LevelError::Parse {
+                path: path.to_path_buf(),
+                line: error.line(),
+                column: error.column(),
+                error,
+            })?;
+        level.directory = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        Ok(level)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("failed to serialize level")
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LevelError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json() + "\n").map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    pub fn world_size(&self) -> Vector2<f32> {
+        vector(self.world_size)
+    }
+
+    /// Replaces the contents of `game_state` with the contents of the level.
+    /// Returns the ids of the level's entities, in the same order as `entities`.
+    pub fn populate(&self, game_state: &mut GameState) -> Vec<EntityId> {
+        game_state.clear();
+        let player = &self.player;
+        let player_id = game_state.add_player(EntityCommon::new(
+            vector(player.top_left),
+            Shape::AxisAlignedRect(AxisAlignedRect::new(vector(player.size))),
+            player.colour,
+        ));
+        if let Some(health) = player.health {
+            game_state.components_mut().insert(player_id, health);
+        }
+        let ids = self
+            .entities
+            .iter()
+            .map(|entity| entity.spawn(game_state))
+            .collect();
+        for trigger in self.triggers.iter() {
+            let id = game_state.add_entity();
+            game_state.components_mut().insert(
+                id,
+                Trigger {
+                    name: trigger.name.clone(),
+                    aabb: Aabb::new(vector(trigger.top_left), vector(trigger.size)),
+                    properties: trigger.properties.clone(),
+                },
+            );
+            if let Some(ref script) = trigger.script {
+                game_state.components_mut().insert(id, script.clone());
+            }
+        }
+        ids
+    }
+
+    /// Like `populate`, but the player keeps its current position and velocity
+    /// rather than respawning.
+    /// Systems are told to drop anything they loaded from the level's files, such
+    /// as scripts.
+    pub fn reload(&self, game_state: &mut GameState) -> Vec<EntityId> {
+        game_state.reload_systems();
+        let player = game_state.player_id().and_then(|id| {
+            let top_left = game_state.common(id)?.top_left;
+            let velocity = game_state.components().get::<Velocity>(id).cloned();
+            Some((top_left, velocity))
+        });
+        let ids = self.populate(game_state);
+        if let (Some((top_left, velocity)), Some(id)) = (player, game_state.player_id()) {
+            if let Some(common) = game_state.common_mut(id) {
+                common.top_left = top_left;
+            }
+            if let Some(velocity) = velocity {
+                game_state.components_mut().insert(id, velocity);
+            }
+        }
+        ids
+    }
+
+    /// Scripts attached to entities run if the `scripting` feature is enabled, after
+    /// behaviours and combat, so they can override them.
+    pub fn build(&self, broad_phase_kind: BroadPhaseKind) -> GameState {
+        let mut game_state = GameState::new(self.world_size(), broad_phase_kind);
+        self.populate(&mut game_state);
+        game_state.add_system(Box::new(BehaviourSystem));
+        game_state.add_system(Box::new(CombatSystem));
+        #[cfg(feature = "scripting")]
+        game_state.add_system(Box::new(
+            ScriptSystem::new().with_directory(&self.directory),
+        ));
+        game_state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DEMO: &str = include_str!("../levels/demo.json");
+    const ENEMIES: &str = include_str!("../levels/enemies.json");
+
+    fn parse_error(text: &str) -> (usize, usize, String) {
+        match Level::parse("test.json", text) {
+            Err(LevelError::Parse {
+                line,
+                column,
+                error,
+                ..
+            }) => (line, column, error.to_string()),
+            other => panic!("expected parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn demo_level_loads() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let game_state = level.build(BroadPhaseKind::default());
+        assert_eq!(game_state.common_iter().count(), level.entities.len() + 1);
+        let round_trip = Level::parse("round_trip.json", &level.to_json()).unwrap();
+        assert_eq!(round_trip.to_json(), level.to_json());
+    }
+
+    #[test]
+    fn behaviours_spawn_movers() {
+        let level = Level::parse("enemies.json", ENEMIES).unwrap();
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let ids = game_state
+            .snapshot()
+            .entities
+            .into_iter()
+            .filter(|entity| entity.behaviour.is_some())
+            .map(|entity| entity.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 3);
+        for &id in ids.iter() {
+            assert!(!game_state.is_static(id));
+            assert!(game_state.components().get::<Velocity>(id).is_some());
+        }
+        let snapshot = game_state.snapshot();
+        for _ in 0..10 {
+            game_state.update(&Default::default());
+        }
+        assert!(game_state.common(ids[1]).unwrap().top_left.x < 500.);
+        game_state.restore(&snapshot);
+        assert_eq!(game_state.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn reload_keeps_player() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let player = game_state.player_id().unwrap();
+        game_state.common_mut(player).unwrap().top_left = vec2(123., 45.);
+        game_state
+            .components_mut()
+            .insert(player, Velocity(vec2(1., 2.)));
+
+        let mut edited = level.clone();
+        edited.entities.truncate(1);
+        edited.reload(&mut game_state);
+        assert_eq!(game_state.common_iter().count(), 2);
+        let player = game_state.player_id().unwrap();
+        assert_eq!(game_state.common(player).unwrap().top_left, vec2(123., 45.));
+        assert_eq!(
+            game_state.components().get::<Velocity>(player),
+            Some(&Velocity(vec2(1., 2.)))
+        );
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let text = r#"{
+    "version": 1,
+    "world_size": [100, 100],
+    "player": { "top_left": [0, 0], "size": [0, 10], "colour": [1, 0, 0] },
+    "entities": []
+}"#;
+        let (line, _, message) = parse_error(text);
+        assert_eq!(line, 4);
+        assert!(message.contains("size must be positive"), "{}", message);
+
+        let (line, _, message) =
+            parse_error("{\n  \"version\": 2,\n  \"world_size\": [1, 1]\n}");
+        assert_eq!(line, 2);
+        assert!(message.contains("unsupported level format version 2"));
+
+        let (line, column, _) = parse_error("{\n  \"version\": 1,\n  oops\n}");
+        assert_eq!((line, column), (3, 3));
+    }
+
+    #[test]
+    fn only_rects_move() {
+        let level = |shape: &str, movement: &str| {
+            format!(
+                r#"{{
+    "version": 1,
+    "world_size": [100, 100],
+    "player": {{ "top_left": [0, 0], "size": [10, 10], "colour": [1, 0, 0] }},
+    "entities": [
+        {{ "top_left": [0, 50], "shape": {{ "type": "rect", "size": [10, 10] }},
+           "colour": [1, 1, 1], "velocity": [1, 0] }},
+        {{ "top_left": [0, 90], "shape": {}, "colour": [1, 1, 1]{} }}
+    ]
+}}"#,
+                shape, movement
+            )
+        };
+        let segment = r#"{ "type": "line_segment", "start": [0, 0], "end": [10, 0] }"#;
+        let chain = r#"{ "type": "edge_chain", "vertices": [[0, 0], [10, 0]] }"#;
+        assert!(Level::parse("test.json", &level(segment, "")).is_ok());
+
+        let (line, _, message) = parse_error(&level(segment, r#", "velocity": [1, 0]"#));
+        assert_eq!(line, 9);
+        assert!(message.contains("line segments can't move"), "{}", message);
+
+        let behaviour = r#", "behaviour": { "mode": "patrol", "speed": 1 }"#;
+        let (line, _, message) = parse_error(&level(chain, behaviour));
+        assert_eq!(line, 9);
+        assert!(message.contains("edge chains can't move"), "{}", message);
+    }
+
+    #[test]
+    fn error_display_leads_with_path() {
+        let error = Level::parse("levels/broken.json", "{\n  \"version\": 1,\n  oops\n}")
+            .unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.starts_with("levels/broken.json:3:3: "),
+            "{}",
+            message
+        );
+        assert!(!message.contains("at line"), "{}", message);
+    }
+}
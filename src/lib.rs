@@ -0,0 +1,76 @@
+//! Collision detection, movement and level loading for a 2D platform game.
+//!
+//! `GameState` holds the entities of a level and steps their movement against
+//! static geometry stored in a broad phase such as `LooseQuadTree`. Rendering with
+//! gfx and glutin is behind the default `graphics` feature, and nothing else
+//! depends on it. Running entity scripts written in Rhai is behind the optional
+//! `scripting` feature. The level editor, rewinding and the benchmark, headless and
+//! determinism tools live in the binaries which use them.
+
+extern crate best;
+extern crate cgmath;
+#[macro_use]
+extern crate custom_derive;
+extern crate fnv;
+#[cfg(feature = "graphics")]
+#[macro_use]
+extern crate gfx;
+#[cfg(feature = "graphics")]
+extern crate gfx_device_gl;
+#[cfg(feature = "graphics")]
+extern crate gfx_window_glutin;
+#[cfg(feature = "graphics")]
+extern crate glutin;
+#[macro_use]
+extern crate newtype_derive;
+extern crate num;
+#[cfg(test)]
+extern crate proptest;
+#[cfg(feature = "scripting")]
+extern crate rhai;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+pub mod aabb;
+pub mod arith;
+pub mod behaviour;
+pub mod broad_phase;
+mod collision;
+pub mod combat;
+pub mod component;
+pub mod edge_chain;
+pub mod game;
+#[cfg(feature = "graphics")]
+pub mod glutin_window;
+#[cfg(feature = "graphics")]
+pub mod graphics;
+pub mod level;
+pub mod line_segment;
+pub mod loose_quad_tree;
+pub mod navigation;
+pub mod replay;
+pub mod rng;
+#[cfg(test)]
+mod scenario;
+pub mod script;
+#[cfg(feature = "scripting")]
+pub mod script_system;
+pub mod shape;
+pub mod snapshot;
+pub mod sweep_and_prune;
+#[cfg(test)]
+mod test_util;
+pub mod tiled;
+pub mod tilemap;
+pub mod trigger;
+pub mod uniform_grid;
+
+pub use aabb::Aabb;
+pub use broad_phase::{BroadPhase, BroadPhaseKind};
+pub use game::{EntityCommon, EntityId, GameState, InputModel};
+pub use level::{Level, LevelError};
+pub use line_segment::LineSegment;
+pub use loose_quad_tree::LooseQuadTree;
+pub use shape::Shape;
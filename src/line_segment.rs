@@ -1,6 +1,7 @@
 use aabb::Aabb;
-use cgmath::{vec2, BaseNum, InnerSpace, Vector2};
-use shape::Collide;
+use arith::{self, Scalar};
+use cgmath::{vec2, BaseNum, Vector2};
+use shape::{Collide, MovementTime};
 
 #[derive(Debug, Clone, Copy)]
 pub struct LineSegment<N> {
@@ -22,18 +23,12 @@ pub enum IntersectionNone {
 }
 
 #[derive(Debug, Clone, Copy)]
-pub enum IntersectionOrSlide {
-    IntersectionWithVectorMultiplier(f32),
+pub enum IntersectionOrSlide<N> {
+    IntersectionWithVectorMultiplier(MovementTime<N>),
     Slide(IntersectionSlide),
 }
 
-pub type IntersectionResult = Result<IntersectionOrSlide, IntersectionNone>;
-
-fn vector2_cross_product(v: Vector2<f32>, w: Vector2<f32>) -> f32 {
-    v.x * w.y - v.y * w.x
-}
-
-const EPSILON: f32 = 0.001;
+pub type IntersectionResult<N> = Result<IntersectionOrSlide<N>, IntersectionNone>;
 
 impl<N: BaseNum> LineSegment<N> {
     pub fn new(start: Vector2<N>, end: Vector2<N>) -> Self {
@@ -50,8 +45,8 @@ impl<N: BaseNum> LineSegment<N> {
     }
 }
 
-impl LineSegment<f32> {
-    pub fn intersection(&self, other: &LineSegment<f32>) -> IntersectionResult {
+impl<N: Scalar> LineSegment<N> {
+    pub fn intersection(&self, other: &LineSegment<N>) -> IntersectionResult<N> {
         // treat self as  p + tr for t in 0..1
         // treat other as q + us for u in 0..1
         // if we define * on vectors v, w to mean v.x * w.y - v.y * w.x
@@ -61,18 +56,23 @@ impl LineSegment<f32> {
         let q = other.start;
         let r = self.vector();
         let s = other.vector();
-        let rxs = vector2_cross_product(r, s);
+        let epsilon = N::tolerance();
+        let rxs = arith::cross(r, s);
         let p_to_q = q - p;
-        if rxs.abs() < EPSILON {
+        if rxs.abs() <= epsilon {
             // lines are parallel
-            if vector2_cross_product(p_to_q, r).abs() < EPSILON {
+            // the distance between them is scaled by the length of whichever segment
+            // is used to measure it, so use the shorter one in both argument orders
+            let offset =
+                arith::min(arith::cross(p_to_q, r).abs(), arith::cross(p_to_q, s).abs());
+            if offset <= epsilon {
                 // lines are colinear
-                let r_len2 = r.dot(r);
-                let t0 = p_to_q.dot(r);
-                let t1 = (p_to_q + s).dot(r);
+                let r_len2 = arith::dot(r, r);
+                let t0 = arith::dot(p_to_q, r);
+                let t1 = arith::dot(p_to_q + s, r);
                 // the range t0..t1 will overlap with 0..r_len2 iff the lines overlap
                 if t0 < t1 {
-                    if t0 <= r_len2 && t1 >= 0. {
+                    if t0 <= r_len2 && t1 >= N::zero() {
                         return Ok(IntersectionOrSlide::Slide(
                             IntersectionSlide::Colinear,
                         ));
@@ -80,7 +80,7 @@ impl LineSegment<f32> {
                         return Err(IntersectionNone::ColinearNonOverlapping);
                     }
                 } else {
-                    if t1 <= r_len2 && t0 >= 0. {
+                    if t1 <= r_len2 && t0 >= N::zero() {
                         return Ok(IntersectionOrSlide::Slide(
                             IntersectionSlide::Colinear,
                         ));
@@ -93,46 +93,69 @@ impl LineSegment<f32> {
                 return Err(IntersectionNone::ParallelNonColinear);
             }
         }
-        let t = vector2_cross_product(p_to_q, s) / rxs;
-        if t < -EPSILON || t > 1. + EPSILON {
+        // t and u are compared as fractions over rxs, so that the comparisons are
+        // exact for integer scalars
+        let sign = rxs.signum();
+        let denominator = rxs.abs();
+        let t_numerator = arith::cross(p_to_q, s) * sign;
+        let u_numerator = arith::cross(p_to_q, r) * sign;
+        // how close t or u is to an end is measured as a distance along the segment,
+        // so that the tolerance is the same for long and short segments, and is never
+        // more than the tolerance as a fraction of the segment
+        let tolerance = epsilon * denominator;
+        let is_near_zero = |numerator: N, len2: N| {
+            numerator.abs() <= tolerance
+                && (tolerance.is_zero()
+                    || numerator * numerator * len2 <= tolerance * tolerance)
+        };
+        let is_outside = |numerator: N, len2: N| {
+            (numerator < N::zero() && !is_near_zero(numerator, len2))
+                || (numerator > denominator
+                    && !is_near_zero(numerator - denominator, len2))
+        };
+        let s_len2 = arith::dot(s, s);
+        if is_outside(t_numerator, arith::dot(r, r)) {
             return Err(IntersectionNone::NonParallelNonIntersecting);
         }
-        let u = vector2_cross_product(p_to_q, r) / rxs;
-        if u.abs() < EPSILON || (u - 1.).abs() < EPSILON {
+        // hits this close to an end of other are left to the test of that end
+        // against the edges of self
+        if is_near_zero(u_numerator, s_len2)
+            || is_near_zero(u_numerator - denominator, s_len2)
+        {
             return Ok(IntersectionOrSlide::Slide(IntersectionSlide::Vertex));
         }
-        if u < -EPSILON || u > 1. + EPSILON {
+        if is_outside(u_numerator, s_len2) {
             return Err(IntersectionNone::NonParallelNonIntersecting);
         }
-        println!("{:?}", t);
+        let t = MovementTime::new(t_numerator, denominator);
         Ok(IntersectionOrSlide::IntersectionWithVectorMultiplier(
-            (t - EPSILON).max(0.),
+            t.less_tolerance(),
         ))
     }
 }
 
-impl Collide for LineSegment<f32> {
-    fn aabb(&self, top_left: Vector2<f32>) -> Aabb {
+impl<N: Scalar> Collide<N> for LineSegment<N> {
+    fn aabb(&self, top_left: Vector2<N>) -> Aabb<N> {
         let start = self.start + top_left;
         let end = self.end + top_left;
-        let x_min = start.x.min(end.x);
-        let x_max = start.x.max(end.x);
-        let y_min = start.y.min(end.y);
-        let y_max = start.y.max(end.y);
+        let x_min = arith::min(start.x, end.x);
+        let x_max = arith::max(start.x, end.x);
+        let y_min = arith::min(start.y, end.y);
+        let y_max = arith::max(start.y, end.y);
         let top_left = vec2(x_min, y_min);
         let bottom_right = vec2(x_max, y_max);
         Aabb::new(top_left, bottom_right - top_left)
     }
-    fn for_each_edge_facing<F: FnMut(LineSegment<f32>)>(
+    fn for_each_edge_facing<F: FnMut(LineSegment<N>)>(
         &self,
-        _direction: Vector2<f32>,
+        _direction: Vector2<N>,
         mut f: F,
     ) {
         f(*self);
     }
-    fn for_each_vertex_facing<F: FnMut(Vector2<f32>)>(
+    fn for_each_vertex_facing<F: FnMut(Vector2<N>)>(
         &self,
-        _direction: Vector2<f32>,
+        _direction: Vector2<N>,
         mut f: F,
     ) {
         f(self.start);
@@ -143,20 +166,21 @@ impl Collide for LineSegment<f32> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use cgmath::vec2;
+    use cgmath::{vec2, InnerSpace};
+    use proptest::prelude::*;
 
-    impl IntersectionOrSlide {
-        pub fn intersection_vector_multiplier(&self) -> Option<f32> {
+    impl<N: Scalar> IntersectionOrSlide<N> {
+        pub fn intersection_vector_multiplier(&self) -> Option<N> {
             match self {
                 &IntersectionOrSlide::IntersectionWithVectorMultiplier(multiplier) => {
-                    Some(multiplier)
+                    Some(multiplier.ratio())
                 }
                 _ => None,
             }
         }
     }
 
-    fn expect_multiplier(intersection: IntersectionResult, multiplier: f32) {
+    fn expect_multiplier(intersection: IntersectionResult<f32>, multiplier: f32) {
         match intersection.unwrap().intersection_vector_multiplier() {
             None => panic!("{:?}", intersection),
             Some(m) => {
@@ -256,12 +280,98 @@ mod test {
 
     #[test]
     fn nearby_lines_intersect() {
-        const DIFF: f32 = EPSILON * EPSILON;
-        let a = LineSegment::new(vec2(10., 10. - DIFF), vec2(20., 10. - DIFF));
-        let b = LineSegment::new(vec2(10., 10. + DIFF), vec2(20., 10. + DIFF));
+        let diff = f32::tolerance() * f32::tolerance();
+        let a = LineSegment::new(vec2(10., 10. - diff), vec2(20., 10. - diff));
+        let b = LineSegment::new(vec2(10., 10. + diff), vec2(20., 10. + diff));
         match a.intersection(&b).unwrap() {
             IntersectionOrSlide::Slide(IntersectionSlide::Colinear) => (),
             other => panic!("{:?}", other),
         }
     }
+
+    fn coordinate() -> impl Strategy<Value = f32> {
+        // Small integers make parallel, colinear and touching segments likely.
+        prop_oneof![(-8i32..8).prop_map(|x| x as f32), -100f32..100.]
+    }
+
+    prop_compose! {
+        fn line_segment()(
+            start_x in coordinate(),
+            start_y in coordinate(),
+            end_x in coordinate(),
+            end_y in coordinate(),
+        ) -> LineSegment<f32> {
+            LineSegment::new(vec2(start_x, start_y), vec2(end_x, end_y))
+        }
+    }
+
+    fn is_degenerate(line_segment: &LineSegment<f32>) -> bool {
+        line_segment.vector().magnitude() < 0.1
+    }
+
+    prop_compose! {
+        /// A segment and a parallel one, moved sideways by around the tolerance.
+        fn parallel_pair()(
+            a in line_segment().prop_filter("degenerate", |s| !is_degenerate(s)),
+            start in -2f32..2.,
+            end in -2f32..2.,
+            nudge in -2f32..2.,
+        ) -> (LineSegment<f32>, LineSegment<f32>) {
+            let direction = a.vector();
+            let normal = vec2(-direction.y, direction.x).normalize();
+            let offset = normal * nudge * f32::tolerance();
+            let b = LineSegment::new(
+                a.start + direction * start + offset,
+                a.start + direction * end + offset,
+            );
+            (a, b)
+        }
+    }
+
+    fn pair() -> impl Strategy<Value = (LineSegment<f32>, LineSegment<f32>)> {
+        prop_oneof![(line_segment(), line_segment()), parallel_pair()]
+            .prop_filter("degenerate", |&(ref a, ref b)| {
+                !is_degenerate(a) && !is_degenerate(b)
+            })
+    }
+
+    fn is_colinear(intersection: &IntersectionResult<f32>) -> bool {
+        match intersection {
+            &Ok(IntersectionOrSlide::Slide(IntersectionSlide::Colinear)) => true,
+            _ => false,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn intersection_is_symmetric((a, b) in pair()) {
+            let ab = a.intersection(&b);
+            let ba = b.intersection(&a);
+            prop_assert_eq!(ab.is_ok(), ba.is_ok(), "{:?} vs {:?}", ab, ba);
+            prop_assert_eq!(is_colinear(&ab), is_colinear(&ba), "{:?} vs {:?}", ab, ba);
+            // Where one segment's end touches the other, one side sees an intersection
+            // and the other a vertex slide, so only pairs of intersections are compared.
+            // Where the segments are nearly parallel the point of intersection is too
+            // sensitive to rounding to compare.
+            let sine = arith::cross(a.vector(), b.vector()).abs()
+                / (a.vector().magnitude() * b.vector().magnitude());
+            if let (Some(t), Some(u), true) = (
+                ab.ok().and_then(|i| i.intersection_vector_multiplier()),
+                ba.ok().and_then(|i| i.intersection_vector_multiplier()),
+                sine > 0.01,
+            ) {
+                let on_a = a.start + a.vector() * t;
+                let on_b = b.start + b.vector() * u;
+                // Multipliers are reduced by the tolerance so that movement stops short.
+                let lengths = a.vector().magnitude() + b.vector().magnitude();
+                let tolerance = f32::tolerance() * (lengths + 1.);
+                prop_assert!(
+                    (on_a - on_b).magnitude() <= tolerance,
+                    "{:?} vs {:?}",
+                    on_a,
+                    on_b
+                );
+            }
+        }
+    }
 }
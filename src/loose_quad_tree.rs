@@ -1,23 +1,24 @@
 use aabb::*;
+use arith::Scalar;
 use cgmath::{vec2, Vector2};
 use std::num::NonZeroUsize;
 
 #[derive(Debug, Clone)]
-pub struct LooseQuadTree<T> {
+pub struct LooseQuadTree<T, N> {
     seq: u64,
-    nodes: Vec<Node<T>>,
-    size: Vector2<f32>,
+    nodes: Vec<Node<T, N>>,
+    size: Vector2<N>,
     next_free: usize,
 }
 
 #[derive(Debug, Clone)]
-struct Node<T> {
-    items: Vec<(Aabb, T)>,
+struct Node<T, N> {
+    items: Vec<(Aabb<N>, T)>,
     child_offset: Option<NonZeroUsize>,
     seq: u64,
 }
 
-impl<T> Default for Node<T> {
+impl<T, N> Default for Node<T, N> {
     fn default() -> Self {
         Self {
             items: Vec::new(),
@@ -26,7 +27,7 @@ impl<T> Default for Node<T> {
         }
     }
 }
-impl<T> Node<T> {
+impl<T, N> Node<T, N> {
     fn reuse(&mut self, seq: u64) {
         self.items.clear();
         self.child_offset = None;
@@ -34,14 +35,14 @@ impl<T> Node<T> {
     }
 }
 
-impl<T> LooseQuadTree<T> {
+impl<T, N: Scalar> LooseQuadTree<T, N> {
     const TOP_LEFT: usize = 0;
     const TOP_RIGHT: usize = 1;
     const BOTTOM_LEFT: usize = 2;
     const BOTTOM_RIGHT: usize = 3;
     const NUM_CHILDREN: usize = 4;
 
-    pub fn new(size: Vector2<f32>) -> Self {
+    pub fn new(size: Vector2<N>) -> Self {
         Self {
             seq: 1,
             nodes: vec![Default::default()],
@@ -55,10 +56,10 @@ impl<T> LooseQuadTree<T> {
         self.nodes[0].reuse(self.seq);
     }
 
-    pub fn insert(&mut self, aabb: Aabb, t: T) {
+    pub fn insert(&mut self, aabb: Aabb<N>, t: T) {
         let mut centre = aabb.centre();
         let mut index = 0;
-        let mut max_size = self.size / 2.;
+        let mut max_size = self.size / N::two();
         let nodes = &mut self.nodes;
         let next_free = &mut self.next_free;
         loop {
@@ -99,15 +100,63 @@ impl<T> LooseQuadTree<T> {
                     centre = centre - max_size;
                 }
             }
-            max_size = max_size / 2.;
+            max_size = max_size / N::two();
         }
     }
 
-    fn for_each_intersection_rec<F: FnMut(&Aabb, &T)>(
-        nodes: &[Node<T>],
+    fn index_of_node_containing(&self, aabb: &Aabb<N>) -> Option<usize> {
+        let mut centre = aabb.centre();
+        let mut index = 0;
+        let mut max_size = self.size / N::two();
+        loop {
+            let node = self.nodes.get(index)?;
+            if node.seq != self.seq {
+                return None;
+            }
+            let size = aabb.size();
+            if size.x > max_size.x || size.y > max_size.y {
+                return Some(index);
+            }
+            let child_offset = node.child_offset?.get() as usize;
+            if centre.x < max_size.x {
+                if centre.y < max_size.y {
+                    index = child_offset + Self::TOP_LEFT;
+                } else {
+                    index = child_offset + Self::BOTTOM_LEFT;
+                    centre.y = centre.y - max_size.y;
+                }
+            } else {
+                if centre.y < max_size.y {
+                    index = child_offset + Self::TOP_RIGHT;
+                    centre.x = centre.x - max_size.x;
+                } else {
+                    index = child_offset + Self::BOTTOM_RIGHT;
+                    centre = centre - max_size;
+                }
+            }
+            max_size = max_size / N::two();
+        }
+    }
+
+    /// Removes the first item for which `predicate` returns true from the node that
+    /// `aabb` would have been inserted into. `aabb` must be the aabb the item was
+    /// inserted with.
+    pub fn remove<P: FnMut(&T) -> bool>(
+        &mut self,
+        aabb: &Aabb<N>,
+        mut predicate: P,
+    ) -> Option<T> {
+        let index = self.index_of_node_containing(aabb)?;
+        let items = &mut self.nodes[index].items;
+        let position = items.iter().position(|&(_, ref t)| predicate(t))?;
+        Some(items.swap_remove(position).1)
+    }
+
+    fn for_each_intersection_rec<F: FnMut(&Aabb<N>, &T)>(
+        nodes: &[Node<T, N>],
         current_index: usize,
-        current_node_aabb: &Aabb,
-        aabb_to_test: &Aabb,
+        current_node_aabb: &Aabb<N>,
+        aabb_to_test: &Aabb<N>,
         f: &mut F,
     ) {
         if let Some(node) = nodes.get(current_index) {
@@ -176,8 +225,174 @@ impl<T> LooseQuadTree<T> {
         }
     }
 
-    pub fn for_each_intersection<F: FnMut(&Aabb, &T)>(&self, aabb: &Aabb, mut f: F) {
-        let root_aabb = Aabb::new(vec2(0., 0.), self.size);
+    pub fn for_each_intersection<F: FnMut(&Aabb<N>, &T)>(
+        &self,
+        aabb: &Aabb<N>,
+        mut f: F,
+    ) {
+        let root_aabb = Aabb::new(vec2(N::zero(), N::zero()), self.size);
         Self::for_each_intersection_rec(&self.nodes, 0, &root_aabb, aabb, &mut f);
     }
+
+    fn for_each_overlapping_pair_rec<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(
+        nodes: &[Node<T, N>],
+        current_index: usize,
+        current_node_aabb: &Aabb<N>,
+        f: &mut F,
+    ) {
+        if let Some(node) = nodes.get(current_index) {
+            let children = node.child_offset.map(|child_offset| {
+                let child_offset = child_offset.get() as usize;
+                let AabbSplitFour {
+                    top_left,
+                    top_right,
+                    bottom_left,
+                    bottom_right,
+                } = current_node_aabb.split_four();
+                [
+                    (child_offset + Self::TOP_LEFT, top_left),
+                    (child_offset + Self::TOP_RIGHT, top_right),
+                    (child_offset + Self::BOTTOM_LEFT, bottom_left),
+                    (child_offset + Self::BOTTOM_RIGHT, bottom_right),
+                ]
+            });
+            for (i, &(ref aabb, ref t)) in node.items.iter().enumerate() {
+                // pairs within this node
+                for &(ref other_aabb, ref other_t) in node.items[(i + 1)..].iter() {
+                    if aabb.is_intersecting(other_aabb) {
+                        f(aabb, t, other_aabb, other_t);
+                    }
+                }
+                // pairs between this node and its descendants
+                if let Some(ref children) = children {
+                    for &(child_index, ref child_aabb) in children.iter() {
+                        if child_aabb.double_about_centre().is_intersecting(aabb) {
+                            Self::for_each_intersection_rec(
+                                nodes,
+                                child_index,
+                                child_aabb,
+                                aabb,
+                                &mut |other_aabb, other_t| {
+                                    f(aabb, t, other_aabb, other_t)
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+            if let Some(ref children) = children {
+                // Loose bounds of siblings overlap, so items in different child
+                // subtrees can intersect. Each such pair is found here, at the
+                // lowest common ancestor of the two nodes containing its items.
+                for (i, &(index_a, ref aabb_a)) in children.iter().enumerate() {
+                    let loose_a = aabb_a.double_about_centre();
+                    for &(index_b, ref aabb_b) in children[(i + 1)..].iter() {
+                        let loose_b = aabb_b.double_about_centre();
+                        if !loose_a.is_intersecting(&loose_b) {
+                            continue;
+                        }
+                        Self::for_each_intersection_rec(
+                            nodes,
+                            index_a,
+                            aabb_a,
+                            &loose_b,
+                            &mut |item_aabb_a, t_a| {
+                                Self::for_each_intersection_rec(
+                                    nodes,
+                                    index_b,
+                                    aabb_b,
+                                    item_aabb_a,
+                                    &mut |item_aabb_b, t_b| {
+                                        f(item_aabb_a, t_a, item_aabb_b, t_b)
+                                    },
+                                );
+                            },
+                        );
+                    }
+                }
+                for &(child_index, ref child_aabb) in children.iter() {
+                    Self::for_each_overlapping_pair_rec(
+                        nodes,
+                        child_index,
+                        child_aabb,
+                        f,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Calls `f` exactly once for each unordered pair of items whose aabbs intersect.
+    /// Each node is tested against itself and its descendants, and each pair of
+    /// sibling subtrees is tested against each other, so no pair is visited twice.
+    pub fn for_each_overlapping_pair<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(
+        &self,
+        mut f: F,
+    ) {
+        let root_aabb = Aabb::new(vec2(N::zero(), N::zero()), self.size);
+        Self::for_each_overlapping_pair_rec(&self.nodes, 0, &root_aabb, &mut f);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arith::PixelI64;
+
+    fn n<N: Scalar>(x: i64) -> N {
+        N::from(x).unwrap()
+    }
+
+    fn aabbs<N: Scalar>() -> Vec<Aabb<N>> {
+        let mut aabbs = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let size = vec2(n(3 + (i * 7 + j * 3) % 40), n(2 + (i + j * 5) % 90));
+                aabbs.push(Aabb::new(vec2(n(i * 24), n(j * 31)), size));
+            }
+        }
+        aabbs
+    }
+
+    fn brute_force_pairs<N: Scalar>(aabbs: &[Aabb<N>]) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for i in 0..aabbs.len() {
+            for j in (i + 1)..aabbs.len() {
+                if aabbs[i].is_intersecting(&aabbs[j]) {
+                    pairs.push((i, j));
+                }
+            }
+        }
+        pairs
+    }
+
+    fn overlapping_pairs_match_brute_force<N: Scalar>() {
+        let aabbs = aabbs::<N>();
+        let mut tree = LooseQuadTree::new(vec2(n(480), n(620)));
+        for (i, aabb) in aabbs.iter().enumerate() {
+            tree.insert(*aabb, i);
+        }
+        let mut pairs = Vec::new();
+        tree.for_each_overlapping_pair(|_, &a, _, &b| pairs.push((a.min(b), a.max(b))));
+        let num_pairs = pairs.len();
+        pairs.sort();
+        pairs.dedup();
+        assert_eq!(pairs.len(), num_pairs, "a pair was reported more than once");
+        assert_eq!(pairs, brute_force_pairs(&aabbs));
+    }
+
+    #[test]
+    fn overlapping_pairs_match_brute_force_f32() {
+        overlapping_pairs_match_brute_force::<f32>();
+    }
+
+    #[test]
+    fn overlapping_pairs_match_brute_force_f64() {
+        overlapping_pairs_match_brute_force::<f64>();
+    }
+
+    #[test]
+    fn overlapping_pairs_match_brute_force_fixed_point() {
+        overlapping_pairs_match_brute_force::<PixelI64>();
+    }
 }
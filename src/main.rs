@@ -1,58 +1,217 @@
-#![feature(nonzero)]
-extern crate best;
 extern crate cgmath;
-#[macro_use]
-extern crate custom_derive;
 extern crate fnv;
-#[macro_use]
 extern crate gfx;
-extern crate gfx_device_gl;
-extern crate gfx_window_glutin;
 extern crate glutin;
-#[macro_use]
-extern crate newtype_derive;
-extern crate num;
-
-mod aabb;
-mod arith;
-mod collision;
-mod game;
-mod glutin_window;
-mod graphics;
-mod line_segment;
-mod loose_quad_tree;
-mod shape;
-
-use cgmath::vec2;
-use game::{GameState, InputModel};
+extern crate platform_game_experiment;
+
+mod editor;
+mod file_watcher;
+mod rewind;
+
+use cgmath::{vec2, Vector2};
+use editor::{Editor, Tool};
+use file_watcher::FileWatcher;
 use gfx::Device;
 use glutin::GlContext;
-use glutin_window::GlutinWindow;
-use graphics::Renderer;
-use shape::Shape;
+use platform_game_experiment::broad_phase::BroadPhaseKind;
+use platform_game_experiment::game::InputModel;
+use platform_game_experiment::glutin_window::GlutinWindow;
+use platform_game_experiment::graphics::Renderer;
+use platform_game_experiment::level::LevelSource;
+use platform_game_experiment::replay::{LevelIdentity, Recording, Replay};
+use platform_game_experiment::shape::Shape;
+use platform_game_experiment::snapshot::Snapshot;
+use rewind::Rewind;
+use std::time::Duration;
+
+const DEFAULT_LEVEL_PATH: &str = "levels/demo.json";
+const LEVEL_POLL_INTERVAL_MS: u64 = 250;
+const QUICKSAVE_PATH: &str = "quicksave.json";
+const REWIND_MAX_BYTES: usize = 16 << 20;
+
+struct Args {
+    level_source: LevelSource,
+    snapshot_path: Option<String>,
+    record_path: Option<String>,
+    replay_path: Option<String>,
+    seed: u64,
+    broad_phase_kind: BroadPhaseKind,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut level_source = LevelSource::Level(DEFAULT_LEVEL_PATH.to_string());
+        let mut snapshot_path = None;
+        let mut record_path = None;
+        let mut replay_path = None;
+        let mut seed = 0;
+        let mut broad_phase_kind = BroadPhaseKind::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--level" | "--tiled-map" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| format!("expected a path after {}", arg))?;
+                    level_source = if arg == "--level" {
+                        LevelSource::Level(path)
+                    } else {
+                        LevelSource::TiledMap(path)
+                    };
+                }
+                "--snapshot" => {
+                    snapshot_path = Some(
+                        args.next()
+                            .ok_or_else(|| format!("expected a path after {}", arg))?,
+                    );
+                }
+                "--record" | "--replay" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| format!("expected a path after {}", arg))?;
+                    if arg == "--record" {
+                        record_path = Some(path);
+                    } else {
+                        replay_path = Some(path);
+                    }
+                }
+                "--seed" => {
+                    seed = args
+                        .next()
+                        .and_then(|seed| seed.parse().ok())
+                        .ok_or_else(|| format!("expected a number after {}", arg))?;
+                }
+                "--broad-phase" => {
+                    let name = args.next();
+                    broad_phase_kind = name
+                        .as_ref()
+                        .and_then(|name| BroadPhaseKind::from_name(name))
+                        .ok_or_else(|| {
+                            format!(
+                                "expected one of quad-tree, grid or sweep-and-prune \
+                                 after --broad-phase, got {:?}",
+                                name
+                            )
+                        })?;
+                }
+                other => return Err(format!("unexpected argument {:?}", other)),
+            }
+        }
+        if snapshot_path.is_some() && (record_path.is_some() || replay_path.is_some()) {
+            // Recordings only identify the level they start from.
+            return Err("--snapshot can't be used with --record or --replay".to_string());
+        }
+        Ok(Self {
+            level_source,
+            snapshot_path,
+            record_path,
+            replay_path,
+            seed,
+            broad_phase_kind,
+        })
+    }
+}
+
+enum EditorEvent {
+    CursorMoved(Vector2<f32>),
+    MousePressed,
+    MouseReleased,
+    SetTool(Tool),
+    ScaleGrid(f32),
+    Delete,
+    Undo,
+    Redo,
+    Save,
+}
 
 enum ExternalEvent {
     Quit,
     Reset,
+    QuickSave,
+    QuickLoad,
+    StartRewind,
+    StopRewind,
+    ToggleEditor,
+    Editor(EditorEvent),
+}
+
+fn editor_key_event(
+    virtual_keycode: glutin::VirtualKeyCode,
+    modifiers: glutin::ModifiersState,
+) -> Option<EditorEvent> {
+    match virtual_keycode {
+        glutin::VirtualKeyCode::Key1 => Some(EditorEvent::SetTool(Tool::Select)),
+        glutin::VirtualKeyCode::Key2 => Some(EditorEvent::SetTool(Tool::Rect)),
+        glutin::VirtualKeyCode::Key3 => Some(EditorEvent::SetTool(Tool::LineSegment)),
+        glutin::VirtualKeyCode::LBracket => Some(EditorEvent::ScaleGrid(0.5)),
+        glutin::VirtualKeyCode::RBracket => Some(EditorEvent::ScaleGrid(2.)),
+        glutin::VirtualKeyCode::Delete | glutin::VirtualKeyCode::Back => {
+            Some(EditorEvent::Delete)
+        }
+        glutin::VirtualKeyCode::Z if modifiers.ctrl => Some(EditorEvent::Undo),
+        glutin::VirtualKeyCode::Y if modifiers.ctrl => Some(EditorEvent::Redo),
+        glutin::VirtualKeyCode::S if modifiers.ctrl => Some(EditorEvent::Save),
+        _ => None,
+    }
 }
 
 fn process_input(
     events_loop: &mut glutin::EventsLoop,
     input_model: &mut InputModel,
-) -> Option<ExternalEvent> {
-    let mut external_event = None;
+    editing: bool,
+) -> Vec<ExternalEvent> {
+    let mut external_events = Vec::new();
 
     events_loop.poll_events(|event| match event {
         glutin::Event::WindowEvent { event, .. } => match event {
             glutin::WindowEvent::CloseRequested => {
-                external_event = Some(ExternalEvent::Quit);
+                external_events.push(ExternalEvent::Quit);
+            }
+            glutin::WindowEvent::CursorMoved { position, .. } => {
+                if editing {
+                    let position = vec2(position.0 as f32, position.1 as f32);
+                    external_events
+                        .push(ExternalEvent::Editor(EditorEvent::CursorMoved(position)));
+                }
+            }
+            glutin::WindowEvent::MouseInput {
+                state,
+                button: glutin::MouseButton::Left,
+                ..
+            } => {
+                if editing {
+                    external_events.push(ExternalEvent::Editor(match state {
+                        glutin::ElementState::Pressed => EditorEvent::MousePressed,
+                        glutin::ElementState::Released => EditorEvent::MouseReleased,
+                    }));
+                }
             }
             glutin::WindowEvent::KeyboardInput { input, .. } => {
                 if let Some(virtual_keycode) = input.virtual_keycode {
                     match input.state {
                         glutin::ElementState::Pressed => match virtual_keycode {
                             glutin::VirtualKeyCode::Return => {
-                                external_event = Some(ExternalEvent::Reset)
+                                external_events.push(ExternalEvent::Reset)
+                            }
+                            glutin::VirtualKeyCode::F5 if !editing => {
+                                external_events.push(ExternalEvent::QuickSave)
+                            }
+                            glutin::VirtualKeyCode::F9 if !editing => {
+                                external_events.push(ExternalEvent::QuickLoad)
+                            }
+                            glutin::VirtualKeyCode::R if !editing => {
+                                external_events.push(ExternalEvent::StartRewind)
+                            }
+                            glutin::VirtualKeyCode::E => {
+                                external_events.push(ExternalEvent::ToggleEditor)
+                            }
+                            _ if editing => {
+                                if let Some(editor_event) =
+                                    editor_key_event(virtual_keycode, input.modifiers)
+                                {
+                                    external_events
+                                        .push(ExternalEvent::Editor(editor_event))
+                                }
                             }
                             glutin::VirtualKeyCode::Left => input_model.set_left(1.),
                             glutin::VirtualKeyCode::Right => input_model.set_right(1.),
@@ -61,6 +220,9 @@ fn process_input(
                             _ => (),
                         },
                         glutin::ElementState::Released => match virtual_keycode {
+                            glutin::VirtualKeyCode::R => {
+                                external_events.push(ExternalEvent::StopRewind)
+                            }
                             glutin::VirtualKeyCode::Left => input_model.set_left(0.),
                             glutin::VirtualKeyCode::Right => input_model.set_right(0.),
                             glutin::VirtualKeyCode::Up => input_model.set_up(0.),
@@ -75,10 +237,68 @@ fn process_input(
         _ => (),
     });
 
-    external_event
+    external_events
+}
+
+/// Saves the input recorded so far and stops recording. Recordings only hold the
+/// input for each step from the start of the level, so anything else which changes
+/// the game state ends them.
+fn stop_recording(recording: &mut Option<(Recording, String)>, reason: &str) {
+    if let Some((recording, path)) = recording.take() {
+        eprintln!("stopped recording: {}", reason);
+        match recording.save(&path) {
+            Ok(()) => eprintln!("recorded {} steps to {}", recording.len(), path),
+            Err(error) => eprintln!("failed to save recording: {}", error),
+        }
+    }
 }
 
 fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    let level = match args.level_source.load() {
+        Ok(level) => level,
+        Err(error) => {
+            eprintln!("failed to load level: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let level_identity = LevelIdentity::new(args.level_source.path(), &level);
+    let recording_to_replay = args.replay_path.as_ref().map(|path| {
+        let recording = Recording::load(path).unwrap_or_else(|error| {
+            eprintln!("failed to load recording: {}", error);
+            std::process::exit(1);
+        });
+        if let Err(message) = recording.check_level(&level_identity) {
+            eprintln!("{}: {}", path, message);
+            std::process::exit(1);
+        }
+        eprintln!("replaying {} steps", recording.len());
+        recording
+    });
+    let mut replay = recording_to_replay.as_ref().map(Replay::new);
+    let seed = recording_to_replay
+        .as_ref()
+        .map_or(args.seed, |recording| recording.seed());
+    let mut recording = args
+        .record_path
+        .clone()
+        .map(|path| (Recording::new(level_identity.clone(), seed), path));
+    let mut game_state = level.build(args.broad_phase_kind);
+    if let Some(ref path) = args.snapshot_path {
+        match Snapshot::load(path) {
+            Ok(snapshot) => game_state.restore(&snapshot),
+            Err(error) => {
+                eprintln!("failed to load snapshot: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
     let width = 960;
     let height = 640;
     let GlutinWindow {
@@ -94,19 +314,136 @@ fn main() {
     let mut renderer =
         Renderer::new(render_target_view.clone(), &mut factory, &mut encoder);
 
-    let mut game_state = GameState::new(vec2(width as f32, height as f32));
-    game_state.init_demo();
+    let mut quicksave = None;
+    let mut rewind = Rewind::new(&game_state, REWIND_MAX_BYTES);
+    let mut rewinding = false;
 
     let mut input_model = InputModel::default();
 
-    loop {
+    let mut editor = Editor::new(level, args.level_source.save_path());
+    let mut level_watcher = FileWatcher::new(
+        args.level_source.path(),
+        Duration::from_millis(LEVEL_POLL_INTERVAL_MS),
+    );
+    let mut editing = false;
+    let mut cursor = vec2(0., 0.);
+
+    'main: loop {
         encoder.clear(&render_target_view, [0.0, 0.0, 0.0, 1.0]);
-        match process_input(&mut events_loop, &mut input_model) {
-            Some(ExternalEvent::Quit) => break,
-            Some(ExternalEvent::Reset) => game_state.init_demo(),
-            None => (),
+        for external_event in process_input(&mut events_loop, &mut input_model, editing) {
+            match external_event {
+                ExternalEvent::Quit => break 'main,
+                ExternalEvent::Reset => {
+                    stop_recording(&mut recording, "reset");
+                    editor.attach(&mut game_state);
+                }
+                ExternalEvent::QuickSave => {
+                    let snapshot = game_state.snapshot();
+                    if let Err(error) = snapshot.save(QUICKSAVE_PATH) {
+                        eprintln!("failed to save snapshot: {}", error);
+                    }
+                    quicksave = Some(snapshot);
+                }
+                ExternalEvent::QuickLoad => {
+                    if quicksave.is_none() {
+                        quicksave = Snapshot::load(QUICKSAVE_PATH).ok();
+                    }
+                    if let Some(ref snapshot) = quicksave {
+                        stop_recording(&mut recording, "quickloaded");
+                        game_state.restore(snapshot);
+                    }
+                }
+                ExternalEvent::StartRewind => {
+                    if !rewinding {
+                        stop_recording(&mut recording, "rewound");
+                        eprintln!(
+                            "rewinding: {} steps recorded using {} of {} KiB",
+                            rewind.len(),
+                            rewind.bytes_used() / 1024,
+                            rewind.max_bytes() / 1024
+                        );
+                    }
+                    rewinding = true;
+                }
+                ExternalEvent::StopRewind => rewinding = false,
+                ExternalEvent::ToggleEditor => {
+                    editing = !editing;
+                    if editing {
+                        stop_recording(&mut recording, "opened the editor");
+                        editor.attach(&mut game_state);
+                    }
+                }
+                ExternalEvent::Editor(editor_event) => match editor_event {
+                    EditorEvent::CursorMoved(position) => {
+                        cursor = position;
+                        editor.mouse_drag(cursor, &mut game_state);
+                    }
+                    EditorEvent::MousePressed => {
+                        editor.mouse_press(cursor, &mut game_state)
+                    }
+                    EditorEvent::MouseReleased => {
+                        editor.mouse_release(cursor, &mut game_state)
+                    }
+                    EditorEvent::SetTool(tool) => editor.set_tool(tool),
+                    EditorEvent::ScaleGrid(scale) => {
+                        let grid_size = (editor.grid_size() * scale).max(1.).min(128.);
+                        editor.set_grid_size(grid_size);
+                    }
+                    EditorEvent::Delete => editor.delete_selected(&mut game_state),
+                    EditorEvent::Undo => editor.undo(&mut game_state),
+                    EditorEvent::Redo => editor.redo(&mut game_state),
+                    EditorEvent::Save => match editor.save() {
+                        // Don't reload the level that was just saved.
+                        Ok(()) => level_watcher.mark_seen(),
+                        Err(error) => eprintln!("failed to save level: {}", error),
+                    },
+                },
+            }
+        }
+        if level_watcher.poll() {
+            match args.level_source.load() {
+                Ok(level) => {
+                    stop_recording(&mut recording, "reloaded the level");
+                    editor.reload(level, &mut game_state);
+                }
+                Err(error) => eprintln!("failed to reload level: {}", error),
+            }
+        }
+        if editing {
+            // The simulation is paused while editing.
+        } else if rewinding {
+            rewind.step_back(&mut game_state);
+        } else {
+            // A replay's input is used instead of the keyboard until it runs out.
+            let step_input = match replay {
+                Some(ref replay) => replay.next_input(),
+                None => Some(input_model),
+            };
+            if let Some(step_input) = step_input {
+                game_state.update(&step_input);
+                rewind.record(&game_state);
+                if let Some((ref mut recording, _)) = recording {
+                    recording.record(&step_input, &game_state);
+                }
+            }
+            let replay_finished = match replay {
+                Some(_) if step_input.is_none() => true,
+                Some(ref mut replay) => match replay.advance(&game_state) {
+                    Ok(()) => replay.next_input().is_none(),
+                    Err(divergence) => {
+                        eprintln!("{}", divergence);
+                        true
+                    }
+                },
+                None => false,
+            };
+            if replay_finished {
+                if let Some(ref replay) = replay {
+                    eprintln!("replay stopped after {} steps", replay.step());
+                }
+                replay = None;
+            }
         }
-        game_state.update(&input_model);
         {
             let mut frame = renderer.prepare_frame(&mut factory);
             let mut updater = frame.updater();
@@ -122,6 +459,15 @@ fn main() {
                         line_segment.end + common.top_left,
                         common.colour,
                     ),
+                    &Shape::EdgeChain(ref edge_chain) => {
+                        edge_chain.for_each_edge(|edge| {
+                            updater.line_segment(
+                                edge.start + common.top_left,
+                                edge.end + common.top_left,
+                                common.colour,
+                            )
+                        })
+                    }
                 }
             }
         }
@@ -130,4 +476,5 @@ fn main() {
         window.swap_buffers().expect("Failed to swap buffers");
         device.cleanup();
     }
+    stop_recording(&mut recording, "quit");
 }
@@ -0,0 +1,708 @@
+//! A graph of where a walker affected by gravity can get to in a level, built from
+//! its static entities. Nodes are spans of ground the walker can stand on, and
+//! edges are ways of getting from one span to another: walking across a join,
+//! dropping off the end, or jumping.
+
+use aabb::Aabb;
+use cgmath::{vec2, InnerSpace, Vector2};
+use game::{EntityId, GameState};
+use line_segment::LineSegment;
+use shape::{AxisAlignedRect, Shape};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::f32;
+
+/// Gap left between the walker and the ground it takes off from and lands on, so
+/// that arcs don't start or end in contact with it.
+const CLEARANCE: f32 = 0.5;
+/// Spans whose ends are closer than this are joined by walking.
+const JOIN_DISTANCE: f32 = 2.;
+/// Spans narrower than this are left out.
+const MIN_SPAN_WIDTH: f32 = 1.;
+/// Arcs are checked against static entities in at most this many pieces.
+const MAX_ARC_SEGMENTS: usize = 32;
+/// How far an arc can be cut short by static entities before it counts as blocked.
+const ARC_TOLERANCE: f32 = 0.05;
+/// How far the walker's feet can be below a span and still be on it.
+const SNAP_DISTANCE: f32 = 1.;
+
+/// The size and movement of whatever follows the paths, in units and steps. The
+/// game doesn't apply gravity itself, so this is up to the follower to implement.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Walker {
+    pub size: Vector2<f32>,
+    /// Horizontal distance covered each step, on the ground or in the air.
+    pub run_speed: f32,
+    /// Upward speed at the start of a jump.
+    pub jump_speed: f32,
+    /// Added to the downward speed each step.
+    pub gravity: f32,
+    /// The steepest gradient which can be walked on.
+    pub max_slope: f32,
+}
+
+impl Default for Walker {
+    fn default() -> Self {
+        Self {
+            size: vec2(24., 24.),
+            run_speed: 3.,
+            jump_speed: 10.,
+            gravity: 0.5,
+            max_slope: 1.,
+        }
+    }
+}
+
+impl Walker {
+    /// How high the walker's feet get above where it jumped from.
+    pub fn jump_height(&self) -> f32 {
+        self.jump_speed * self.jump_speed / (2. * self.gravity)
+    }
+    fn shape(&self) -> Shape<f32> {
+        Shape::AxisAlignedRect(AxisAlignedRect::new(self.size))
+    }
+    fn top_left(&self, feet: Vector2<f32>) -> Vector2<f32> {
+        feet - vec2(self.size.x / 2., self.size.y)
+    }
+}
+
+/// The middle of the bottom of `aabb`. Paths are between the feet of walkers.
+pub fn feet(aabb: &Aabb<f32>) -> Vector2<f32> {
+    vec2(aabb.centre().x, aabb.bottom_right_coord().y)
+}
+
+pub type SpanId = usize;
+
+/// Part of the top of a static entity where the walker's feet can be, with room
+/// for the walker above it. `start` is left of `end`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub entity: EntityId,
+    pub start: Vector2<f32>,
+    pub end: Vector2<f32>,
+}
+
+impl Span {
+    fn gradient(&self) -> f32 {
+        let v = self.end - self.start;
+        v.y / v.x
+    }
+    /// The point on the span at `x`, which is clamped to the span.
+    pub fn at(&self, x: f32) -> Vector2<f32> {
+        let x = x.max(self.start.x).min(self.end.x);
+        vec2(x, self.start.y + (x - self.start.x) * self.gradient())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+    Walk,
+    /// Jumping at the walker's jump speed.
+    Jump,
+    /// Walking off the end of a span and falling.
+    Drop,
+}
+
+/// A way of getting from one span to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub from: SpanId,
+    pub to: SpanId,
+    pub movement: Move,
+    /// Where on `from` the walker sets off.
+    pub takeoff: Vector2<f32>,
+    /// Where on `to` the walker ends up.
+    pub landing: Vector2<f32>,
+    /// Steps taken between setting off and landing.
+    pub time: f32,
+}
+
+/// One part of a path, for a follower to carry out in order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathStep {
+    pub movement: Move,
+    pub from: Vector2<f32>,
+    pub to: Vector2<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub steps: Vec<PathStep>,
+    /// The number of steps the path takes to follow.
+    pub time: f32,
+}
+
+impl Path {
+    fn walk(&mut self, from: Vector2<f32>, to: Vector2<f32>) {
+        if (to - from).magnitude() < ARC_TOLERANCE {
+            return;
+        }
+        if let Some(step) = self.steps.last_mut() {
+            if step.movement == Move::Walk {
+                step.to = to;
+                return;
+            }
+        }
+        self.steps.push(PathStep {
+            movement: Move::Walk,
+            from,
+            to,
+        });
+    }
+}
+
+/// Upward facing edges of a static entity's shape, left to right, before checking
+/// whether there's room above them.
+fn surfaces(
+    shape: &Shape<f32>,
+    top_left: Vector2<f32>,
+    max_slope: f32,
+) -> Vec<LineSegment<f32>> {
+    let mut surfaces = Vec::new();
+    {
+        let mut add = |segment: LineSegment<f32>| {
+            let segment = segment.add_vector(top_left);
+            let v = segment.vector();
+            if v.x > 0. && (v.y / v.x).abs() <= max_slope {
+                surfaces.push(segment);
+            }
+        };
+        match shape {
+            &Shape::AxisAlignedRect(ref rect) => add(LineSegment::new(
+                vec2(0., 0.),
+                vec2(rect.dimensions().x, 0.),
+            )),
+            // Line segments are solid from both sides.
+            &Shape::LineSegment(segment) => add(if segment.start.x <= segment.end.x {
+                segment
+            } else {
+                LineSegment::new(segment.end, segment.start)
+            }),
+            // Chains are solid on the right of each edge, which is below edges
+            // going left to right.
+            &Shape::EdgeChain(ref edge_chain) => edge_chain.for_each_edge(&mut add),
+        }
+    }
+    surfaces
+}
+
+/// Removes the interval from `start` to `end` from each of `intervals`.
+fn subtract(intervals: &mut Vec<(f32, f32)>, start: f32, end: f32) {
+    let mut remaining = Vec::new();
+    for &(a, b) in intervals.iter() {
+        if end <= a || start >= b {
+            remaining.push((a, b));
+            continue;
+        }
+        if start > a {
+            remaining.push((a, start));
+        }
+        if end < b {
+            remaining.push((end, b));
+        }
+    }
+    *intervals = remaining;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate {
+    priority: f32,
+    state: usize,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    /// Reversed, so that `BinaryHeap` pops the lowest priority first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .partial_cmp(&self.priority)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.state.cmp(&self.state))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Where a walker can stand in a level and how it can get between those places.
+/// The graph doesn't follow changes to the level, so should be rebuilt after
+/// static entities are added, moved or removed.
+pub struct NavGraph {
+    walker: Walker,
+    spans: Vec<Span>,
+    edges: Vec<Edge>,
+    /// Indices into `edges` of the edges from each span.
+    edges_from: Vec<Vec<usize>>,
+}
+
+impl NavGraph {
+    pub fn new(game_state: &GameState, walker: Walker) -> Self {
+        let mut graph = Self {
+            walker,
+            spans: Vec::new(),
+            edges: Vec::new(),
+            edges_from: Vec::new(),
+        };
+        graph.find_spans(game_state);
+        graph.find_edges(game_state);
+        graph
+    }
+
+    fn find_spans(&mut self, game_state: &GameState) {
+        let mut statics = Vec::new();
+        game_state.for_each_static(|id, common, _| {
+            statics.push((id, common.shape.clone(), common.top_left));
+        });
+        statics.sort_by_key(|&(id, ..)| (id.index(), id.generation()));
+        let half_width = self.walker.size.x / 2.;
+        for &(id, ref shape, top_left) in statics.iter() {
+            for surface in surfaces(shape, top_left, self.walker.max_slope) {
+                // Anything solid in the way of the walker standing above the
+                // highest point of the surface takes that part of it away.
+                // One-way platforms can be passed through sideways.
+                let top = surface.start.y.min(surface.end.y);
+                let room = Aabb::new(
+                    vec2(surface.start.x - half_width, top - self.walker.size.y),
+                    vec2(
+                        surface.end.x - surface.start.x + self.walker.size.x,
+                        self.walker.size.y - CLEARANCE,
+                    ),
+                );
+                let mut intervals = vec![(surface.start.x, surface.end.x)];
+                game_state.for_each_static_intersecting(
+                    &room,
+                    |other, common, one_way| {
+                        if other != id && !one_way {
+                            let aabb = common.shape.aabb(common.top_left);
+                            subtract(
+                                &mut intervals,
+                                aabb.top_left().x - half_width,
+                                aabb.bottom_right_coord().x + half_width,
+                            );
+                        }
+                    },
+                );
+                let whole = Span {
+                    entity: id,
+                    start: surface.start,
+                    end: surface.end,
+                };
+                for (start, end) in intervals {
+                    if end - start >= MIN_SPAN_WIDTH {
+                        self.spans.push(Span {
+                            entity: id,
+                            start: whole.at(start),
+                            end: whole.at(end),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn find_edges(&mut self, game_state: &GameState) {
+        self.edges_from = vec![Vec::new(); self.spans.len()];
+        for from in 0..self.spans.len() {
+            for to in 0..self.spans.len() {
+                if from == to {
+                    continue;
+                }
+                let edge = self.walk_edge(from, to).or_else(|| {
+                    if !self.within_reach(from, to) {
+                        return None;
+                    }
+                    self.drop_edge(game_state, from, to)
+                        .or_else(|| self.jump_edge(game_state, from, to))
+                });
+                if let Some(edge) = edge {
+                    self.edges_from[from].push(self.edges.len());
+                    self.edges.push(edge);
+                }
+            }
+        }
+    }
+
+    fn walk_time(&self, from: Vector2<f32>, to: Vector2<f32>) -> f32 {
+        (to.x - from.x).abs() / self.walker.run_speed
+    }
+
+    fn walk_edge(&self, from: SpanId, to: SpanId) -> Option<Edge> {
+        let (a, b) = (&self.spans[from], &self.spans[to]);
+        let (takeoff, landing) = if (a.end - b.start).magnitude() < JOIN_DISTANCE {
+            (a.end, b.start)
+        } else if (a.start - b.end).magnitude() < JOIN_DISTANCE {
+            (a.start, b.end)
+        } else {
+            return None;
+        };
+        Some(Edge {
+            from,
+            to,
+            movement: Move::Walk,
+            takeoff,
+            landing,
+            time: self.walk_time(takeoff, landing),
+        })
+    }
+
+    /// How far above a point on `span` the walker's feet have to be for the walker
+    /// to be clear of it.
+    fn lift(&self, span: &Span) -> f32 {
+        self.walker.size.x / 2. * span.gradient().abs() + CLEARANCE
+    }
+
+    /// Whether a drop or jump from `from` to `to` might be possible, going by how
+    /// far apart they are. Cheap enough to rule out most pairs before any arcs are
+    /// checked against static entities.
+    fn within_reach(&self, from: SpanId, to: SpanId) -> bool {
+        let (a, b) = (&self.spans[from], &self.spans[to]);
+        let walker = &self.walker;
+        // The furthest the walker's feet can fall from any takeoff on `a` to any
+        // landing on `b`, which is negative if `b` is above `a`.
+        let fall =
+            b.start.y.max(b.end.y) - a.start.y.min(a.end.y) + self.lift(a) - self.lift(b);
+        let discriminant =
+            walker.jump_speed * walker.jump_speed + 2. * walker.gravity * fall;
+        if discriminant < 0. {
+            return false;
+        }
+        let time = (walker.jump_speed + discriminant.sqrt()) / walker.gravity;
+        // Drops take off from beyond the end of `a`.
+        let gap = (b.start.x - a.end.x).max(a.start.x - b.end.x)
+            - (walker.size.x / 2. + CLEARANCE);
+        gap <= walker.run_speed * time
+    }
+
+    /// The time taken to get from `takeoff` to `landing`, starting with an upward
+    /// speed of `launch_speed`, if it can be done without hitting static entities.
+    fn arc(
+        &self,
+        game_state: &GameState,
+        takeoff: Vector2<f32>,
+        landing: Vector2<f32>,
+        launch_speed: f32,
+    ) -> Option<f32> {
+        let walker = &self.walker;
+        let rise = takeoff.y - landing.y;
+        let discriminant = launch_speed * launch_speed - 2. * walker.gravity * rise;
+        if discriminant < 0. {
+            return None;
+        }
+        let time = (launch_speed + discriminant.sqrt()) / walker.gravity;
+        let dx = landing.x - takeoff.x;
+        if time <= 0. || dx.abs() > walker.run_speed * time {
+            return None;
+        }
+        let shape = walker.shape();
+        let top_left = |t: f32| {
+            walker.top_left(vec2(
+                takeoff.x + dx * t / time,
+                takeoff.y - launch_speed * t + walker.gravity * t * t / 2.,
+            ))
+        };
+        let segments = (time.ceil() as usize).max(1).min(MAX_ARC_SEGMENTS);
+        let mut previous = top_left(0.);
+        for i in 1..segments + 1 {
+            let next = top_left(time * i as f32 / segments as f32);
+            let reached = game_state.sweep(&shape, previous, next - previous);
+            if (reached - next).magnitude() > ARC_TOLERANCE {
+                return None;
+            }
+            previous = next;
+        }
+        Some(time)
+    }
+
+    /// Walking off either end of `from` and falling onto `to`, landing as close
+    /// below the end as possible.
+    fn drop_edge(
+        &self,
+        game_state: &GameState,
+        from: SpanId,
+        to: SpanId,
+    ) -> Option<Edge> {
+        let (a, b) = (&self.spans[from], &self.spans[to]);
+        let step_off = self.walker.size.x / 2. + CLEARANCE;
+        let ends = [(a.start, -1.), (a.end, 1.)];
+        ends.iter()
+            .filter_map(|&(end, direction)| {
+                let off = end + vec2(direction * step_off, 0.);
+                let landing = b.at(off.x);
+                if (landing.x - off.x) * direction < 0. || landing.y <= off.y {
+                    return None;
+                }
+                let lifted = landing - vec2(0., self.lift(b));
+                let time = self.arc(game_state, off, lifted, 0.)?;
+                Some(Edge {
+                    from,
+                    to,
+                    movement: Move::Drop,
+                    takeoff: end,
+                    landing,
+                    time: time + self.walk_time(end, off),
+                })
+            })
+            .min_by(|x, y| x.time.partial_cmp(&y.time).unwrap_or(Ordering::Equal))
+    }
+
+    /// Jumping from `from` onto `to`. Takeoffs are tried from the ends of `from` and
+    /// from just beyond the ends of `to`, so as to get round it if it's overhead,
+    /// and landings at and just inside the ends of `to`.
+    fn jump_edge(
+        &self,
+        game_state: &GameState,
+        from: SpanId,
+        to: SpanId,
+    ) -> Option<Edge> {
+        let (a, b) = (&self.spans[from], &self.spans[to]);
+        let width = self.walker.size.x;
+        let takeoffs = [a.start.x, a.end.x, b.start.x - width, b.end.x + width];
+        let landings = [
+            b.start.x,
+            b.end.x,
+            b.start.x + width / 2.,
+            b.end.x - width / 2.,
+        ];
+        let mut best: Option<Edge> = None;
+        for &takeoff_x in takeoffs.iter() {
+            if takeoff_x < a.start.x || takeoff_x > a.end.x {
+                continue;
+            }
+            let takeoff = a.at(takeoff_x);
+            for &landing_x in landings.iter() {
+                let landing = b.at(landing_x);
+                let time = match self.arc(
+                    game_state,
+                    takeoff - vec2(0., self.lift(a)),
+                    landing - vec2(0., self.lift(b)),
+                    self.walker.jump_speed,
+                ) {
+                    Some(time) => time,
+                    None => continue,
+                };
+                if best.map_or(true, |best| time < best.time) {
+                    best = Some(Edge {
+                        from,
+                        to,
+                        movement: Move::Jump,
+                        takeoff,
+                        landing,
+                        time,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    pub fn walker(&self) -> &Walker {
+        &self.walker
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        &self.edges
+    }
+
+    /// The span the walker is standing on with its feet at `feet`, or would land on
+    /// if it fell from there.
+    pub fn span_below(&self, feet: Vector2<f32>) -> Option<SpanId> {
+        let half_width = self.walker.size.x / 2.;
+        self.spans
+            .iter()
+            .enumerate()
+            .filter(|&(_, span)| {
+                span.start.x - half_width <= feet.x && feet.x <= span.end.x + half_width
+            })
+            .map(|(id, span)| (id, span.at(feet.x).y - feet.y))
+            .filter(|&(_, depth)| depth > -SNAP_DISTANCE)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))
+            .map(|(id, _)| id)
+    }
+
+    /// The quickest path from the span below `from` to the span below `to`, found
+    /// with A*. Both are positions of the walker's feet.
+    pub fn find_path(&self, from: Vector2<f32>, to: Vector2<f32>) -> Option<Path> {
+        let start_span = self.span_below(from)?;
+        let goal_span = self.span_below(to)?;
+        let start = self.spans[start_span].at(from.x);
+        let goal = self.spans[goal_span].at(to.x);
+        // State 0 is the start, and state `i + 1` is having just taken edge `i`.
+        // Horizontal speed is limited to the run speed in the air too, so the time
+        // to walk to the goal never overestimates.
+        let heuristic = |position: Vector2<f32>| self.walk_time(position, goal);
+        let mut cost = vec![f32::INFINITY; self.edges.len() + 1];
+        let mut previous = vec![None; self.edges.len() + 1];
+        let mut done = vec![false; self.edges.len() + 1];
+        let mut best: Option<(f32, usize)> = None;
+        let mut open = BinaryHeap::new();
+        cost[0] = 0.;
+        open.push(Candidate {
+            priority: heuristic(start),
+            state: 0,
+        });
+        while let Some(Candidate { priority, state }) = open.pop() {
+            if best.map_or(false, |(time, _)| priority >= time) {
+                break;
+            }
+            if done[state] {
+                continue;
+            }
+            done[state] = true;
+            let (span, position) = match state {
+                0 => (start_span, start),
+                _ => {
+                    let edge = &self.edges[state - 1];
+                    (edge.to, edge.landing)
+                }
+            };
+            if span == goal_span {
+                let time = cost[state] + self.walk_time(position, goal);
+                if best.map_or(true, |(best_time, _)| time < best_time) {
+                    best = Some((time, state));
+                }
+            }
+            for &index in self.edges_from[span].iter() {
+                let edge = &self.edges[index];
+                let next_cost =
+                    cost[state] + self.walk_time(position, edge.takeoff) + edge.time;
+                if next_cost < cost[index + 1] {
+                    cost[index + 1] = next_cost;
+                    previous[index + 1] = Some(state);
+                    open.push(Candidate {
+                        priority: next_cost + heuristic(edge.landing),
+                        state: index + 1,
+                    });
+                }
+            }
+        }
+        let (time, mut state) = best?;
+        let mut edges = Vec::new();
+        while state != 0 {
+            edges.push(&self.edges[state - 1]);
+            state = previous[state].expect("state with no previous state");
+        }
+        let mut path = Path {
+            steps: Vec::new(),
+            time,
+        };
+        let mut position = start;
+        for edge in edges.into_iter().rev() {
+            path.walk(position, edge.takeoff);
+            match edge.movement {
+                Move::Walk => path.walk(edge.takeoff, edge.landing),
+                movement => path.steps.push(PathStep {
+                    movement,
+                    from: edge.takeoff,
+                    to: edge.landing,
+                }),
+            }
+            position = edge.landing;
+        }
+        path.walk(position, goal);
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use test_util::rect;
+
+    /// A floor split by a wall too high to jump onto, a platform on the left from
+    /// which the wall can be jumped onto, and a platform too high to reach.
+    fn level() -> GameState {
+        let mut game_state = GameState::new(vec2(600., 320.), Default::default());
+        game_state.add_static_solid(rect(0., 300., 600., 20.));
+        game_state.add_static_solid(rect(150., 220., 100., 10.));
+        game_state.add_static_solid(rect(300., 150., 20., 150.));
+        game_state.add_static_solid(rect(400., 40., 100., 10.));
+        game_state
+    }
+
+    fn graph() -> NavGraph {
+        NavGraph::new(&level(), Walker::default())
+    }
+
+    fn movements(path: &Path) -> Vec<Move> {
+        path.steps.iter().map(|step| step.movement).collect()
+    }
+
+    #[test]
+    fn spans_leave_room_for_the_walker() {
+        let graph = graph();
+        let mut spans = graph
+            .spans()
+            .iter()
+            .map(|span| (span.start.x, span.end.x, span.start.y))
+            .collect::<Vec<_>>();
+        spans.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(
+            spans,
+            vec![
+                (0., 288., 300.),
+                (150., 250., 220.),
+                (300., 320., 150.),
+                (332., 600., 300.),
+                (400., 500., 40.),
+            ]
+        );
+    }
+
+    #[test]
+    fn paths_jump_and_drop_between_spans() {
+        let graph = graph();
+        let path = graph.find_path(vec2(50., 300.), vec2(500., 300.)).unwrap();
+        assert_eq!(
+            movements(&path),
+            vec![
+                Move::Walk,
+                Move::Jump,
+                Move::Jump,
+                Move::Walk,
+                Move::Drop,
+                Move::Walk,
+            ]
+        );
+        assert_eq!(path.steps[0].from, vec2(50., 300.));
+        assert_eq!(path.steps.last().unwrap().to, vec2(500., 300.));
+        for pair in path.steps.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+        }
+        // There's no way back over the wall or up to the high platform.
+        assert_eq!(graph.find_path(vec2(500., 300.), vec2(50., 300.)), None);
+        assert_eq!(graph.find_path(vec2(50., 300.), vec2(450., 40.)), None);
+
+        let path = graph.find_path(vec2(200., 220.), vec2(50., 300.)).unwrap();
+        assert_eq!(movements(&path), vec![Move::Walk, Move::Drop, Move::Walk]);
+        let path = graph.find_path(vec2(450., 260.), vec2(550., 300.)).unwrap();
+        assert_eq!(movements(&path), vec![Move::Walk]);
+        assert_eq!(path.time, 100. / Walker::default().run_speed);
+    }
+
+    #[test]
+    fn spans_out_of_reach_have_no_drops_or_jumps() {
+        let game_state = level();
+        let graph = NavGraph::new(&game_state, Walker::default());
+        let mut out_of_reach = 0;
+        for from in 0..graph.spans().len() {
+            for to in 0..graph.spans().len() {
+                if from == to || graph.within_reach(from, to) {
+                    continue;
+                }
+                out_of_reach += 1;
+                assert_eq!(graph.drop_edge(&game_state, from, to), None);
+                assert_eq!(graph.jump_edge(&game_state, from, to), None);
+            }
+        }
+        assert!(out_of_reach > 0);
+    }
+}
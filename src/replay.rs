@@ -0,0 +1,501 @@
+use fnv::{FnvHashMap, FnvHasher};
+use game::{EntityId, GameState, InputModel};
+use level::{Level, LevelError};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::Serialize;
+use serde_json;
+use snapshot::Snapshot;
+use std::fmt;
+use std::fs;
+use std::hash::Hasher;
+use std::path::Path;
+
+/// Version of the recording format written by this version of the game. Bump this
+/// when making a change to the format which old recordings can't be read with.
+pub const RECORDING_FORMAT_VERSION: u32 = 1;
+
+fn supported_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == RECORDING_FORMAT_VERSION {
+        Ok(version)
+    } else {
+        Err(de::Error::custom(format_args!(
+            "unsupported recording format version {} (expected {})",
+            version, RECORDING_FORMAT_VERSION
+        )))
+    }
+}
+
+/// Unlike the standard library's hasher, FNV gives the same hash of the same bytes
+/// on every build and platform.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+fn hash_json<T: Serialize>(value: &T) -> u64 {
+    let json = serde_json::to_string(value).expect("failed to serialize state");
+    hash_bytes(json.as_bytes())
+}
+
+/// A hash of the state of a game, and of each entity in it, so that two states
+/// which differ can be narrowed down to an entity. Everything a snapshot captures is
+/// hashed. Snapshots list entities by id, so the order entities are stored in
+/// doesn't affect the hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateHash {
+    pub total: u64,
+    /// The player id and the allocated entity ids.
+    pub globals: u64,
+    /// Sorted by id.
+    pub entities: Vec<(EntityId, u64)>,
+}
+
+/// The first thing found to differ between two states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    /// The player id or the allocated entity ids.
+    Globals,
+    /// An entity exists in only one of the states, or differs between them.
+    Entity(EntityId),
+    /// One run ended before the other.
+    Length,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Difference::Globals => write!(f, "the player or allocated ids differ"),
+            &Difference::Entity(id) => write!(
+                f,
+                "entity {} (generation {}) differs",
+                id.index(),
+                id.generation()
+            ),
+            &Difference::Length => write!(f, "one run ended early"),
+        }
+    }
+}
+
+impl StateHash {
+    pub fn new(game_state: &GameState) -> Self {
+        Self::from_snapshot(&game_state.snapshot())
+    }
+
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let globals = hash_json(&(snapshot.player_id, &snapshot.allocator));
+        let entities = snapshot
+            .entities
+            .iter()
+            .map(|entity| (entity.id, hash_json(entity)))
+            .collect();
+        Self::from_parts(globals, entities)
+    }
+
+    fn from_parts(globals: u64, mut entities: Vec<(EntityId, u64)>) -> Self {
+        entities.sort_by_key(|&(id, _)| (id.index(), id.generation()));
+        Self {
+            total: hash_json(&(globals, &entities)),
+            globals,
+            entities,
+        }
+    }
+
+    /// The first difference between the states, checking entities in order of id.
+    pub fn difference(&self, other: &StateHash) -> Option<Difference> {
+        if self.total == other.total {
+            return None;
+        }
+        if self.globals != other.globals {
+            return Some(Difference::Globals);
+        }
+        let ours = self.entities.iter().cloned().collect::<FnvHashMap<_, _>>();
+        let theirs = other.entities.iter().cloned().collect::<FnvHashMap<_, _>>();
+        self.entities
+            .iter()
+            .chain(other.entities.iter())
+            .map(|&(id, _)| id)
+            .filter(|id| ours.get(id) != theirs.get(id))
+            .min_by_key(|id| (id.index(), id.generation()))
+            .map(Difference::Entity)
+    }
+}
+
+/// A hash of everything in the game state which a snapshot captures.
+pub fn checksum(game_state: &GameState) -> u64 {
+    StateHash::new(game_state).total
+}
+
+/// Identifies the level a recording was made on, so that replaying it on a
+/// different or edited level can be detected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelIdentity {
+    pub path: String,
+    pub hash: u64,
+}
+
+impl LevelIdentity {
+    pub fn new(path: &str, level: &Level) -> Self {
+        Self {
+            path: path.to_string(),
+            hash: hash_bytes(level.to_json().as_bytes()),
+        }
+    }
+}
+
+/// The same input held for a number of consecutive steps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InputRun {
+    steps: u32,
+    input: InputModel,
+}
+
+/// Input for a number of steps, written as comma-separated runs of keys held for a
+/// number of steps, such as `right*30,right+up*10,none*20`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputScript {
+    runs: Vec<InputRun>,
+}
+
+impl InputScript {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let runs = text
+            .split(',')
+            .map(|run| {
+                let mut parts = run.trim().splitn(2, '*');
+                let keys = parts.next().unwrap_or("");
+                let steps = match parts.next() {
+                    Some(steps) => steps
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("invalid step count in {:?}", run))?,
+                    None => 1,
+                };
+                let mut input = InputModel::default();
+                for key in keys.split('+').map(str::trim) {
+                    match key {
+                        "left" => input.set_left(1.),
+                        "right" => input.set_right(1.),
+                        "up" => input.set_up(1.),
+                        "down" => input.set_down(1.),
+                        "none" => (),
+                        other => return Err(format!("unknown key {:?}", other)),
+                    }
+                }
+                Ok(InputRun { steps, input })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { runs })
+    }
+
+    /// The number of steps the script has input for.
+    pub fn len(&self) -> usize {
+        self.runs.iter().map(|run| run.steps as usize).sum()
+    }
+
+    /// The input held during `step`, or `None` once the script has run out.
+    pub fn input(&self, mut step: usize) -> Option<InputModel> {
+        for run in self.runs.iter() {
+            if step < run.steps as usize {
+                return Some(run.input);
+            }
+            step -= run.steps as usize;
+        }
+        None
+    }
+}
+
+/// The input for each step of a game, and a checksum of the state after each step.
+/// Only input is recorded, so resetting, rewinding or editing the level while
+/// recording will make replays diverge.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Recording {
+    #[serde(deserialize_with = "supported_version")]
+    version: u32,
+    level: LevelIdentity,
+    /// Seeds any randomness in the simulation.
+    seed: u64,
+    inputs: Vec<InputRun>,
+    checksums: Vec<u64>,
+}
+
+/// The first step at which a replay's state didn't match the recording.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: usize,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "replay diverged at step {} (expected checksum {:016x}, got {:016x})",
+            self.step, self.expected, self.actual
+        )
+    }
+}
+
+impl Recording {
+    pub fn new(level: LevelIdentity, seed: u64) -> Self {
+        Self {
+            version: RECORDING_FORMAT_VERSION,
+            level,
+            seed,
+            inputs: Vec::new(),
+            checksums: Vec::new(),
+        }
+    }
+
+    pub fn level(&self) -> &LevelIdentity {
+        &self.level
+    }
+
+    /// Fails if the recording was made on a different level, or a different
+    /// version of the same level.
+    pub fn check_level(&self, level: &LevelIdentity) -> Result<(), String> {
+        if self.level.hash == level.hash {
+            Ok(())
+        } else {
+            Err(format!(
+                "recording was made on a different version of {}",
+                self.level.path
+            ))
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The number of steps recorded.
+    pub fn len(&self) -> usize {
+        self.checksums.len()
+    }
+
+    /// The input recorded for `step`, or `None` past the end of the recording.
+    pub fn input(&self, mut step: usize) -> Option<InputModel> {
+        for run in self.inputs.iter() {
+            if step < run.steps as usize {
+                return Some(run.input);
+            }
+            step -= run.steps as usize;
+        }
+        None
+    }
+
+    /// Call after each step with the input the step was run with.
+    pub fn record(&mut self, input_model: &InputModel, game_state: &GameState) {
+        let extend = match self.inputs.last_mut() {
+            Some(run) if run.input == *input_model => {
+                run.steps += 1;
+                true
+            }
+            _ => false,
+        };
+        if !extend {
+            self.inputs.push(InputRun {
+                steps: 1,
+                input: *input_model,
+            });
+        }
+        self.checksums.push(checksum(game_state));
+    }
+
+    /// Checks the state after `step` (counting from 0) against the recording.
+    pub fn check(&self, step: usize, game_state: &GameState) -> Result<(), Divergence> {
+        let expected = self.checksums[step];
+        let actual = checksum(game_state);
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(Divergence {
+                step,
+                expected,
+                actual,
+            })
+        }
+    }
+
+    /// Runs every recorded step on `game_state`, which should be in the state the
+    /// recording started from, stopping at the first divergence.
+    pub fn replay(&self, game_state: &mut GameState) -> Result<(), Divergence> {
+        let mut replay = Replay::new(self);
+        while let Some(input_model) = replay.next_input() {
+            game_state.update(&input_model);
+            replay.advance(game_state)?;
+        }
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(path, &text)
+    }
+
+    /// Parses a recording from `text`. `path` is only used in error messages.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let recording: Self =
+            serde_json::from_str(text).map_err(|error| LevelError::Parse {
+                path: path.to_path_buf(),
+                line: error.line(),
+                column: error.column(),
+                error,
+            })?;
+        if recording.inputs.iter().any(|run| run.steps == 0) {
+            return Err(LevelError::Unsupported {
+                path: path.to_path_buf(),
+                message: "recording has an input run of 0 steps".to_string(),
+            });
+        }
+        let steps = recording
+            .inputs
+            .iter()
+            .map(|run| run.steps as usize)
+            .sum::<usize>();
+        if steps != recording.checksums.len() {
+            return Err(LevelError::Unsupported {
+                path: path.to_path_buf(),
+                message: format!(
+                    "recording has input for {} steps but checksums for {}",
+                    steps,
+                    recording.checksums.len()
+                ),
+            });
+        }
+        Ok(recording)
+    }
+
+    /// Recordings are written without whitespace, as they can get long.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize recording")
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LevelError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json() + "\n").map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
+
+/// Feeds a recording's input into a game one step at a time, checking the state
+/// after each step.
+pub struct Replay<'a> {
+    recording: &'a Recording,
+    step: usize,
+    run: usize,
+    run_step: u32,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(recording: &'a Recording) -> Self {
+        Self {
+            recording,
+            step: 0,
+            run: 0,
+            run_step: 0,
+        }
+    }
+
+    /// The number of steps replayed so far.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// The input to run the next step with, or None once every recorded step has
+    /// been replayed.
+    pub fn next_input(&self) -> Option<InputModel> {
+        self.recording.inputs.get(self.run).map(|run| run.input)
+    }
+
+    /// Call after running a step with `next_input`.
+    pub fn advance(&mut self, game_state: &GameState) -> Result<(), Divergence> {
+        let result = self.recording.check(self.step, game_state);
+        self.step += 1;
+        self.run_step += 1;
+        if self.run_step == self.recording.inputs[self.run].steps {
+            self.run += 1;
+            self.run_step = 0;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use broad_phase::BroadPhaseKind;
+
+    const DEMO: &str = include_str!("../levels/demo.json");
+
+    fn input(right: f32, down: f32) -> InputModel {
+        let mut input_model = InputModel::default();
+        input_model.set_right(right);
+        input_model.set_down(down);
+        input_model
+    }
+
+    fn record(level: &Level) -> Recording {
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let mut recording = Recording::new(LevelIdentity::new("demo.json", level), 1);
+        for step in 0..120 {
+            let input_model = input(1., if step < 60 { 0. } else { 1. });
+            game_state.update(&input_model);
+            recording.record(&input_model, &game_state);
+        }
+        recording
+    }
+
+    #[test]
+    fn parse_script() {
+        let script = InputScript::parse("right*30, right+up*10,none").unwrap();
+        assert_eq!(script.len(), 41);
+        let mut right_up = InputModel::default();
+        right_up.set_right(1.);
+        right_up.set_up(1.);
+        assert_eq!(script.input(35), Some(right_up));
+        assert_eq!(script.input(40), Some(InputModel::default()));
+        assert_eq!(script.input(41), None);
+        assert!(InputScript::parse("jump*3").is_err());
+        assert!(InputScript::parse("left*x").is_err());
+    }
+
+    #[test]
+    fn replay_matches_recording() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let recording = record(&level);
+        assert_eq!(recording.inputs.len(), 2);
+        assert_eq!(recording.input(60), Some(input(1., 1.)));
+        assert_eq!(recording.input(120), None);
+        let parsed = Recording::parse("replay.json", &recording.to_json()).unwrap();
+        assert_eq!(parsed, recording);
+        assert_eq!(parsed.level(), &LevelIdentity::new("demo.json", &level));
+        let mut game_state = level.build(BroadPhaseKind::SweepAndPrune);
+        assert_eq!(parsed.replay(&mut game_state), Ok(()));
+    }
+
+    #[test]
+    fn replay_detects_divergence() {
+        let level = Level::parse("demo.json", DEMO).unwrap();
+        let recording = record(&level);
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let player = game_state.player_id().unwrap();
+        game_state.common_mut(player).unwrap().top_left.x += 1.;
+        let divergence = recording.replay(&mut game_state).unwrap_err();
+        assert_eq!(divergence.step, 0);
+    }
+}
@@ -0,0 +1,341 @@
+use fnv::FnvHashMap;
+use platform_game_experiment::game::{EntityId, GameState};
+use platform_game_experiment::snapshot::{
+    AllocatorSnapshot, EntitySnapshot, ShapeSnapshot,
+};
+use std::collections::VecDeque;
+use std::mem;
+
+fn entity_size(entity: &EntitySnapshot) -> usize {
+    let shape_size = match entity.common.as_ref().map(|common| &common.shape) {
+        Some(&ShapeSnapshot::EdgeChain { ref vertices, .. }) => {
+            vertices.len() * mem::size_of::<[f32; 2]>()
+        }
+        _ => 0,
+    };
+    let trigger_size = entity.trigger.as_ref().map_or(0, |trigger| {
+        trigger.name.len()
+            + trigger
+                .properties
+                .keys()
+                .map(|key| key.len() + mem::size_of::<(String, f64)>())
+                .sum::<usize>()
+    });
+    let script_size = entity.script.as_ref().map_or(0, |script| {
+        script.path.len()
+            + script
+                .properties
+                .keys()
+                .map(|key| key.len() + mem::size_of::<(String, f64)>())
+                .sum::<usize>()
+    });
+    let hitbox_size = entity.hitbox.as_ref().map_or(0, |hitbox| {
+        let vertices = match hitbox.shape {
+            ShapeSnapshot::EdgeChain { ref vertices, .. } => vertices.len(),
+            _ => 0,
+        };
+        vertices * mem::size_of::<[f32; 2]>()
+            + hitbox.hit.len() * mem::size_of::<EntityId>()
+    });
+    mem::size_of::<EntitySnapshot>()
+        + shape_size
+        + trigger_size
+        + script_size
+        + hitbox_size
+}
+
+/// What changed in one step, stored backwards so that applying it to the state
+/// after the step gives the state before it.
+struct Delta {
+    /// Entities as they were before the step, if they were changed, removed or
+    /// added. Added entities have no previous snapshot.
+    previous: Vec<(EntityId, Option<EntitySnapshot>)>,
+    player_id: Option<EntityId>,
+    allocator: Option<AllocatorSnapshot>,
+}
+
+impl Delta {
+    /// An estimate of the memory used by the delta, in bytes.
+    fn size(&self) -> usize {
+        mem::size_of::<Self>()
+            + self
+                .previous
+                .iter()
+                .map(|&(_, ref entity)| {
+                    entity
+                        .as_ref()
+                        .map_or(mem::size_of::<EntityId>(), entity_size)
+                })
+                .sum::<usize>()
+            + self.allocator.as_ref().map_or(0, |allocator| {
+                (allocator.generations.len() + allocator.free.len())
+                    * mem::size_of::<u32>()
+            })
+    }
+}
+
+/// Records how the game state changes each step so it can be stepped backwards.
+/// Only the differences between steps are kept, and the oldest steps are
+/// forgotten once the recording uses more than a fixed amount of memory.
+///
+/// Static entities are only compared while the game state reports that they may
+/// have changed, so on large tile levels a step costs about as much as the
+/// number of moving entities.
+pub struct Rewind {
+    /// Every entity as of the last recorded step.
+    entities: FnvHashMap<EntityId, EntitySnapshot>,
+    /// The dynamic entities as of the last recorded step.
+    dynamic: Vec<EntityId>,
+    player_id: Option<EntityId>,
+    allocator: AllocatorSnapshot,
+    allocator_version: u64,
+    statics_version: u64,
+    deltas: VecDeque<Delta>,
+    bytes_used: usize,
+    max_bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(game_state: &GameState, max_bytes: usize) -> Self {
+        let snapshot = game_state.snapshot();
+        Self {
+            entities: snapshot
+                .entities
+                .into_iter()
+                .map(|entity| (entity.id, entity))
+                .collect(),
+            dynamic: game_state.dynamic_entities(),
+            player_id: snapshot.player_id,
+            allocator: snapshot.allocator,
+            allocator_version: game_state.allocator_version(),
+            statics_version: game_state.statics_version(),
+            deltas: VecDeque::new(),
+            bytes_used: 0,
+            max_bytes,
+        }
+    }
+
+    /// Call after each step. Changes made to `game_state` by anything other than
+    /// a step are recorded too, and are undone when rewinding past them.
+    pub fn record(&mut self, game_state: &GameState) {
+        let dynamic = game_state.dynamic_entities();
+        let mut candidates = dynamic.clone();
+        candidates.extend(self.dynamic.iter().cloned());
+        if game_state.statics_version() != self.statics_version {
+            candidates.extend(self.entities.keys().cloned());
+            candidates.extend(game_state.static_entities());
+            self.statics_version = game_state.statics_version();
+        }
+        candidates.sort();
+        candidates.dedup();
+        let mut previous = Vec::new();
+        for id in candidates {
+            let entity = game_state.snapshot_entity(id);
+            if self.entities.get(&id) == entity.as_ref() {
+                continue;
+            }
+            let before = match entity {
+                Some(entity) => self.entities.insert(id, entity),
+                None => self.entities.remove(&id),
+            };
+            previous.push((id, before));
+        }
+        self.dynamic = dynamic;
+        let mut allocator = None;
+        if game_state.allocator_version() != self.allocator_version {
+            self.allocator_version = game_state.allocator_version();
+            let after = game_state.allocator_snapshot();
+            if after != self.allocator {
+                allocator = Some(mem::replace(&mut self.allocator, after));
+            }
+        }
+        let delta = Delta {
+            previous,
+            player_id: mem::replace(&mut self.player_id, game_state.player_id()),
+            allocator,
+        };
+        self.bytes_used += delta.size();
+        self.deltas.push_back(delta);
+        while self.bytes_used > self.max_bytes {
+            match self.deltas.pop_front() {
+                Some(delta) => self.bytes_used -= delta.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Puts `game_state` back to how it was one step earlier. Returns false if
+    /// there are no more recorded steps. Only the entities which changed in that
+    /// step are touched.
+    pub fn step_back(&mut self, game_state: &mut GameState) -> bool {
+        let delta = match self.deltas.pop_back() {
+            Some(delta) => delta,
+            None => return false,
+        };
+        self.bytes_used -= delta.size();
+        game_state.restore_entities(
+            delta.player_id,
+            delta.allocator.as_ref(),
+            &delta.previous,
+        );
+        for (id, entity) in delta.previous {
+            match entity {
+                Some(entity) => self.entities.insert(id, entity),
+                None => self.entities.remove(&id),
+            };
+        }
+        self.player_id = delta.player_id;
+        if let Some(allocator) = delta.allocator {
+            self.allocator = allocator;
+        }
+        self.dynamic = game_state.dynamic_entities();
+        self.allocator_version = game_state.allocator_version();
+        self.statics_version = game_state.statics_version();
+        true
+    }
+
+    /// The number of steps which can be rewound.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    /// An estimate of the memory used by the recorded steps, in bytes.
+    pub fn bytes_used(&self) -> usize {
+        self.bytes_used
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::vec2;
+    use platform_game_experiment::game::{EntityCommon, InputModel};
+    use platform_game_experiment::shape::{AxisAlignedRect, Shape};
+
+    fn rect(top_left: (f32, f32)) -> EntityCommon {
+        EntityCommon::new(
+            vec2(top_left.0, top_left.1),
+            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(10., 10.))),
+            [1., 1., 1.],
+        )
+    }
+
+    fn game_state() -> GameState {
+        let mut game_state = GameState::new(vec2(200., 200.), Default::default());
+        game_state.add_static_solid(rect((100., 0.)));
+        game_state.add_player(rect((0., 0.)));
+        game_state.add_mover(rect((0., 50.)), vec2(3., 0.));
+        game_state
+    }
+
+    #[test]
+    fn rewinds_to_each_earlier_step() {
+        let mut game_state = game_state();
+        let mut rewind = Rewind::new(&game_state, 1 << 20);
+        let mut input_model = InputModel::default();
+        input_model.set_right(1.);
+        let mut history = vec![game_state.snapshot()];
+        for step in 0..40 {
+            if step == 20 {
+                let player = game_state.player_id().unwrap();
+                game_state.despawn(player);
+            }
+            game_state.update(&input_model);
+            rewind.record(&game_state);
+            history.push(game_state.snapshot());
+        }
+        assert_eq!(rewind.len(), 40);
+        history.pop();
+        while let Some(expected) = history.pop() {
+            assert!(rewind.step_back(&mut game_state));
+            assert_eq!(game_state.snapshot(), expected);
+        }
+        assert!(!rewind.step_back(&mut game_state));
+        assert_eq!(rewind.bytes_used(), 0);
+    }
+
+    #[test]
+    fn rewinds_statics_recoloured_by_the_player() {
+        let mut game_state = GameState::new(vec2(200., 200.), Default::default());
+        // The player jumps up through the platform, touching it on the way.
+        let platform = game_state.add_one_way_platform(rect((0., 20.)));
+        game_state.add_player(rect((0., 50.)));
+        let mut rewind = Rewind::new(&game_state, 1 << 20);
+        let mut input_model = InputModel::default();
+        input_model.set_up(1.);
+        let mut history = vec![game_state.snapshot()];
+        for _ in 0..20 {
+            game_state.update(&input_model);
+            rewind.record(&game_state);
+            history.push(game_state.snapshot());
+        }
+        assert_eq!(game_state.common(platform).unwrap().colour, [0., 1., 1.]);
+        history.pop();
+        while let Some(expected) = history.pop() {
+            assert!(rewind.step_back(&mut game_state));
+            assert_eq!(game_state.snapshot(), expected);
+        }
+        assert_eq!(game_state.common(platform).unwrap().colour, [1., 1., 1.]);
+    }
+
+    #[test]
+    fn memory_is_capped() {
+        let mut game_state = game_state();
+        let max_bytes = 4096;
+        let mut rewind = Rewind::new(&game_state, max_bytes);
+        for _ in 0..1000 {
+            game_state.update(&Default::default());
+            rewind.record(&game_state);
+            assert!(rewind.bytes_used() <= max_bytes);
+        }
+        assert!(rewind.len() > 0 && rewind.len() < 1000);
+    }
+
+    #[test]
+    fn records_only_changed_entities() {
+        let mut game_state = game_state();
+        for i in 0..100 {
+            game_state.add_static_solid(rect((i as f32 * 10., 150.)));
+        }
+        let mut rewind = Rewind::new(&game_state, 1 << 20);
+        game_state.update(&Default::default());
+        rewind.record(&game_state);
+        let delta = rewind.deltas.back().unwrap();
+        assert_eq!(delta.previous.len(), 1);
+        assert!(delta.allocator.is_none());
+    }
+
+    #[test]
+    fn rewinds_changes_to_statics() {
+        let mut game_state = game_state();
+        let wall = game_state.add_static_solid(rect((30., 50.)));
+        let mover = game_state.dynamic_entities()[1];
+        let mut rewind = Rewind::new(&game_state, 1 << 20);
+        let mut history = vec![game_state.snapshot()];
+        for step in 0..20 {
+            if step == 5 {
+                game_state.despawn(wall);
+            }
+            if step == 10 {
+                game_state.add_one_way_platform(rect((150., 0.)));
+            }
+            game_state.update(&Default::default());
+            rewind.record(&game_state);
+            history.push(game_state.snapshot());
+        }
+        history.pop();
+        while let Some(expected) = history.pop() {
+            assert!(rewind.step_back(&mut game_state));
+            assert_eq!(game_state.snapshot(), expected);
+        }
+        // The wall is back in the broad phase, so it blocks the mover again.
+        let blocked = game_state
+            .top_left_after_movement(mover, vec2(30., 0.))
+            .unwrap();
+        assert!(blocked.x < 20.1);
+    }
+}
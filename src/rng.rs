@@ -0,0 +1,27 @@
+use cgmath::{vec2, Vector2};
+
+/// A xorshift generator. Deterministic so that every run benchmarks the same scenes
+/// and tests the same generated cases.
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed.max(1) }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+    /// Uniformly distributed in `[min, max)`.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        min + unit * (max - min)
+    }
+    pub fn vector(&mut self, min: f32, max: f32) -> Vector2<f32> {
+        vec2(self.range(min, max), self.range(min, max))
+    }
+}
@@ -0,0 +1,437 @@
+//! Declarative tests of how the player moves through a level. A scenario is a
+//! starting state, scripted input and expectations about the player at particular
+//! steps. Step `n` refers to the state after `n` updates, so step 0 is the start.
+
+use aabb::Aabb;
+use cgmath::{vec2, Vector2};
+use game::{EntityId, GameState};
+use replay::InputScript;
+use std::fmt;
+
+/// How far below the player to probe when deciding whether it's grounded.
+const GROUND_PROBE: f32 = 1.;
+/// Penetration smaller than this doesn't count as overlapping, so that resting
+/// against a solid isn't reported.
+const OVERLAP_TOLERANCE: f32 = 0.01;
+/// The number of steps leading up to a failure which are included in its trace.
+const TRACE_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy)]
+enum Axis {
+    X,
+    Y,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Expectation {
+    GroundedBy(usize),
+    Near {
+        step: usize,
+        axis: Axis,
+        value: f32,
+        tolerance: f32,
+    },
+    NeverOverlapsSolid,
+}
+
+impl Expectation {
+    fn last_step(&self) -> usize {
+        match self {
+            &Expectation::GroundedBy(step) => step,
+            &Expectation::Near { step, .. } => step,
+            &Expectation::NeverOverlapsSolid => 0,
+        }
+    }
+}
+
+/// The player at one step of a scenario.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceStep {
+    pub step: usize,
+    pub top_left: Vector2<f32>,
+    pub grounded: bool,
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {:>4}: ({:>9.3}, {:>9.3}){}",
+            self.step,
+            self.top_left.x,
+            self.top_left.y,
+            if self.grounded { " grounded" } else { "" }
+        )
+    }
+}
+
+/// Why a scenario failed, with the player's positions leading up to it.
+#[derive(Debug, Clone)]
+pub struct ScenarioFailure {
+    pub step: usize,
+    pub message: String,
+    pub trace: Vec<TraceStep>,
+}
+
+impl fmt::Display for ScenarioFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "step {}: {}", self.step, self.message)?;
+        let start = self.trace.len().saturating_sub(TRACE_LEN);
+        if start > 0 {
+            writeln!(f, "...")?;
+        }
+        for trace_step in self.trace[start..].iter() {
+            writeln!(f, "{}", trace_step)?;
+        }
+        Ok(())
+    }
+}
+
+/// The static solid with the lowest id which `aabb` overlaps by more than
+/// `tolerance`, if there is one.
+fn first_overlap(
+    game_state: &GameState,
+    aabb: &Aabb<f32>,
+    tolerance: f32,
+) -> Option<EntityId> {
+    let shrunk = Aabb::new(
+        aabb.top_left() + vec2(tolerance, tolerance),
+        aabb.size() - vec2(tolerance, tolerance) * 2.,
+    );
+    let mut first = None;
+    game_state.for_each_static_intersecting(&shrunk, |id, common, one_way| {
+        if !one_way
+            && first.map_or(true, |first| id < first)
+            && common.shape.overlaps_aabb(common.top_left, &shrunk)
+        {
+            first = Some(id);
+        }
+    });
+    first
+}
+
+/// A static solid which the bounding box of entity `id` overlaps by more than
+/// `tolerance`, if there is one. With no tolerance, touching counts as overlapping.
+/// One-way platforms don't count.
+pub fn overlapping_solid(
+    game_state: &GameState,
+    id: EntityId,
+    tolerance: f32,
+) -> Option<EntityId> {
+    game_state.common(id).and_then(|common| {
+        first_overlap(game_state, &common.shape.aabb(common.top_left), tolerance)
+    })
+}
+
+/// Whether the player would be stopped, or deflected along a slope, if it tried to
+/// move down.
+fn is_grounded(
+    game_state: &GameState,
+    player_id: EntityId,
+    top_left: Vector2<f32>,
+) -> bool {
+    match game_state.top_left_after_movement(player_id, vec2(0., GROUND_PROBE)) {
+        Some(probed) => probed.y - top_left.y < GROUND_PROBE - OVERLAP_TOLERANCE,
+        None => false,
+    }
+}
+
+/// A starting state, scripted input, and expectations about the player.
+///
+/// ```ignore
+/// Scenario::new(level.build(BroadPhaseKind::default()))
+///     .input("down*30,right*30")
+///     .grounded_by(30)
+///     .x_near(60, 320., 1.)
+///     .never_overlaps_solid()
+///     .run();
+/// ```
+pub struct Scenario {
+    game_state: GameState,
+    script: Option<InputScript>,
+    steps: Option<usize>,
+    expectations: Vec<Expectation>,
+}
+
+impl Scenario {
+    /// The game state must have a player.
+    pub fn new(game_state: GameState) -> Self {
+        Self {
+            game_state,
+            script: None,
+            steps: None,
+            expectations: Vec::new(),
+        }
+    }
+
+    /// Input in the format of `InputScript`. No keys are held once it runs out.
+    /// Panics if the script can't be parsed.
+    pub fn input(mut self, script: &str) -> Self {
+        match InputScript::parse(script) {
+            Ok(script) => self.script = Some(script),
+            Err(message) => panic!("invalid scenario input {:?}: {}", script, message),
+        }
+        self
+    }
+
+    /// Defaults to the length of the input, or the last step with an
+    /// expectation if that's later. Running the scenario panics if an expectation
+    /// is for a later step.
+    pub fn steps(mut self, steps: usize) -> Self {
+        self.steps = Some(steps);
+        self
+    }
+
+    /// The player must be standing on something at some step up to `step`.
+    pub fn grounded_by(mut self, step: usize) -> Self {
+        self.expectations.push(Expectation::GroundedBy(step));
+        self
+    }
+
+    pub fn x_near(self, step: usize, x: f32, tolerance: f32) -> Self {
+        self.near(step, Axis::X, x, tolerance)
+    }
+
+    pub fn y_near(self, step: usize, y: f32, tolerance: f32) -> Self {
+        self.near(step, Axis::Y, y, tolerance)
+    }
+
+    fn near(mut self, step: usize, axis: Axis, value: f32, tolerance: f32) -> Self {
+        self.expectations.push(Expectation::Near {
+            step,
+            axis,
+            value,
+            tolerance,
+        });
+        self
+    }
+
+    /// At no step may the player's bounding box overlap a static solid by more than
+    /// a small tolerance. One-way platforms don't count.
+    pub fn never_overlaps_solid(mut self) -> Self {
+        self.expectations.push(Expectation::NeverOverlapsSolid);
+        self
+    }
+
+    /// Runs every step, stopping at the first unmet expectation. Panics if an
+    /// expectation is for a step after the last.
+    pub fn check(mut self) -> Result<Vec<TraceStep>, ScenarioFailure> {
+        let player_id = self.game_state.player_id().expect("scenario has no player");
+        let script_len = self.script.as_ref().map_or(0, InputScript::len);
+        let last_expectation = self
+            .expectations
+            .iter()
+            .map(Expectation::last_step)
+            .max()
+            .unwrap_or(0);
+        let steps = self
+            .steps
+            .unwrap_or_else(|| script_len.max(last_expectation));
+        if last_expectation > steps {
+            panic!(
+                "scenario has an expectation at step {} but only runs {} steps",
+                last_expectation, steps
+            );
+        }
+        let mut grounded_at = None;
+        let mut trace = Vec::new();
+        for step in 0..(steps + 1) {
+            if step > 0 {
+                let input_model = self
+                    .script
+                    .as_ref()
+                    .and_then(|script| script.input(step - 1))
+                    .unwrap_or_default();
+                self.game_state.update(&input_model);
+            }
+            let common = match self.game_state.common(player_id) {
+                Some(common) => common.clone(),
+                None => {
+                    return Err(ScenarioFailure {
+                        step,
+                        message: "the player was despawned".to_string(),
+                        trace,
+                    })
+                }
+            };
+            let grounded = is_grounded(&self.game_state, player_id, common.top_left);
+            if grounded && grounded_at.is_none() {
+                grounded_at = Some(step);
+            }
+            trace.push(TraceStep {
+                step,
+                top_left: common.top_left,
+                grounded,
+            });
+            let aabb = common.shape.aabb(common.top_left);
+            for expectation in self.expectations.iter() {
+                let message = match expectation {
+                    &Expectation::GroundedBy(by) => {
+                        if step == by && grounded_at.is_none() {
+                            Some(format!(
+                                "expected the player to be grounded by step {}",
+                                by
+                            ))
+                        } else {
+                            None
+                        }
+                    }
+                    &Expectation::Near {
+                        step: at,
+                        axis,
+                        value,
+                        tolerance,
+                    } => {
+                        let actual = match axis {
+                            Axis::X => common.top_left.x,
+                            Axis::Y => common.top_left.y,
+                        };
+                        if step == at && (actual - value).abs() > tolerance {
+                            Some(format!(
+                                "expected {:?} within {} of {}, got {}",
+                                axis, tolerance, value, actual
+                            ))
+                        } else {
+                            None
+                        }
+                    }
+                    &Expectation::NeverOverlapsSolid => first_overlap(
+                        &self.game_state,
+                        &aabb,
+                        OVERLAP_TOLERANCE,
+                    )
+                    .map(|id| format!("the player overlaps solid entity {}", id.index())),
+                };
+                if let Some(message) = message {
+                    return Err(ScenarioFailure {
+                        step,
+                        message,
+                        trace,
+                    });
+                }
+            }
+        }
+        Ok(trace)
+    }
+
+    /// Like `check`, but panics with the failure and a trace of the player's
+    /// positions, for use in tests.
+    pub fn run(self) {
+        if let Err(failure) = self.check() {
+            panic!("scenario failed at {}", failure);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use broad_phase::BroadPhaseKind;
+    use game::{EntityCommon, System};
+    use level::Level;
+    use shape::{AxisAlignedRect, Shape};
+
+    const DEMO: &str = include_str!("../levels/demo.json");
+
+    fn demo() -> GameState {
+        Level::parse("demo.json", DEMO)
+            .unwrap()
+            .build(BroadPhaseKind::default())
+    }
+
+    #[test]
+    fn walk_along_floor() {
+        // There's no gravity, so holding down moves the player 4 pixels a step from
+        // y = 50 until it stands on the floor at y = 200, then it walks right.
+        Scenario::new(demo())
+            .input("down*25,right*30")
+            .grounded_by(25)
+            .y_near(25, 136., 0.01)
+            .x_near(55, 320., 0.01)
+            .y_near(55, 136., 0.01)
+            .never_overlaps_solid()
+            .run();
+    }
+
+    #[test]
+    fn walk_into_wall() {
+        Scenario::new(demo())
+            .input("right*100")
+            .x_near(100, 568., 0.01)
+            .never_overlaps_solid()
+            .run();
+    }
+
+    #[test]
+    fn failure_has_trace() {
+        let failure = Scenario::new(demo())
+            .input("down*5")
+            .grounded_by(5)
+            .check()
+            .unwrap_err();
+        assert_eq!(failure.step, 5);
+        assert_eq!(failure.trace.len(), 6);
+        let text = failure.to_string();
+        assert!(text.contains("grounded by step 5"), "{}", text);
+        assert!(
+            text.contains("step    5: (  200.000,    70.000)"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn overlap_is_detected() {
+        let mut game_state = demo();
+        game_state.add_player(EntityCommon::new(
+            vec2(40., 190.),
+            Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(32., 32.))),
+            [1., 1., 1.],
+        ));
+        let failure = Scenario::new(game_state)
+            .never_overlaps_solid()
+            .check()
+            .unwrap_err();
+        assert_eq!(failure.step, 0);
+        assert!(failure.message.contains("overlaps solid"));
+    }
+
+    #[test]
+    #[should_panic(expected = "expectation at step 40 but only runs 30 steps")]
+    fn expectations_past_the_last_step_panic() {
+        Scenario::new(demo())
+            .input("right*50")
+            .steps(30)
+            .x_near(40, 0., 1.)
+            .run();
+    }
+
+    /// Adds a solid in the same place as the player after a number of steps.
+    struct AddSolidAtPlayer {
+        step: usize,
+        runs: usize,
+    }
+
+    impl System for AddSolidAtPlayer {
+        fn run(&mut self, game_state: &mut GameState) {
+            self.runs += 1;
+            if self.runs == self.step {
+                let player = game_state.player_id().unwrap();
+                let common = game_state.common(player).unwrap().clone();
+                game_state.add_static_solid(common);
+            }
+        }
+    }
+
+    #[test]
+    fn overlap_with_solid_added_mid_run_is_detected() {
+        let mut game_state = demo();
+        game_state.add_system(Box::new(AddSolidAtPlayer { step: 3, runs: 0 }));
+        let failure = Scenario::new(game_state)
+            .steps(10)
+            .never_overlaps_solid()
+            .check()
+            .unwrap_err();
+        assert_eq!(failure.step, 3);
+    }
+}
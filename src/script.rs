@@ -0,0 +1,14 @@
+use trigger::Properties;
+
+/// Behaviour for an entity, written in Rhai. Scripts only run when the game is
+/// built with the `scripting` feature, but are loaded and saved either way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    /// Where the script's source is, relative to the level's directory.
+    pub path: String,
+    /// State for this entity which the script can read and change, starting with
+    /// the values in the level.
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+}
@@ -0,0 +1,753 @@
+use aabb::Aabb;
+use broad_phase::BroadPhaseKind;
+use cgmath::vec2;
+use combat::{self, Health, Hitbox};
+use fnv::{FnvHashMap, FnvHashSet};
+use game::{EntityCommon, EntityId, GameState, System, Velocity};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, ImmutableString, Scope, AST};
+use script::Script;
+use shape::{AxisAlignedRect, Shape};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use trigger::{PropertyValue, Trigger};
+
+/// Stops a script which loops forever from hanging the game.
+const MAX_OPERATIONS: u64 = 100_000;
+const SPAWN_COLOUR: [f32; 3] = [1., 1., 1.];
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The game state, as seen by scripts. It's only shared with scripts while the
+/// script system is running.
+#[derive(Clone)]
+struct World(Rc<RefCell<GameState>>);
+
+fn number(value: &Dynamic) -> ScriptResult<f32> {
+    value
+        .as_float()
+        .map(|value| value as f32)
+        .or_else(|_| value.as_int().map(|value| value as f32))
+        .map_err(|type_name| format!("expected a number, got {}", type_name).into())
+}
+
+fn rect(
+    x: &Dynamic,
+    y: &Dynamic,
+    width: &Dynamic,
+    height: &Dynamic,
+) -> ScriptResult<Aabb<f32>> {
+    Ok(Aabb::new(
+        vec2(number(x)?, number(y)?),
+        vec2(number(width)?, number(height)?),
+    ))
+}
+
+fn pair(x: f32, y: f32) -> Array {
+    vec![Dynamic::from_float(x as f64), Dynamic::from_float(y as f64)]
+}
+
+fn no_such_entity(id: EntityId) -> Box<EvalAltResult> {
+    format!("entity {} has no shape", id.index()).into()
+}
+
+fn to_dynamic(value: &PropertyValue) -> Dynamic {
+    match value {
+        &PropertyValue::Bool(value) => value.into(),
+        &PropertyValue::Int(value) => value.into(),
+        &PropertyValue::Float(value) => value.into(),
+        &PropertyValue::String(ref value) => value.clone().into(),
+    }
+}
+
+fn to_property(value: Dynamic) -> ScriptResult<PropertyValue> {
+    let type_name = value.type_name();
+    if let Ok(value) = value.as_bool() {
+        Ok(PropertyValue::Bool(value))
+    } else if let Ok(value) = value.as_int() {
+        Ok(PropertyValue::Int(value))
+    } else if let Ok(value) = value.as_float() {
+        Ok(PropertyValue::Float(value))
+    } else if let Ok(value) = value.into_immutable_string() {
+        Ok(PropertyValue::String(value.to_string()))
+    } else {
+        Err(format!("properties can't hold a {}", type_name).into())
+    }
+}
+
+impl World {
+    fn spawn(&mut self, aabb: Aabb<f32>, solid: bool) -> EntityId {
+        let common = EntityCommon::new(
+            aabb.top_left(),
+            Shape::AxisAlignedRect(AxisAlignedRect::new(aabb.size())),
+            SPAWN_COLOUR,
+        );
+        let mut game_state = self.0.borrow_mut();
+        if solid {
+            game_state.add_static_solid(common)
+        } else {
+            game_state.add_mover(common, vec2(0., 0.))
+        }
+    }
+
+    fn common<T, F: FnOnce(&EntityCommon) -> T>(
+        &self,
+        id: EntityId,
+        f: F,
+    ) -> ScriptResult<T> {
+        self.0
+            .borrow()
+            .common(id)
+            .map(f)
+            .ok_or_else(|| no_such_entity(id))
+    }
+
+    fn entities_in(&self, aabb: &Aabb<f32>) -> Array {
+        let mut ids = Vec::new();
+        self.0.borrow().for_each_entity_in(aabb, |id| ids.push(id));
+        ids.sort_by_key(|id| id.index());
+        ids.dedup();
+        ids.into_iter().map(Dynamic::from).collect()
+    }
+}
+
+fn register_api(engine: &mut Engine) {
+    engine.register_type_with_name::<World>("World");
+    engine.register_type_with_name::<EntityId>("Entity");
+    engine.register_fn("==", |a: EntityId, b: EntityId| a == b);
+    engine.register_fn("!=", |a: EntityId, b: EntityId| a != b);
+    engine.register_fn("to_string", |id: &mut EntityId| {
+        format!("entity {}", id.index())
+    });
+    engine.register_fn("to_debug", |id: &mut EntityId| format!("{:?}", id));
+    engine.register_fn("player", |world: &mut World| {
+        world
+            .0
+            .borrow()
+            .player_id()
+            .map_or(Dynamic::UNIT, Dynamic::from)
+    });
+    engine.register_fn("is_alive", |world: &mut World, id: EntityId| {
+        world.0.borrow().is_alive(id)
+    });
+    engine.register_fn(
+        "spawn",
+        |world: &mut World,
+         x: Dynamic,
+         y: Dynamic,
+         width: Dynamic,
+         height: Dynamic|
+         -> ScriptResult<EntityId> {
+            Ok(world.spawn(rect(&x, &y, &width, &height)?, false))
+        },
+    );
+    engine.register_fn(
+        "spawn_solid",
+        |world: &mut World,
+         x: Dynamic,
+         y: Dynamic,
+         width: Dynamic,
+         height: Dynamic|
+         -> ScriptResult<EntityId> {
+            Ok(world.spawn(rect(&x, &y, &width, &height)?, true))
+        },
+    );
+    engine.register_fn("despawn", |world: &mut World, id: EntityId| {
+        world.0.borrow_mut().despawn(id)
+    });
+    engine.register_fn("top_left", |world: &mut World, id: EntityId| {
+        world.common(id, |common| pair(common.top_left.x, common.top_left.y))
+    });
+    engine.register_fn(
+        "set_top_left",
+        |world: &mut World, id: EntityId, x: Dynamic, y: Dynamic| {
+            let top_left = vec2(number(&x)?, number(&y)?);
+            if world.0.borrow_mut().set_top_left(id, top_left) {
+                Ok(())
+            } else {
+                Err(no_such_entity(id))
+            }
+        },
+    );
+    engine.register_fn("velocity", |world: &mut World, id: EntityId| {
+        world
+            .0
+            .borrow()
+            .components()
+            .get::<Velocity>(id)
+            .map_or(Dynamic::UNIT, |&Velocity(v)| pair(v.x, v.y).into())
+    });
+    engine.register_fn(
+        "set_velocity",
+        |world: &mut World, id: EntityId, x: Dynamic, y: Dynamic| {
+            let velocity = Velocity(vec2(number(&x)?, number(&y)?));
+            let mut game_state = world.0.borrow_mut();
+            if game_state.common(id).is_none() {
+                return Err(no_such_entity(id));
+            }
+            if game_state.is_static(id) {
+                return Err(format!("static entity {} can't move", id.index()).into());
+            }
+            game_state.components_mut().insert(id, velocity);
+            Ok(())
+        },
+    );
+    engine.register_fn("colour", |world: &mut World, id: EntityId| {
+        world.common(id, |common| {
+            common
+                .colour
+                .iter()
+                .map(|&c| Dynamic::from_float(c as f64))
+                .collect::<Array>()
+        })
+    });
+    engine.register_fn(
+        "set_colour",
+        |world: &mut World, id: EntityId, r: Dynamic, g: Dynamic, b: Dynamic| {
+            let colour = [number(&r)?, number(&g)?, number(&b)?];
+            match world.0.borrow_mut().common_mut(id) {
+                Some(common) => {
+                    common.colour = colour;
+                    Ok(())
+                }
+                None => Err(no_such_entity(id)),
+            }
+        },
+    );
+    engine.register_fn(
+        "entities_in",
+        |world: &mut World,
+         x: Dynamic,
+         y: Dynamic,
+         width: Dynamic,
+         height: Dynamic|
+         -> ScriptResult<Array> {
+            Ok(world.entities_in(&rect(&x, &y, &width, &height)?))
+        },
+    );
+    engine.register_fn("health", |world: &mut World, id: EntityId| {
+        world
+            .0
+            .borrow()
+            .components()
+            .get::<Health>(id)
+            .map_or(Dynamic::UNIT, |health| (health.current as i64).into())
+    });
+    // The hitbox is positioned relative to the attacker, and is on its team.
+    engine.register_fn(
+        "attack",
+        |world: &mut World,
+         id: EntityId,
+         x: Dynamic,
+         y: Dynamic,
+         width: Dynamic,
+         height: Dynamic,
+         damage: i64|
+         -> ScriptResult<EntityId> {
+            let aabb = rect(&x, &y, &width, &height)?;
+            let mut game_state = world.0.borrow_mut();
+            let team = match game_state.components().get::<Health>(id) {
+                Some(health) => health.team,
+                None => return Err(format!("entity {} has no health", id.index()).into()),
+            };
+            let shape = Shape::AxisAlignedRect(AxisAlignedRect::new(aabb.size()));
+            let hitbox = Hitbox::new(team, aabb.top_left(), shape, damage.max(0) as u32)
+                .with_owner(id);
+            Ok(combat::spawn_hitbox(&mut game_state, hitbox))
+        },
+    );
+    engine.register_fn(
+        "get",
+        |world: &mut World, id: EntityId, name: ImmutableString| {
+            world
+                .0
+                .borrow()
+                .components()
+                .get::<Script>(id)
+                .and_then(|script| script.properties.get(name.as_str()))
+                .map_or(Dynamic::UNIT, to_dynamic)
+        },
+    );
+    engine.register_fn(
+        "set",
+        |world: &mut World,
+         id: EntityId,
+         name: ImmutableString,
+         value: Dynamic|
+         -> ScriptResult<()> {
+            let value = to_property(value)?;
+            match world.0.borrow_mut().components_mut().get_mut::<Script>(id) {
+                Some(script) => {
+                    script.properties.insert(name.to_string(), value);
+                    Ok(())
+                }
+                None => Err(format!("entity {} has no script", id.index()).into()),
+            }
+        },
+    );
+}
+
+/// Runs the scripts attached to entities. Each script can define any of:
+///
+/// - `on_update(world, me)`, called every step
+/// - `on_collision(world, me, other)`, called when `me` runs into `other` or the
+///   other way round
+/// - `on_trigger_enter(world, me, other)` and `on_trigger_exit(world, me, other)`,
+///   called when a moving entity starts or stops overlapping the trigger `me`
+/// - `on_damage(world, me, attacker)`, called when `me` is hurt and survives.
+///   `attacker` is the hitbox if it has no owner
+///
+/// Callbacks run in order of entity id, so that runs are deterministic. Which
+/// entities are in each trigger isn't part of the game state, so restoring a
+/// snapshot can cause enter callbacks to run again.
+pub struct ScriptSystem {
+    engine: Engine,
+    /// Script paths are relative to this.
+    directory: PathBuf,
+    /// Sources to use instead of reading files, by script path.
+    sources: FnvHashMap<String, String>,
+    /// Scripts which failed to load are `None`, so the error is only reported once.
+    asts: FnvHashMap<String, Option<Rc<AST>>>,
+    in_triggers: FnvHashSet<(EntityId, EntityId)>,
+    reported: HashSet<String>,
+    on_error: Box<dyn FnMut(&str)>,
+    /// Takes the place of the game state while scripts have it.
+    spare: Option<GameState>,
+}
+
+impl ScriptSystem {
+    /// Errors in scripts are printed to stderr.
+    pub fn new() -> Self {
+        Self::with_error_handler(|message| eprintln!("{}", message))
+    }
+
+    /// Errors in scripts are passed to `on_error`, once for each distinct error.
+    pub fn with_error_handler<F: FnMut(&str) + 'static>(on_error: F) -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        register_api(&mut engine);
+        Self {
+            engine,
+            directory: PathBuf::new(),
+            sources: FnvHashMap::default(),
+            asts: FnvHashMap::default(),
+            in_triggers: FnvHashSet::default(),
+            reported: HashSet::new(),
+            on_error: Box::new(on_error),
+            spare: None,
+        }
+    }
+
+    /// Resolves script paths against `directory`, which is normally the level's,
+    /// rather than the working directory.
+    pub fn with_directory<P: AsRef<Path>>(mut self, directory: P) -> Self {
+        self.directory = directory.as_ref().to_path_buf();
+        self
+    }
+
+    /// Uses `source` for scripts with the path `path` rather than reading the file.
+    pub fn with_source(mut self, path: &str, source: &str) -> Self {
+        self.sources.insert(path.to_string(), source.to_string());
+        self
+    }
+
+    fn report(&mut self, message: String) {
+        if self.reported.insert(message.clone()) {
+            (self.on_error)(&message);
+        }
+    }
+
+    fn compile(&mut self, path: &str, source: &str) -> Option<Rc<AST>> {
+        match self.engine.compile(source) {
+            Ok(ast) => Some(Rc::new(ast)),
+            Err(error) => {
+                self.report(format!("{}: {}", path, error));
+                None
+            }
+        }
+    }
+
+    fn ast(&mut self, path: &str) -> Option<Rc<AST>> {
+        if let Some(ast) = self.asts.get(path) {
+            return ast.clone();
+        }
+        let source = match self.sources.get(path) {
+            Some(source) => Ok(source.clone()),
+            None => fs::read_to_string(self.directory.join(path)),
+        };
+        let ast = match source {
+            Ok(source) => self.compile(path, &source),
+            Err(error) => {
+                self.report(format!("{}: {}", path, error));
+                None
+            }
+        };
+        self.asts.insert(path.to_string(), ast.clone());
+        ast
+    }
+
+    /// Calls `name` in the script attached to `id`, if it has one which defines it.
+    fn call(&mut self, world: &World, id: EntityId, name: &str, other: Option<EntityId>) {
+        let path = match world.0.borrow().components().get::<Script>(id) {
+            Some(script) => script.path.clone(),
+            None => return,
+        };
+        let ast = match self.ast(&path) {
+            Some(ast) => ast,
+            None => return,
+        };
+        let arity = if other.is_some() { 3 } else { 2 };
+        if !ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == arity)
+        {
+            return;
+        }
+        let mut scope = Scope::new();
+        let result = match other {
+            Some(other) => self.engine.call_fn::<Dynamic>(
+                &mut scope,
+                &ast,
+                name,
+                (world.clone(), id, other),
+            ),
+            None => self.engine.call_fn::<Dynamic>(
+                &mut scope,
+                &ast,
+                name,
+                (world.clone(), id),
+            ),
+        };
+        if let Err(error) = result {
+            self.report(format!("{}: in {}: {}", path, name, error));
+        }
+    }
+
+    fn run_scripts(&mut self, world: &World) {
+        let mut collisions = world
+            .0
+            .borrow()
+            .collisions()
+            .iter()
+            .flat_map(|collision| {
+                vec![
+                    (collision.mover, collision.solid),
+                    (collision.solid, collision.mover),
+                ]
+            })
+            .collect::<Vec<_>>();
+        collisions.sort_by_key(|&(a, b)| (a.index(), b.index()));
+        collisions.dedup();
+        for (id, other) in collisions {
+            if world.0.borrow().is_alive(id) && world.0.borrow().is_alive(other) {
+                self.call(world, id, "on_collision", Some(other));
+            }
+        }
+
+        let mut damaged = world
+            .0
+            .borrow()
+            .damage_events()
+            .iter()
+            .map(|event| (event.target, event.attacker.unwrap_or(event.hitbox)))
+            .collect::<Vec<_>>();
+        damaged.sort_by_key(|&(id, attacker)| (id.index(), attacker.index()));
+        for (id, attacker) in damaged {
+            if world.0.borrow().is_alive(id) {
+                self.call(world, id, "on_damage", Some(attacker));
+            }
+        }
+
+        let mut in_triggers = FnvHashSet::default();
+        {
+            let game_state = world.0.borrow();
+            game_state
+                .components()
+                .join(|id, trigger: &Trigger, _: &Script| {
+                    game_state.for_each_entity_in(&trigger.aabb, |other| {
+                        if game_state.components().contains::<Velocity>(other) {
+                            in_triggers.insert((id, other));
+                        }
+                    });
+                });
+        }
+        let mut changes = in_triggers
+            .difference(&self.in_triggers)
+            .map(|&pair| (pair, "on_trigger_enter"))
+            .chain(
+                self.in_triggers
+                    .difference(&in_triggers)
+                    .map(|&pair| (pair, "on_trigger_exit")),
+            )
+            .collect::<Vec<_>>();
+        changes.sort_by_key(|&((id, other), name)| (id.index(), other.index(), name));
+        self.in_triggers = in_triggers;
+        for ((id, other), name) in changes {
+            if world.0.borrow().is_alive(id) {
+                self.call(world, id, name, Some(other));
+            }
+        }
+
+        let mut scripted = Vec::new();
+        world
+            .0
+            .borrow()
+            .components()
+            .for_each(|id, _: &Script| scripted.push(id));
+        scripted.sort_by_key(|id| id.index());
+        for id in scripted {
+            if world.0.borrow().is_alive(id) {
+                self.call(world, id, "on_update", None);
+            }
+        }
+    }
+}
+
+impl Default for ScriptSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl System for ScriptSystem {
+    fn run(&mut self, game_state: &mut GameState) {
+        let spare = self
+            .spare
+            .take()
+            .unwrap_or_else(|| GameState::new(vec2(1., 1.), BroadPhaseKind::default()));
+        let world = World(Rc::new(RefCell::new(mem::replace(game_state, spare))));
+        self.run_scripts(&world);
+        let state = match Rc::try_unwrap(world.0) {
+            Ok(state) => state.into_inner(),
+            Err(_) => panic!("a script kept hold of the world"),
+        };
+        self.spare = Some(mem::replace(game_state, state));
+    }
+
+    /// Scripts are read again the next time they're needed, and errors in them are
+    /// reported again.
+    fn reload(&mut self) {
+        self.asts.clear();
+        self.reported.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use combat::{CombatSystem, Team};
+    use game::InputModel;
+    use level::Level;
+    use std::env;
+    use test_util::rect;
+
+    const LEVEL: &str = include_str!("../levels/scripted.json");
+    const SWITCH: &str = include_str!("../levels/scripts/switch.rhai");
+    const PATROL: &str = include_str!("../levels/scripts/patrol.rhai");
+
+    /// A script system with the level's scripts, which adds errors to `errors`.
+    fn system(errors: &Rc<RefCell<Vec<String>>>) -> ScriptSystem {
+        let errors = errors.clone();
+        ScriptSystem::with_error_handler(move |message| {
+            errors.borrow_mut().push(message.to_string())
+        })
+        .with_source("scripts/switch.rhai", SWITCH)
+        .with_source("scripts/patrol.rhai", PATROL)
+    }
+
+    fn game_state() -> (GameState, Rc<RefCell<Vec<String>>>) {
+        let level = Level::parse("scripted.json", LEVEL).unwrap();
+        let mut game_state = GameState::new(level.world_size(), Default::default());
+        level.populate(&mut game_state);
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        game_state.add_system(Box::new(system(&errors)));
+        (game_state, errors)
+    }
+
+    fn scripted_mover(game_state: &GameState) -> EntityId {
+        let mut ids = Vec::new();
+        game_state
+            .components()
+            .join(|id, _: &Script, _: &Velocity| ids.push(id));
+        assert_eq!(ids.len(), 1);
+        ids[0]
+    }
+
+    #[test]
+    fn switch_opens_door() {
+        let (mut game_state, errors) = game_state();
+        let player = game_state.player_id().unwrap();
+        let mut input_model = InputModel::default();
+        input_model.set_right(1.);
+        for _ in 0..100 {
+            game_state.update(&input_model);
+        }
+        assert_eq!(*errors.borrow(), Vec::<String>::new());
+        // Without the switch the door would stop the player at 268.
+        assert!(game_state.common(player).unwrap().top_left.x > 300.);
+        let switch = game_state
+            .snapshot()
+            .entities
+            .into_iter()
+            .find(|entity| entity.trigger.is_some())
+            .unwrap();
+        assert_eq!(
+            switch.script.unwrap().properties.get("pressed"),
+            Some(&PropertyValue::Bool(true))
+        );
+    }
+
+    #[test]
+    fn enemy_patrols_between_walls() {
+        let (mut game_state, errors) = game_state();
+        let enemy = scripted_mover(&game_state);
+        let mut turns = 0;
+        let mut last_velocity = Velocity(vec2(3., 0.));
+        for _ in 0..300 {
+            game_state.update(&Default::default());
+            let x = game_state.common(enemy).unwrap().top_left.x;
+            assert!(x >= 320. && x <= 576., "{}", x);
+            let velocity = *game_state.components().get::<Velocity>(enemy).unwrap();
+            if velocity != last_velocity {
+                turns += 1;
+                last_velocity = velocity;
+            }
+        }
+        assert_eq!(*errors.borrow(), Vec::<String>::new());
+        assert!(turns >= 3, "{}", turns);
+    }
+
+    #[test]
+    fn attacks_call_on_damage() {
+        let mut game_state = GameState::new(vec2(200., 100.), Default::default());
+        let script = |path: &str| Script {
+            path: path.to_string(),
+            properties: Default::default(),
+        };
+        let player = game_state.add_player(rect(82., 0., 16., 16.));
+        let enemy = game_state.add_mover(rect(100., 0., 16., 16.), vec2(0., 0.));
+        game_state
+            .components_mut()
+            .insert(player, Health::new(Team::Player, 5));
+        game_state
+            .components_mut()
+            .insert(player, script("victim.rhai"));
+        game_state
+            .components_mut()
+            .insert(enemy, Health::new(Team::Enemy, 1));
+        game_state
+            .components_mut()
+            .insert(enemy, script("attacker.rhai"));
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let system = system(&errors)
+            .with_source(
+                "attacker.rhai",
+                "fn on_update(world, me) {
+                    if world.get(me, \"attacked\") != true {
+                        world.set(me, \"attacked\", true);
+                        world.attack(me, -20, 0, 20, 16, 2);
+                    }
+                }",
+            )
+            .with_source(
+                "victim.rhai",
+                "fn on_damage(world, me, attacker) {
+                    world.set(me, \"health\", world.health(me));
+                }",
+            );
+        game_state.add_system(Box::new(CombatSystem));
+        game_state.add_system(Box::new(system));
+        game_state.update(&Default::default());
+        game_state.update(&Default::default());
+        assert_eq!(*errors.borrow(), Vec::<String>::new());
+        let properties = &game_state
+            .components()
+            .get::<Script>(player)
+            .unwrap()
+            .properties;
+        assert_eq!(properties.get("health"), Some(&PropertyValue::Int(3)));
+    }
+
+    #[test]
+    fn errors_are_reported_once() {
+        let (mut game_state, errors) = game_state();
+        let enemy = scripted_mover(&game_state);
+        game_state.components_mut().insert(
+            enemy,
+            Script {
+                path: "broken.rhai".to_string(),
+                properties: Default::default(),
+            },
+        );
+        let mut system = system(&errors).with_source(
+            "broken.rhai",
+            "fn on_update(world, me) { world.set_velocity(me, \"fast\", 0); }",
+        );
+        for _ in 0..3 {
+            system.run(&mut game_state);
+        }
+        let errors = errors.borrow();
+        assert_eq!(errors.len(), 1, "{:?}", *errors);
+        assert!(
+            errors[0].starts_with("broken.rhai: in on_update: "),
+            "{}",
+            errors[0]
+        );
+        assert!(errors[0].contains("expected a number"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn scripts_are_relative_to_level_and_reloaded_with_it() {
+        let directory = env::temp_dir().join("script_system_test");
+        fs::create_dir_all(directory.join("scripts")).unwrap();
+        let script_path = directory.join("scripts").join("speed.rhai");
+        let set_speed = |speed: i32| {
+            let source = format!(
+                "fn on_update(world, me) {{ world.set_velocity(me, {}, 0); }}",
+                speed
+            );
+            fs::write(&script_path, source).unwrap();
+        };
+        let level_path = directory.join("level.json");
+        fs::write(
+            &level_path,
+            r#"{
+                "version": 1,
+                "world_size": [256, 256],
+                "player": { "top_left": [0, 0], "size": [16, 16], "colour": [1, 0, 0] },
+                "entities": [
+                    {
+                        "top_left": [100, 0],
+                        "shape": { "type": "rect", "size": [16, 16] },
+                        "colour": [1, 1, 1],
+                        "velocity": [0, 0],
+                        "script": { "path": "scripts/speed.rhai" }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+        set_speed(1);
+        let level = Level::load(&level_path).unwrap();
+        let mut game_state = level.build(BroadPhaseKind::default());
+        let velocity = |game_state: &GameState| {
+            *game_state
+                .components()
+                .get::<Velocity>(scripted_mover(game_state))
+                .unwrap()
+        };
+        game_state.update(&Default::default());
+        assert_eq!(velocity(&game_state), Velocity(vec2(1., 0.)));
+
+        set_speed(2);
+        game_state.update(&Default::default());
+        assert_eq!(velocity(&game_state), Velocity(vec2(1., 0.)));
+        level.reload(&mut game_state);
+        game_state.update(&Default::default());
+        assert_eq!(velocity(&game_state), Velocity(vec2(2., 0.)));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use aabb::Aabb;
+use behaviour::Behaviour;
+use cgmath::{vec2, Vector2};
+use combat::{Health, Hitbox, Team};
+use edge_chain::EdgeChain;
+use game::EntityId;
+use level::LevelError;
+use line_segment::LineSegment;
+use script::Script;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json;
+use shape::{AxisAlignedRect, Shape};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use trigger::{Properties, Trigger};
+
+/// Version of the snapshot format written by this version of the game. Bump this
+/// when making a change to the format which old snapshots can't be read with.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+fn supported_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == SNAPSHOT_FORMAT_VERSION {
+        Ok(version)
+    } else {
+        Err(de::Error::custom(format_args!(
+            "unsupported snapshot format version {} (expected {})",
+            version, SNAPSHOT_FORMAT_VERSION
+        )))
+    }
+}
+
+fn vector(v: [f32; 2]) -> Vector2<f32> {
+    vec2(v[0], v[1])
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShapeSnapshot {
+    Rect {
+        size: [f32; 2],
+    },
+    LineSegment {
+        start: [f32; 2],
+        end: [f32; 2],
+    },
+    EdgeChain {
+        vertices: Vec<[f32; 2]>,
+        closed: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        previous_ghost: Option<[f32; 2]>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next_ghost: Option<[f32; 2]>,
+    },
+}
+
+impl<'a> From<&'a Shape<f32>> for ShapeSnapshot {
+    fn from(shape: &'a Shape<f32>) -> Self {
+        match shape {
+            &Shape::AxisAlignedRect(ref rect) => ShapeSnapshot::Rect {
+                size: rect.dimensions().into(),
+            },
+            &Shape::LineSegment(ref line_segment) => ShapeSnapshot::LineSegment {
+                start: line_segment.start.into(),
+                end: line_segment.end.into(),
+            },
+            &Shape::EdgeChain(ref edge_chain) => {
+                let (previous_ghost, next_ghost) = edge_chain.ghost_vertices();
+                ShapeSnapshot::EdgeChain {
+                    vertices: edge_chain.vertices().iter().map(|&v| v.into()).collect(),
+                    closed: edge_chain.is_closed(),
+                    previous_ghost: previous_ghost.map(Into::into),
+                    next_ghost: next_ghost.map(Into::into),
+                }
+            }
+        }
+    }
+}
+
+impl ShapeSnapshot {
+    pub fn to_shape(&self) -> Shape<f32> {
+        match self {
+            &ShapeSnapshot::Rect { size } => {
+                Shape::AxisAlignedRect(AxisAlignedRect::new(vector(size)))
+            }
+            &ShapeSnapshot::LineSegment { start, end } => {
+                Shape::LineSegment(LineSegment::new(vector(start), vector(end)))
+            }
+            &ShapeSnapshot::EdgeChain {
+                ref vertices,
+                closed,
+                previous_ghost,
+                next_ghost,
+            } => {
+                let vertices = vertices.iter().map(|&v| vector(v)).collect();
+                Shape::EdgeChain(if closed {
+                    EdgeChain::closed(vertices)
+                } else {
+                    EdgeChain::new(vertices).with_ghost_vertices(
+                        previous_ghost.map(vector),
+                        next_ghost.map(vector),
+                    )
+                })
+            }
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match self {
+            &ShapeSnapshot::EdgeChain {
+                ref vertices,
+                closed,
+                previous_ghost,
+                next_ghost,
+            } => {
+                vertices.len() >= 2
+                    && !(closed && (previous_ghost.is_some() || next_ghost.is_some()))
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerSnapshot {
+    pub name: String,
+    pub top_left: [f32; 2],
+    pub size: [f32; 2],
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+}
+
+impl<'a> From<&'a Trigger> for TriggerSnapshot {
+    fn from(trigger: &'a Trigger) -> Self {
+        Self {
+            name: trigger.name.clone(),
+            top_left: trigger.aabb.top_left().into(),
+            size: trigger.aabb.size().into(),
+            properties: trigger.properties.clone(),
+        }
+    }
+}
+
+impl TriggerSnapshot {
+    pub fn to_trigger(&self) -> Trigger {
+        Trigger {
+            name: self.name.clone(),
+            aabb: Aabb::new(vector(self.top_left), vector(self.size)),
+            properties: self.properties.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HitboxSnapshot {
+    pub team: Team,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<EntityId>,
+    pub offset: [f32; 2],
+    pub shape: ShapeSnapshot,
+    pub damage: u32,
+    pub knockback: [f32; 2],
+    pub steps: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hit: Vec<EntityId>,
+}
+
+impl<'a> From<&'a Hitbox> for HitboxSnapshot {
+    fn from(hitbox: &'a Hitbox) -> Self {
+        Self {
+            team: hitbox.team,
+            owner: hitbox.owner,
+            offset: hitbox.offset.into(),
+            shape: (&hitbox.shape).into(),
+            damage: hitbox.damage,
+            knockback: hitbox.knockback.into(),
+            steps: hitbox.steps,
+            hit: hitbox.hit.clone(),
+        }
+    }
+}
+
+impl HitboxSnapshot {
+    pub fn to_hitbox(&self) -> Hitbox {
+        Hitbox {
+            team: self.team,
+            owner: self.owner,
+            offset: vector(self.offset),
+            shape: self.shape.to_shape(),
+            damage: self.damage,
+            knockback: vector(self.knockback),
+            steps: self.steps,
+            hit: self.hit.clone(),
+        }
+    }
+}
+
+/// How an entity takes part in collision detection, if it does at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Solidity {
+    Solid,
+    OneWay,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommonSnapshot {
+    pub top_left: [f32; 2],
+    pub shape: ShapeSnapshot,
+    pub colour: [f32; 3],
+    /// Static entities are in the broad phase. Entities which move aren't.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solidity: Option<Solidity>,
+}
+
+/// Everything known about one entity. Entities with no shape only have components.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EntitySnapshot {
+    pub id: EntityId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub common: Option<CommonSnapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trigger: Option<TriggerSnapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behaviour: Option<Behaviour>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health: Option<Health>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hitbox: Option<HitboxSnapshot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Script>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllocatorSnapshot {
+    pub generations: Vec<u32>,
+    pub free: Vec<u32>,
+}
+
+impl AllocatorSnapshot {
+    /// The free indices, which must each be allocated and appear only once.
+    fn free_set(&self) -> Result<HashSet<u32>, String> {
+        let mut free = HashSet::new();
+        for &index in self.free.iter() {
+            if index as usize >= self.generations.len() {
+                return Err(format!("free index {} was never allocated", index));
+            }
+            if !free.insert(index) {
+                return Err(format!("free index {} appears more than once", index));
+            }
+        }
+        Ok(free)
+    }
+}
+
+/// The state of a `GameState`. Systems are code rather than data so aren't
+/// included, and neither are components other than velocities, triggers,
+/// behaviours, health, hitboxes and scripts. The broad phase is rebuilt from the
+/// static entities when restoring, so its backend can differ from the one the
+/// snapshot was taken with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    #[serde(deserialize_with = "supported_version")]
+    pub version: u32,
+    pub player_id: Option<EntityId>,
+    pub allocator: AllocatorSnapshot,
+    /// Sorted by id, so that the same state always serializes the same way.
+    pub entities: Vec<EntitySnapshot>,
+}
+
+impl Snapshot {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        Self::parse(path, &text)
+    }
+
+    /// Parses a snapshot from `text`. `path` is only used in error messages.
+    pub fn parse<P: AsRef<Path>>(path: P, text: &str) -> Result<Self, LevelError> {
+        let path = path.as_ref();
+        let snapshot: Self =
+            serde_json::from_str(text).map_err(|error| LevelError::Parse {
+                path: path.to_path_buf(),
+                line: error.line(),
+                column: error.column(),
+                error,
+            })?;
+        snapshot
+            .validate()
+            .map_err(|message| LevelError::Unsupported {
+                path: path.to_path_buf(),
+                message,
+            })?;
+        Ok(snapshot)
+    }
+
+    /// Checks that the snapshot could have been taken from a real game state.
+    fn validate(&self) -> Result<(), String> {
+        let free = self.allocator.free_set()?;
+        let generations = &self.allocator.generations;
+        let is_live = |id: EntityId| {
+            generations.get(id.index() as usize) == Some(&id.generation())
+                && !free.contains(&id.index())
+        };
+        let mut seen = HashSet::new();
+        for entity in self.entities.iter() {
+            if !is_live(entity.id) {
+                return Err(format!("entity {:?} is not allocated", entity.id));
+            }
+            if !seen.insert(entity.id) {
+                return Err(format!("entity {:?} appears more than once", entity.id));
+            }
+            if let Some(ref common) = entity.common {
+                if !common.shape.is_valid() {
+                    return Err(format!("entity {:?} has an invalid shape", entity.id));
+                }
+                let is_rect = match common.shape {
+                    ShapeSnapshot::Rect { .. } => true,
+                    _ => false,
+                };
+                if entity.velocity.is_some() && !is_rect {
+                    return Err(format!("entity {:?} moves but isn't a rect", entity.id));
+                }
+                let moves =
+                    entity.velocity.is_some() || self.player_id == Some(entity.id);
+                if moves && common.solidity.is_some() {
+                    return Err(format!("entity {:?} moves but is static", entity.id));
+                }
+            }
+            if let Some(ref hitbox) = entity.hitbox {
+                if !hitbox.shape.is_valid() {
+                    return Err(format!("hitbox {:?} has an invalid shape", entity.id));
+                }
+            }
+        }
+        match self.player_id {
+            Some(id) if !is_live(id) => Err(format!("player {:?} is not allocated", id)),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("failed to serialize snapshot")
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LevelError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_json() + "\n").map_err(|error| LevelError::Io {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+}
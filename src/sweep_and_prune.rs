@@ -0,0 +1,116 @@
+use aabb::Aabb;
+use arith::{self, Scalar};
+use broad_phase::BroadPhase;
+
+/// Items kept sorted by the left edge of their aabb. Works best when items are
+/// spread out horizontally, as in levels that scroll sideways. Inserting and
+/// removing shift the items after the one changed, so each takes time linear in the
+/// number of items, and filling one with n items takes O(n^2) time.
+#[derive(Debug, Clone)]
+pub struct SweepAndPrune<T, N> {
+    items: Vec<(Aabb<N>, T)>,
+    max_width: N,
+}
+
+impl<T, N: Scalar> SweepAndPrune<T, N> {
+    pub fn new() -> Self {
+        Self {
+            items: Vec::new(),
+            max_width: N::zero(),
+        }
+    }
+
+    /// Index of the first item whose left edge is not less than `x`.
+    fn lower_bound(&self, x: N) -> usize {
+        let mut low = 0;
+        let mut high = self.items.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.items[mid].0.top_left().x < x {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
+
+impl<T, N: Scalar> BroadPhase<T, N> for SweepAndPrune<T, N> {
+    fn clear(&mut self) {
+        self.items.clear();
+        self.max_width = N::zero();
+    }
+
+    fn insert(&mut self, aabb: Aabb<N>, t: T) {
+        let index = self.lower_bound(aabb.top_left().x);
+        self.max_width = arith::max(self.max_width, aabb.size().x);
+        self.items.insert(index, (aabb, t));
+    }
+
+    fn remove<P: FnMut(&T) -> bool>(
+        &mut self,
+        aabb: &Aabb<N>,
+        mut predicate: P,
+    ) -> Option<T> {
+        let start = self.lower_bound(aabb.top_left().x);
+        let position = self.items[start..]
+            .iter()
+            .take_while(|&&(ref item_aabb, _)| {
+                item_aabb.top_left().x <= aabb.top_left().x
+            })
+            .position(|&(ref item_aabb, ref t)| item_aabb == aabb && predicate(t))?;
+        let (removed_aabb, t) = self.items.remove(start + position);
+        if removed_aabb.size().x == self.max_width {
+            self.max_width = self
+                .items
+                .iter()
+                .fold(N::zero(), |width, item| arith::max(width, item.0.size().x));
+        }
+        Some(t)
+    }
+
+    fn for_each_intersection<F: FnMut(&Aabb<N>, &T)>(&self, aabb: &Aabb<N>, mut f: F) {
+        let start = self.lower_bound(aabb.top_left().x - self.max_width);
+        let x_max = aabb.bottom_right_coord().x;
+        for &(ref item_aabb, ref t) in self.items[start..].iter() {
+            if item_aabb.top_left().x > x_max {
+                break;
+            }
+            if item_aabb.is_intersecting(aabb) {
+                f(item_aabb, t);
+            }
+        }
+    }
+
+    fn for_each_overlapping_pair<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(&self, mut f: F) {
+        for (i, &(ref aabb, ref t)) in self.items.iter().enumerate() {
+            let x_max = aabb.bottom_right_coord().x;
+            for &(ref other_aabb, ref other_t) in self.items[(i + 1)..].iter() {
+                if other_aabb.top_left().x > x_max {
+                    break;
+                }
+                if aabb.is_intersecting(other_aabb) {
+                    f(aabb, t, other_aabb, other_t);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cgmath::vec2;
+
+    #[test]
+    fn max_width_shrinks_on_remove() {
+        let mut sweep_and_prune = SweepAndPrune::new();
+        let wide = Aabb::new(vec2(0., 0.), vec2(100., 10.));
+        sweep_and_prune.insert(wide, 0);
+        sweep_and_prune.insert(Aabb::new(vec2(50., 0.), vec2(20., 10.)), 1);
+        assert_eq!(sweep_and_prune.max_width, 100.);
+        assert_eq!(sweep_and_prune.remove(&wide, |_| true), Some(0));
+        assert_eq!(sweep_and_prune.max_width, 20.);
+    }
+}
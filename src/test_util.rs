@@ -0,0 +1,14 @@
+//! Fixtures shared by unit tests.
+
+use cgmath::vec2;
+use game::EntityCommon;
+use shape::{AxisAlignedRect, Shape};
+
+/// A white axis-aligned rect.
+pub fn rect(x: f32, y: f32, width: f32, height: f32) -> EntityCommon {
+    EntityCommon::new(
+        vec2(x, y),
+        Shape::AxisAlignedRect(AxisAlignedRect::new(vec2(width, height))),
+        [1., 1., 1.],
+    )
+}
@@ -0,0 +1,528 @@
+//! Imports maps made with the Tiled editor (https://www.mapeditor.org), saved in
+//! its JSON format.
+//!
+//! - Tile layers become solid geometry, with neighbouring tiles merged into rects
+//!   by a `Tilemap`. Layers with a `solid` property set to false are skipped.
+//! - Rects in object layers become solids, unless their type is `trigger`.
+//! - Polylines and polygons become chains of line segments.
+//! - An object whose type is `player_spawn` sets the player's position, and its
+//!   size if it has one.
+
+use cgmath::vec2;
+use level::{
+    Level, LevelEntity, LevelError, LevelShape, LevelTrigger, PlayerSpawn,
+    LEVEL_FORMAT_VERSION,
+};
+use serde_json::{self, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tilemap::{TileGeometry, Tilemap};
+use trigger::{Properties, PropertyValue};
+
+const SOLID_COLOUR: [f32; 3] = [1., 1., 0.];
+const PLAYER_COLOUR: [f32; 3] = [1., 0., 0.];
+const DEFAULT_PLAYER_SIZE: [f32; 2] = [32., 64.];
+
+#[derive(Debug, Deserialize)]
+struct TiledProperty {
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    value: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledPoint {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledObject {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    // Tiled 1.9 renamed an object's "type" to "class".
+    #[serde(rename = "type", alias = "class", default)]
+    kind: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default)]
+    point: bool,
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    polyline: Option<Vec<TiledPoint>>,
+    #[serde(default)]
+    polygon: Option<Vec<TiledPoint>>,
+    #[serde(default)]
+    gid: Option<u32>,
+    #[serde(default)]
+    text: Option<Value>,
+    #[serde(default)]
+    properties: Vec<TiledProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TiledLayer {
+    #[serde(rename = "tilelayer")]
+    TileLayer {
+        name: String,
+        #[serde(default)]
+        width: usize,
+        #[serde(default)]
+        height: usize,
+        #[serde(default)]
+        data: Option<Value>,
+        #[serde(default)]
+        encoding: Option<String>,
+        #[serde(default)]
+        properties: Vec<TiledProperty>,
+    },
+    #[serde(rename = "objectgroup")]
+    ObjectGroup {
+        name: String,
+        objects: Vec<TiledObject>,
+    },
+    Group {
+        layers: Vec<TiledLayer>,
+    },
+    #[serde(rename = "imagelayer")]
+    ImageLayer {},
+}
+
+#[derive(Debug, Deserialize)]
+struct TiledMap {
+    #[serde(default)]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    layers: Vec<TiledLayer>,
+}
+
+fn convert_properties(properties: &[TiledProperty]) -> Result<Properties, String> {
+    let mut converted = Properties::new();
+    for property in properties.iter() {
+        let value = match (property.kind.as_str(), &property.value) {
+            ("bool", &Value::Bool(b)) => PropertyValue::Bool(b),
+            ("int", &Value::Number(ref n)) | ("object", &Value::Number(ref n)) => {
+                match n.as_i64() {
+                    Some(i) => PropertyValue::Int(i),
+                    None => PropertyValue::Float(n.as_f64().unwrap_or(0.)),
+                }
+            }
+            ("float", &Value::Number(ref n)) => {
+                PropertyValue::Float(n.as_f64().unwrap_or(0.))
+            }
+            ("", &Value::String(ref s))
+            | ("string", &Value::String(ref s))
+            | ("color", &Value::String(ref s))
+            | ("file", &Value::String(ref s)) => PropertyValue::String(s.clone()),
+            (kind, value) => {
+                return Err(format!(
+                    "property {:?} has unsupported type {:?} (value {})",
+                    property.name, kind, value
+                ))
+            }
+        };
+        converted.insert(property.name.clone(), value);
+    }
+    Ok(converted)
+}
+
+fn is_solid_layer(properties: &[TiledProperty]) -> bool {
+    !properties
+        .iter()
+        .any(|property| property.name == "solid" && property.value == Value::Bool(false))
+}
+
+impl TiledMap {
+    fn import_tile_layer(
+        &self,
+        name: &str,
+        width: usize,
+        height: usize,
+        data: &Option<Value>,
+        encoding: &Option<String>,
+        level: &mut Level,
+    ) -> Result<(), String> {
+        if let &Some(ref encoding) = encoding {
+            if encoding != "csv" {
+                return Err(format!(
+                    "tile layer {:?} uses {} encoding; set the layer format to CSV \
+                     in Tiled's map properties",
+                    name, encoding
+                ));
+            }
+        }
+        let data = match data {
+            &Some(Value::Array(ref data)) => data,
+            _ => return Err(format!("tile layer {:?} has no tile data", name)),
+        };
+        if data.len() != width * height {
+            return Err(format!(
+                "tile layer {:?} has {} tiles but is {}x{}",
+                name,
+                data.len(),
+                width,
+                height
+            ));
+        }
+        let mut tilemap = Tilemap::new(
+            width,
+            height,
+            vec2(0., 0.),
+            vec2(self.tilewidth, self.tileheight),
+        );
+        for (index, tile) in data.iter().enumerate() {
+            if tile.as_u64().map_or(false, |gid| gid != 0) {
+                tilemap.set_tile(index % width, index / width, 1);
+            }
+        }
+        for geometry in tilemap.geometry() {
+            if let TileGeometry::Rect { top_left, size } = geometry {
+                level.entities.push(LevelEntity {
+                    top_left: top_left.into(),
+                    shape: LevelShape::Rect { size: size.into() },
+                    colour: SOLID_COLOUR,
+                    velocity: None,
+                    behaviour: None,
+                    health: None,
+                    script: None,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn import_object(
+        &self,
+        object: &TiledObject,
+        level: &mut Level,
+    ) -> Result<(), String> {
+        let describe = || format!("object {} ({:?})", object.id, object.name);
+        if object.rotation != 0. {
+            return Err(format!("{} is rotated, which isn't supported", describe()));
+        }
+        if object.ellipse || object.gid.is_some() || object.text.is_some() {
+            return Err(format!(
+                "{} is an ellipse, tile or text object; only rects, points, \
+                 polylines and polygons are supported",
+                describe()
+            ));
+        }
+        let top_left = [object.x, object.y];
+        if object.kind == "player_spawn" {
+            let size = if object.point || object.width <= 0. || object.height <= 0. {
+                DEFAULT_PLAYER_SIZE
+            } else {
+                [object.width, object.height]
+            };
+            level.player = PlayerSpawn {
+                top_left,
+                size,
+                colour: PLAYER_COLOUR,
+                health: None,
+            };
+            return Ok(());
+        }
+        if let Some(points) = object.polyline.as_ref().or(object.polygon.as_ref()) {
+            let mut points = points.iter().map(|p| [p.x, p.y]).collect::<Vec<_>>();
+            if object.polygon.is_some() && !points.is_empty() {
+                let first = points[0];
+                points.push(first);
+            }
+            for pair in points.windows(2) {
+                level.entities.push(LevelEntity {
+                    top_left,
+                    shape: LevelShape::LineSegment {
+                        start: pair[0],
+                        end: pair[1],
+                    },
+                    colour: SOLID_COLOUR,
+                    velocity: None,
+                    behaviour: None,
+                    health: None,
+                    script: None,
+                });
+            }
+            return Ok(());
+        }
+        if object.point {
+            return Err(format!(
+                "{} is a point, but only player spawns can be points",
+                describe()
+            ));
+        }
+        if object.width <= 0. || object.height <= 0. {
+            return Err(format!("{} has no area", describe()));
+        }
+        let size = [object.width, object.height];
+        if object.kind == "trigger" {
+            level.triggers.push(LevelTrigger {
+                name: object.name.clone(),
+                top_left,
+                size,
+                properties: convert_properties(&object.properties)
+                    .map_err(|message| format!("{}: {}", describe(), message))?,
+                script: None,
+            });
+        } else {
+            level.entities.push(LevelEntity {
+                top_left,
+                shape: LevelShape::Rect { size },
+                colour: SOLID_COLOUR,
+                velocity: None,
+                behaviour: None,
+                health: None,
+                script: None,
+            });
+        }
+        Ok(())
+    }
+
+    fn import_layers(
+        &self,
+        layers: &[TiledLayer],
+        level: &mut Level,
+    ) -> Result<(), String> {
+        for layer in layers.iter() {
+            match layer {
+                &TiledLayer::TileLayer {
+                    ref name,
+                    width,
+                    height,
+                    ref data,
+                    ref encoding,
+                    ref properties,
+                } => {
+                    if is_solid_layer(properties) {
+                        self.import_tile_layer(
+                            name, width, height, data, encoding, level,
+                        )?;
+                    }
+                }
+                &TiledLayer::ObjectGroup {
+                    ref name,
+                    ref objects,
+                } => {
+                    for object in objects.iter() {
+                        self.import_object(object, level).map_err(|message| {
+                            format!("layer {:?}: {}", name, message)
+                        })?;
+                    }
+                }
+                &TiledLayer::Group { ref layers } => self.import_layers(layers, level)?,
+                &TiledLayer::ImageLayer {} => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn to_level(&self) -> Result<Level, String> {
+        if self.orientation != "" && self.orientation != "orthogonal" {
+            return Err(format!(
+                "{} maps aren't supported, only orthogonal ones",
+                self.orientation
+            ));
+        }
+        if self.infinite {
+            return Err("infinite maps aren't supported".to_string());
+        }
+        let mut level = Level {
+            version: LEVEL_FORMAT_VERSION,
+            world_size: [
+                self.width as f32 * self.tilewidth,
+                self.height as f32 * self.tileheight,
+            ],
+            player: PlayerSpawn {
+                top_left: [0., 0.],
+                size: DEFAULT_PLAYER_SIZE,
+                colour: PLAYER_COLOUR,
+                health: None,
+            },
+            entities: Vec::new(),
+            triggers: Vec::new(),
+            directory: PathBuf::new(),
+        };
+        self.import_layers(&self.layers, &mut level)?;
+        Ok(level)
+    }
+}
+
+/// Converts a Tiled map to a level. `path` is only used in error messages.
+pub fn import_str<P: AsRef<Path>>(path: P, text: &str) -> Result<Level, LevelError> {
+    let path = path.as_ref();
+    if text.trim().starts_with('<') {
+        return Err(LevelError::Unsupported {
+            path: path.to_path_buf(),
+            message: "TMX maps aren't supported; export the map as JSON from Tiled"
+                .to_string(),
+        });
+    }
+    let map: TiledMap =
+        serde_json::from_str(text).map_err(|error| LevelError::Parse {
+            path: path.to_path_buf(),
+            line: error.line(),
+            column: error.column(),
+            error,
+        })?;
+    let mut level = map.to_level().map_err(|message| LevelError::Unsupported {
+        path: path.to_path_buf(),
+        message,
+    })?;
+    level.directory = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+    Ok(level)
+}
+
+pub fn import<P: AsRef<Path>>(path: P) -> Result<Level, LevelError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|error| LevelError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    import_str(path, &text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use broad_phase::BroadPhaseKind;
+    use trigger::Trigger;
+
+    const MAP: &str = r#"{
+  "orientation": "orthogonal",
+  "infinite": false,
+  "width": 4,
+  "height": 3,
+  "tilewidth": 16,
+  "tileheight": 16,
+  "layers": [
+    {
+      "type": "tilelayer",
+      "name": "background",
+      "width": 4,
+      "height": 3,
+      "data": [1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+      "properties": [{ "name": "solid", "type": "bool", "value": false }]
+    },
+    {
+      "type": "tilelayer",
+      "name": "ground",
+      "width": 4,
+      "height": 3,
+      "data": [0, 0, 0, 0, 3, 0, 0, 0, 1, 2, 0, 1]
+    },
+    {
+      "type": "objectgroup",
+      "name": "objects",
+      "objects": [
+        { "id": 1, "name": "", "type": "player_spawn", "x": 8, "y": 4, "point": true },
+        {
+          "id": 2,
+          "name": "exit",
+          "class": "trigger",
+          "x": 48,
+          "y": 0,
+          "width": 16,
+          "height": 32,
+          "properties": [
+            { "name": "next_level", "type": "string", "value": "levels/2.json" },
+            { "name": "delay", "type": "float", "value": 0.5 }
+          ]
+        },
+        {
+          "id": 3,
+          "name": "slope",
+          "type": "",
+          "x": 16,
+          "y": 16,
+          "polyline": [{ "x": 0, "y": 16 }, { "x": 16, "y": 0 }, { "x": 32, "y": 0 }]
+        }
+      ]
+    }
+  ]
+}"#;
+
+    #[test]
+    fn imports_layers_and_objects() {
+        let level = import_str("map.json", MAP).unwrap();
+        assert_eq!(level.world_size, [64., 48.]);
+        assert_eq!(level.player.top_left, [8., 4.]);
+        let rects = level
+            .entities
+            .iter()
+            .filter_map(|entity| match entity.shape {
+                LevelShape::Rect { size } => Some((entity.top_left, size)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rects,
+            vec![
+                ([0., 16.], [16., 32.]),
+                ([16., 32.], [16., 16.]),
+                ([48., 32.], [16., 16.]),
+            ]
+        );
+        let segments = level
+            .entities
+            .iter()
+            .filter_map(|entity| match entity.shape {
+                LevelShape::LineSegment { start, end } => Some((start, end)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            segments,
+            vec![([0., 16.], [16., 0.]), ([16., 0.], [32., 0.])]
+        );
+        assert_eq!(level.triggers.len(), 1);
+        assert_eq!(level.triggers[0].name, "exit");
+        assert_eq!(
+            level.triggers[0].properties.get("next_level"),
+            Some(&PropertyValue::String("levels/2.json".to_string()))
+        );
+    }
+
+    #[test]
+    fn triggers_become_components() {
+        let level = import_str("map.json", MAP).unwrap();
+        let game_state = level.build(BroadPhaseKind::default());
+        let mut triggers = Vec::new();
+        game_state
+            .components()
+            .for_each(|_, trigger: &Trigger| triggers.push(trigger.name.clone()));
+        assert_eq!(triggers, vec!["exit".to_string()]);
+    }
+
+    #[test]
+    fn tmx_is_rejected() {
+        let message = import_str("map.tmx", "<?xml version=\"1.0\"?>\n<map/>")
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("export the map as JSON"), "{}", message);
+    }
+
+    #[test]
+    fn unsupported_objects_are_reported() {
+        let map = MAP.replace("\"point\": true", "\"point\": true, \"rotation\": 45");
+        let message = import_str("map.json", &map).unwrap_err().to_string();
+        assert!(
+            message.contains("layer \"objects\": object 1"),
+            "{}",
+            message
+        );
+    }
+}
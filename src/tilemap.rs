@@ -0,0 +1,483 @@
+use cgmath::{vec2, Vector2};
+use fnv::FnvHashMap;
+use game::{EntityCommon, EntityId, GameState};
+use line_segment::LineSegment;
+use shape::{AxisAlignedRect, Shape};
+
+pub type TileId = u32;
+
+/// The tile id of empty space.
+pub const EMPTY_TILE: TileId = 0;
+
+const COLOUR: [f32; 3] = [1., 1., 0.];
+
+/// Offsets of a tile and the tiles next to it, including diagonally for slopes.
+const NEIGHBOURS: [(isize, isize); 9] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (0, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileCollision {
+    Empty,
+    Solid,
+    /// Can be jumped through from below, and stood on from above.
+    OneWay,
+    /// A slope from the bottom-left corner of the tile to the top-right corner.
+    SlopeUp,
+    /// A slope from the top-left corner of the tile to the bottom-right corner.
+    SlopeDown,
+}
+
+/// Collision geometry in world coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TileGeometry {
+    Rect {
+        top_left: Vector2<f32>,
+        size: Vector2<f32>,
+    },
+    OneWay {
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+    },
+    Slope {
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+    },
+}
+
+impl TileGeometry {
+    fn spawn(&self, game_state: &mut GameState) -> EntityId {
+        match self {
+            &TileGeometry::Rect { top_left, size } => {
+                game_state.add_static_solid(EntityCommon::new(
+                    top_left,
+                    Shape::AxisAlignedRect(AxisAlignedRect::new(size)),
+                    COLOUR,
+                ))
+            }
+            &TileGeometry::OneWay { start, end } => {
+                game_state.add_one_way_platform(EntityCommon::new(
+                    start,
+                    Shape::LineSegment(LineSegment::new(vec2(0., 0.), end - start)),
+                    COLOUR,
+                ))
+            }
+            &TileGeometry::Slope { start, end } => {
+                game_state.add_static_solid(EntityCommon::new(
+                    start,
+                    Shape::LineSegment(LineSegment::new(vec2(0., 0.), end - start)),
+                    COLOUR,
+                ))
+            }
+        }
+    }
+}
+
+/// A grid of tiles whose collision geometry is kept merged into as few static
+/// entities as possible. Solid tiles are greedily merged into rects, and runs of
+/// one-way and sloped tiles into single line segments. Merging isn't limited to any
+/// part of the map, so a continuous floor is a single solid.
+#[derive(Debug, Clone)]
+pub struct Tilemap {
+    width: usize,
+    height: usize,
+    top_left: Vector2<f32>,
+    tile_size: Vector2<f32>,
+    tiles: Vec<TileId>,
+    collision: FnvHashMap<TileId, TileCollision>,
+    /// The static entity covering each tile, as of the last sync.
+    owners: Vec<Option<EntityId>>,
+    /// The tiles covered by each static entity, as of the last sync.
+    pieces: FnvHashMap<EntityId, Vec<usize>>,
+    /// Tiles changed since the last sync.
+    dirty: Vec<usize>,
+}
+
+impl Tilemap {
+    pub fn new(
+        width: usize,
+        height: usize,
+        top_left: Vector2<f32>,
+        tile_size: Vector2<f32>,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            top_left,
+            tile_size,
+            tiles: vec![EMPTY_TILE; width * height],
+            collision: Default::default(),
+            owners: vec![None; width * height],
+            pieces: Default::default(),
+            dirty: (0..width * height).collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn tile(&self, x: usize, y: usize) -> TileId {
+        self.tiles[y * self.width + x]
+    }
+
+    pub fn set_tile(&mut self, x: usize, y: usize, tile: TileId) {
+        let index = y * self.width + x;
+        if self.tiles[index] != tile {
+            self.tiles[index] = tile;
+            self.dirty.push(index);
+        }
+    }
+
+    /// Sets how tiles with a given id collide. Empty tiles are `Empty` and all other
+    /// tiles are `Solid` unless set otherwise.
+    pub fn set_tile_collision(&mut self, tile: TileId, collision: TileCollision) {
+        self.collision.insert(tile, collision);
+        self.mark_all_dirty();
+    }
+
+    pub fn collision(&self, x: usize, y: usize) -> TileCollision {
+        let tile = self.tile(x, y);
+        match self.collision.get(&tile) {
+            Some(&collision) => collision,
+            None if tile == EMPTY_TILE => TileCollision::Empty,
+            None => TileCollision::Solid,
+        }
+    }
+
+    /// Makes the next `sync` regenerate all the geometry, e.g. after the game state
+    /// it was synced with has been cleared.
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = (0..self.tiles.len()).collect();
+    }
+
+    fn tile_top_left(&self, x: usize, y: usize) -> Vector2<f32> {
+        self.top_left + vec2(x as f32 * self.tile_size.x, y as f32 * self.tile_size.y)
+    }
+
+    /// Greedily merges the tiles at `indices`, which must be in order, into geometry.
+    /// Tiles already in `taken` aren't merged, and tiles are added to it as they're
+    /// merged. Returns each piece of geometry with the indices of the tiles it covers.
+    fn merge(
+        &self,
+        indices: &[usize],
+        taken: &mut [bool],
+    ) -> Vec<(TileGeometry, Vec<usize>)> {
+        let width = self.width;
+        let mut geometry = Vec::new();
+        for &index in indices.iter() {
+            if taken[index] {
+                continue;
+            }
+            let (x, y) = (index % width, index / width);
+            let collision = self.collision(x, y);
+            let mergeable = |x: usize, y: usize, taken: &[bool]| {
+                !taken[y * width + x] && self.collision(x, y) == collision
+            };
+            let mut covered = vec![index];
+            let piece = match collision {
+                TileCollision::Empty => None,
+                TileCollision::Solid => {
+                    let mut x_end = x + 1;
+                    while x_end < self.width && mergeable(x_end, y, taken) {
+                        x_end += 1;
+                    }
+                    let mut y_end = y + 1;
+                    while y_end < self.height
+                        && (x..x_end).all(|x| mergeable(x, y_end, taken))
+                    {
+                        y_end += 1;
+                    }
+                    covered = (y..y_end)
+                        .flat_map(|y| (x..x_end).map(move |x| y * width + x))
+                        .collect();
+                    Some(TileGeometry::Rect {
+                        top_left: self.tile_top_left(x, y),
+                        size: vec2(
+                            (x_end - x) as f32 * self.tile_size.x,
+                            (y_end - y) as f32 * self.tile_size.y,
+                        ),
+                    })
+                }
+                TileCollision::OneWay => {
+                    let mut x_end = x + 1;
+                    while x_end < self.width && mergeable(x_end, y, taken) {
+                        covered.push(y * width + x_end);
+                        x_end += 1;
+                    }
+                    Some(TileGeometry::OneWay {
+                        start: self.tile_top_left(x, y),
+                        end: self.tile_top_left(x_end, y),
+                    })
+                }
+                TileCollision::SlopeUp | TileCollision::SlopeDown => {
+                    // Tiles are visited from the top down, so an upward slope is first
+                    // found somewhere other than its start. Follow it in both
+                    // directions.
+                    let step_y = if collision == TileCollision::SlopeUp {
+                        -1
+                    } else {
+                        1
+                    };
+                    let is_slope = |x: isize, y: isize| {
+                        x >= 0
+                            && x < self.width as isize
+                            && y >= 0
+                            && y < self.height as isize
+                            && mergeable(x as usize, y as usize, taken)
+                    };
+                    let (mut first_x, mut first_y) = (x as isize, y as isize);
+                    while is_slope(first_x - 1, first_y - step_y) {
+                        first_x -= 1;
+                        first_y -= step_y;
+                    }
+                    let (mut last_x, mut last_y) = (x as isize, y as isize);
+                    while is_slope(last_x + 1, last_y + step_y) {
+                        last_x += 1;
+                        last_y += step_y;
+                    }
+                    covered = (0..(last_x - first_x + 1))
+                        .map(|i| {
+                            let (x, y) = (first_x + i, first_y + i * step_y);
+                            y as usize * width + x as usize
+                        })
+                        .collect();
+                    let (first_x, first_y) = (first_x as usize, first_y as usize);
+                    let (last_x, last_y) = (last_x as usize, last_y as usize);
+                    Some(if collision == TileCollision::SlopeUp {
+                        TileGeometry::Slope {
+                            start: self.tile_top_left(first_x, first_y + 1),
+                            end: self.tile_top_left(last_x + 1, last_y),
+                        }
+                    } else {
+                        TileGeometry::Slope {
+                            start: self.tile_top_left(first_x, first_y),
+                            end: self.tile_top_left(last_x + 1, last_y + 1),
+                        }
+                    })
+                }
+            };
+            for &index in covered.iter() {
+                taken[index] = true;
+            }
+            if let Some(piece) = piece {
+                geometry.push((piece, covered));
+            }
+        }
+        geometry
+    }
+
+    /// The merged geometry of the whole map.
+    pub fn geometry(&self) -> Vec<TileGeometry> {
+        let indices = (0..self.tiles.len()).collect::<Vec<_>>();
+        let mut taken = vec![false; self.tiles.len()];
+        self.merge(&indices, &mut taken)
+            .into_iter()
+            .map(|(geometry, _)| geometry)
+            .collect()
+    }
+
+    /// Updates the static entities in `game_state` to match the tiles. Only the
+    /// geometry covering or next to tiles which have changed since the last sync is
+    /// removed, and the tiles it covered are merged again.
+    pub fn sync(&mut self, game_state: &mut GameState) {
+        if self.dirty.is_empty() {
+            return;
+        }
+        // Geometry next to a changed tile is merged again so that it can join up
+        // with the tile.
+        let mut around = Vec::new();
+        for &index in self.dirty.iter() {
+            let (x, y) = ((index % self.width) as isize, (index / self.width) as isize);
+            for &(dx, dy) in NEIGHBOURS.iter() {
+                let (x, y) = (x + dx, y + dy);
+                if x >= 0 && x < self.width as isize && y >= 0 && y < self.height as isize
+                {
+                    around.push(y as usize * self.width + x as usize);
+                }
+            }
+        }
+        let mut freed = Vec::new();
+        for index in around {
+            if let Some(id) = self.owners[index] {
+                if let Some(tiles) = self.pieces.remove(&id) {
+                    game_state.despawn(id);
+                    for &tile in tiles.iter() {
+                        self.owners[tile] = None;
+                    }
+                    freed.extend(tiles);
+                }
+            }
+        }
+        freed.append(&mut self.dirty);
+        freed.sort();
+        freed.dedup();
+        let mut taken = self.owners.iter().map(Option::is_some).collect::<Vec<_>>();
+        for (geometry, tiles) in self.merge(&freed, &mut taken) {
+            let id = geometry.spawn(game_state);
+            for &tile in tiles.iter() {
+                self.owners[tile] = Some(id);
+            }
+            self.pieces.insert(id, tiles);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TILE_SIZE: f32 = 16.;
+
+    fn tilemap(rows: &[&str]) -> Tilemap {
+        let mut tilemap = Tilemap::new(
+            rows[0].len(),
+            rows.len(),
+            vec2(0., 0.),
+            vec2(TILE_SIZE, TILE_SIZE),
+        );
+        tilemap.set_tile_collision(2, TileCollision::OneWay);
+        tilemap.set_tile_collision(3, TileCollision::SlopeUp);
+        tilemap.set_tile_collision(4, TileCollision::SlopeDown);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let tile = match c {
+                    '#' => 1,
+                    '-' => 2,
+                    '/' => 3,
+                    '\\' => 4,
+                    _ => EMPTY_TILE,
+                };
+                tilemap.set_tile(x, y, tile);
+            }
+        }
+        tilemap
+    }
+
+    fn tile(x: f32, y: f32) -> Vector2<f32> {
+        vec2(x * TILE_SIZE, y * TILE_SIZE)
+    }
+
+    #[test]
+    fn solid_tiles_are_merged_into_rects() {
+        let tilemap = tilemap(&["....", "####", "####", "##.."]);
+        assert_eq!(
+            tilemap.geometry(),
+            vec![
+                TileGeometry::Rect {
+                    top_left: tile(0., 1.),
+                    size: tile(4., 2.),
+                },
+                TileGeometry::Rect {
+                    top_left: tile(0., 3.),
+                    size: tile(2., 1.),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn slopes_and_one_way_runs_are_merged_into_segments() {
+        let tilemap = tilemap(&["..../", ".../.", "---..", "\\....", ".\\..."]);
+        assert_eq!(
+            tilemap.geometry(),
+            vec![
+                TileGeometry::Slope {
+                    start: tile(3., 2.),
+                    end: tile(5., 0.),
+                },
+                TileGeometry::OneWay {
+                    start: tile(0., 2.),
+                    end: tile(3., 2.),
+                },
+                TileGeometry::Slope {
+                    start: tile(0., 3.),
+                    end: tile(2., 5.),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn continuous_floors_are_one_solid() {
+        let tilemap = tilemap(&[&".".repeat(100), &"#".repeat(100)]);
+        assert_eq!(
+            tilemap.geometry(),
+            vec![TileGeometry::Rect {
+                top_left: tile(0., 1.),
+                size: tile(100., 1.),
+            }]
+        );
+    }
+
+    #[test]
+    fn changing_a_tile_only_regenerates_geometry_covering_it() {
+        let width = 40;
+        let platform = format!("{}{}", ".".repeat(width - 4), "####");
+        let ground = "#".repeat(width);
+        let empty = ".".repeat(width);
+        let mut tilemap = tilemap(&[&platform, &empty, &ground]);
+        let mut game_state = GameState::new(tile(width as f32, 3.), Default::default());
+        tilemap.sync(&mut game_state);
+        assert_eq!(game_state.common_iter().count(), 2);
+        let platform_id = tilemap.owners[width - 1].unwrap();
+
+        tilemap.set_tile(1, 2, EMPTY_TILE);
+        tilemap.sync(&mut game_state);
+        assert_eq!(game_state.common_iter().count(), 3);
+        assert_eq!(tilemap.owners[width - 1], Some(platform_id));
+        let right = game_state
+            .common(tilemap.owners[3 * width - 1].unwrap())
+            .unwrap();
+        assert_eq!(right.top_left, tile(2., 2.));
+
+        tilemap.set_tile(1, 2, 1);
+        tilemap.sync(&mut game_state);
+        assert_eq!(game_state.common_iter().count(), 2);
+        assert_eq!(tilemap.owners[width - 1], Some(platform_id));
+    }
+
+    #[test]
+    fn one_way_platforms_only_block_from_above() {
+        let mut tilemap = tilemap(&["....", "....", "----", "....", "...."]);
+        let mut game_state = GameState::new(tile(4., 5.), Default::default());
+        tilemap.sync(&mut game_state);
+        let mover = game_state.add_mover(
+            EntityCommon::new(
+                tile(1., 0.),
+                Shape::AxisAlignedRect(AxisAlignedRect::new(tile(1., 1.))),
+                [1., 1., 1.],
+            ),
+            vec2(0., 0.),
+        );
+        let landed = game_state
+            .top_left_after_movement(mover, tile(0., 2.))
+            .unwrap();
+        assert!(landed.y <= tile(0., 1.).y + 0.1, "{:?}", landed);
+
+        let below = game_state.add_mover(
+            EntityCommon::new(
+                tile(1., 3.),
+                Shape::AxisAlignedRect(AxisAlignedRect::new(tile(1., 1.))),
+                [1., 1., 1.],
+            ),
+            vec2(0., 0.),
+        );
+        assert_eq!(
+            game_state.top_left_after_movement(below, tile(0., -3.)),
+            Some(tile(1., 0.))
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use aabb::Aabb;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+pub type Properties = BTreeMap<String, PropertyValue>;
+
+/// A named region of the level which doesn't collide with anything, but which
+/// systems can check entities against.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub aabb: Aabb<f32>,
+    pub properties: Properties,
+}
@@ -0,0 +1,153 @@
+use aabb::Aabb;
+use arith::{self, Scalar};
+use broad_phase::BroadPhase;
+use cgmath::{vec2, Vector2};
+use fnv::FnvHashMap;
+
+type CellCoord = (i32, i32);
+
+/// A spatial hash of uniformly sized cells. Each item is listed in every cell its
+/// aabb touches. To avoid reporting an intersection once per shared cell, it is
+/// only reported from the cell containing the top-left corner of the overlap.
+#[derive(Debug, Clone)]
+pub struct UniformGrid<T, N> {
+    cell_size: N,
+    cells: FnvHashMap<CellCoord, Vec<usize>>,
+    entries: Vec<Option<(Aabb<N>, T)>>,
+    free: Vec<usize>,
+}
+
+fn overlap_top_left<N: Scalar>(a: &Aabb<N>, b: &Aabb<N>) -> Vector2<N> {
+    let a = a.top_left();
+    let b = b.top_left();
+    vec2(arith::max(a.x, b.x), arith::max(a.y, b.y))
+}
+
+impl<T, N: Scalar> UniformGrid<T, N> {
+    pub fn new(cell_size: N) -> Self {
+        assert!(cell_size > N::zero(), "cell size must be positive");
+        Self {
+            cell_size,
+            cells: Default::default(),
+            entries: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn cell_coord(&self, point: Vector2<N>) -> CellCoord {
+        let coord = |x: N| {
+            x.div_floor(self.cell_size)
+                .to_i32()
+                .expect("cell coordinate out of range")
+        };
+        (coord(point.x), coord(point.y))
+    }
+
+    fn for_each_cell_coord<F: FnMut(CellCoord)>(&self, aabb: &Aabb<N>, mut f: F) {
+        let (x_min, y_min) = self.cell_coord(aabb.top_left());
+        let (x_max, y_max) = self.cell_coord(aabb.bottom_right_coord());
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                f((x, y));
+            }
+        }
+    }
+}
+
+impl<T, N: Scalar> BroadPhase<T, N> for UniformGrid<T, N> {
+    fn clear(&mut self) {
+        self.cells.clear();
+        self.entries.clear();
+        self.free.clear();
+    }
+
+    fn insert(&mut self, aabb: Aabb<N>, t: T) {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = Some((aabb, t));
+                index
+            }
+            None => {
+                self.entries.push(Some((aabb, t)));
+                self.entries.len() - 1
+            }
+        };
+        let mut coords = Vec::new();
+        self.for_each_cell_coord(&aabb, |coord| coords.push(coord));
+        for coord in coords {
+            self.cells.entry(coord).or_insert_with(Vec::new).push(index);
+        }
+    }
+
+    fn remove<P: FnMut(&T) -> bool>(
+        &mut self,
+        aabb: &Aabb<N>,
+        mut predicate: P,
+    ) -> Option<T> {
+        let index = {
+            let coord = self.cell_coord(aabb.top_left());
+            let entries = &self.entries;
+            *self.cells.get(&coord)?.iter().find(|&&index| {
+                entries[index]
+                    .as_ref()
+                    .map_or(false, |&(ref entry_aabb, ref t)| {
+                        entry_aabb == aabb && predicate(t)
+                    })
+            })?
+        };
+        let mut coords = Vec::new();
+        self.for_each_cell_coord(aabb, |coord| coords.push(coord));
+        for coord in coords {
+            let now_empty = match self.cells.get_mut(&coord) {
+                Some(cell) => {
+                    cell.retain(|&i| i != index);
+                    cell.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                self.cells.remove(&coord);
+            }
+        }
+        self.free.push(index);
+        self.entries[index].take().map(|(_, t)| t)
+    }
+
+    fn for_each_intersection<F: FnMut(&Aabb<N>, &T)>(&self, aabb: &Aabb<N>, mut f: F) {
+        self.for_each_cell_coord(aabb, |coord| {
+            if let Some(cell) = self.cells.get(&coord) {
+                for &index in cell.iter() {
+                    if let Some((ref entry_aabb, ref t)) = self.entries[index] {
+                        if entry_aabb.is_intersecting(aabb)
+                            && self.cell_coord(overlap_top_left(entry_aabb, aabb))
+                                == coord
+                        {
+                            f(entry_aabb, t);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn for_each_overlapping_pair<F: FnMut(&Aabb<N>, &T, &Aabb<N>, &T)>(&self, mut f: F) {
+        for (&coord, cell) in self.cells.iter() {
+            for (i, &index) in cell.iter().enumerate() {
+                if let Some((ref aabb, ref t)) = self.entries[index] {
+                    for &other_index in cell[(i + 1)..].iter() {
+                        if let Some((ref other_aabb, ref other_t)) =
+                            self.entries[other_index]
+                        {
+                            if aabb.is_intersecting(other_aabb)
+                                && self.cell_coord(overlap_top_left(aabb, other_aabb))
+                                    == coord
+                            {
+                                f(aabb, t, other_aabb, other_t);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}